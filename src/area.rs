@@ -0,0 +1,131 @@
+use crate::{linearize::LinearImage, opcode::AreaSpec};
+
+// Runs `f` on every sample the area covers, clipped to the image, and keeps what it returns
+fn map_area<F: FnMut(usize, usize, usize, f32) -> f32>(image: &LinearImage, area: &AreaSpec, mut f: F) -> LinearImage {
+    let mut mapped = image.clone();
+    let bottom = (area.bottom as usize).min(image.height);
+    let right = (area.right as usize).min(image.width);
+    let last_plane = (area.plane + area.planes).min(image.planes as u32) as usize;
+    for row in (area.top as usize..bottom).step_by(area.row_pitch.max(1) as usize) {
+        for col in (area.left as usize..right).step_by(area.col_pitch.max(1) as usize) {
+            for plane in area.plane as usize..last_plane {
+                let value = f(row, col, plane, image.get(row, col, plane));
+                mapped.set(row, col, plane, value);
+            }
+        }
+    }
+    mapped
+}
+
+/// MapTable, a lookup table over the 16 bit range with the last entry repeated past its end.
+pub fn map_table(image: &LinearImage, area: &AreaSpec, table: &[u16]) -> LinearImage {
+    map_area(image, area, |_, _, _, v| {
+        let index = ((v.clamp(0.0, 1.0) * 65535.0).round() as usize).min(table.len() - 1);
+        table[index] as f32 / 65535.0
+    })
+}
+
+/// MapPolynomial, coefficients from the constant term up.
+pub fn map_polynomial(image: &LinearImage, area: &AreaSpec, coefficients: &[f64]) -> LinearImage {
+    map_area(image, area, |_, _, _, v| {
+        let y = coefficients.iter().rev().fold(0.0, |acc, c| acc * v as f64 + c);
+        y.clamp(0.0, 1.0) as f32
+    })
+}
+
+// The deltas and scales are indexed by the rows / columns the area processes, not the image's
+fn area_index(position: usize, start: u32, pitch: u32) -> usize {
+    (position - start as usize) / pitch.max(1) as usize
+}
+
+pub fn delta_per_row(image: &LinearImage, area: &AreaSpec, deltas: &[f32]) -> LinearImage {
+    map_area(image, area, |row, _, _, v| (v + deltas[area_index(row, area.top, area.row_pitch)]).clamp(0.0, 1.0))
+}
+
+pub fn delta_per_column(image: &LinearImage, area: &AreaSpec, deltas: &[f32]) -> LinearImage {
+    map_area(image, area, |_, col, _, v| (v + deltas[area_index(col, area.left, area.col_pitch)]).clamp(0.0, 1.0))
+}
+
+pub fn scale_per_row(image: &LinearImage, area: &AreaSpec, scales: &[f32]) -> LinearImage {
+    map_area(image, area, |row, _, _, v| (v * scales[area_index(row, area.top, area.row_pitch)]).min(1.0))
+}
+
+pub fn scale_per_column(image: &LinearImage, area: &AreaSpec, scales: &[f32]) -> LinearImage {
+    map_area(image, area, |_, col, _, v| (v * scales[area_index(col, area.left, area.col_pitch)]).min(1.0))
+}
+
+/// A grid of gains over the image, see DNG spec 1.6 GainMap P106.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GainMap {
+    pub points_v: usize,
+    pub points_h: usize,
+    // the spacing and origin are fractions of the image's height / width
+    pub spacing_v: f64,
+    pub spacing_h: f64,
+    pub origin_v: f64,
+    pub origin_h: f64,
+    pub planes: usize,
+    // indexed by [row][col][plane] of the map
+    pub gains: Vec<f32>,
+}
+
+impl GainMap {
+    fn gain(&self, row: usize, col: usize, plane: usize) -> f32 {
+        self.gains[(row * self.points_h + col) * self.planes + plane]
+    }
+
+    /// The bilinearly interpolated gain at a position relative to the image, clamped to the map's edges.
+    pub fn interpolate(&self, v: f64, h: f64, plane: usize) -> f32 {
+        let plane = plane.min(self.planes - 1);
+        let position = |p: f64, origin: f64, spacing: f64, points: usize| {
+            let index = if spacing > 0.0 { ((p - origin) / spacing).clamp(0.0, (points - 1) as f64) } else { 0.0 };
+            let i0 = (index.floor() as usize).min(points - 1);
+            (i0, (i0 + 1).min(points - 1), (index - i0 as f64) as f32)
+        };
+        let (r0, r1, rf) = position(v, self.origin_v, self.spacing_v, self.points_v);
+        let (c0, c1, cf) = position(h, self.origin_h, self.spacing_h, self.points_h);
+        let top = self.gain(r0, c0, plane) * (1.0 - cf) + self.gain(r0, c1, plane) * cf;
+        let bottom = self.gain(r1, c0, plane) * (1.0 - cf) + self.gain(r1, c1, plane) * cf;
+        top * (1.0 - rf) + bottom * rf
+    }
+
+    pub fn apply(&self, image: &LinearImage, area: &AreaSpec) -> LinearImage {
+        let (height, width) = (image.height as f64, image.width as f64);
+        map_area(image, area, |row, col, plane, v| {
+            let gain = self.interpolate(row as f64 / height, col as f64 / width, plane - area.plane as usize);
+            (v * gain).min(1.0)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn whole(image: &LinearImage) -> AreaSpec {
+        AreaSpec { top: 0, left: 0, bottom: image.height as u32, right: image.width as u32, plane: 0, planes: 1, row_pitch: 1, col_pitch: 1 }
+    }
+
+    #[test]
+    fn gain_map_interpolates() {
+        let image = LinearImage { width: 4, height: 1, planes: 1, data: vec![0.25; 4] };
+        // gains of 1 on the left edge and 2 on the right, across the whole width
+        let map = GainMap { points_v: 1, points_h: 2, spacing_v: 1.0, spacing_h: 0.75, origin_v: 0.0, origin_h: 0.0, planes: 1, gains: vec![1.0, 2.0] };
+
+        let gained = map.apply(&image, &whole(&image));
+
+        for (gained, expected) in gained.data.iter().zip([0.25, 0.25 * 4.0 / 3.0, 0.25 * 5.0 / 3.0, 0.5]) {
+            assert!((gained - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn delta_per_column_with_pitch() {
+        let image = LinearImage { width: 4, height: 1, planes: 1, data: vec![0.5; 4] };
+        let area = AreaSpec { col_pitch: 2, ..whole(&image) };
+
+        let shifted = delta_per_column(&image, &area, &[0.125, -0.125]);
+
+        assert_eq!(shifted.data, vec![0.625, 0.5, 0.375, 0.5]);
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::HashSet;
+
+use crate::{
+    cfa::CfaPattern,
+    linearize::LinearImage,
+    opcode::{Opcode, OpcodeKind},
+    raster::Raster,
+};
+
+// How far to look for good pixels of the same color before giving up on a bad one
+const MAX_SEARCH_RADIUS: isize = 16;
+
+// The color at (0, 0) of each Bayer phase, then the other three of the 2x2 pattern, see DNG spec 1.6
+// FixBadPixelsConstant P102. 0 is red, 1 green and 2 blue.
+fn bayer_color(bayer_phase: u32, row: usize, col: usize) -> u8 {
+    const PHASES: [[u8; 4]; 4] = [[0, 1, 1, 2], [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]];
+    PHASES[bayer_phase as usize % 4][(row % 2) * 2 + col % 2]
+}
+
+impl CfaPattern {
+    /// The FixBadPixels BayerPhase of a Bayer pattern, None for every other pattern.
+    pub fn bayer_phase(&self) -> Option<u32> {
+        if !self.is_bayer() {
+            return None;
+        }
+        let colors = [0, 1, 2, 3].map(|i| self.plane_at(i / 2, i % 2) as u8);
+        (0..4).find(|&phase| (0..4).all(|i| bayer_color(phase, i / 2, i % 2) == colors[i]))
+    }
+}
+
+// Replaces each bad pixel with the average of the nearest good pixels of the same color
+fn fix_bad_pixels<F: Fn(usize, usize) -> bool>(image: &LinearImage, bayer_phase: u32, bad: &[(usize, usize)], is_bad: F) -> LinearImage {
+    let mut fixed = image.clone();
+    for &(row, col) in bad {
+        let color = bayer_color(bayer_phase, row, col);
+        for radius in 1..=MAX_SEARCH_RADIUS {
+            let mut sums = vec![0.0; image.planes];
+            let mut count = 0;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    // just the ring at this radius, the inside has already been searched
+                    if dy.abs() != radius && dx.abs() != radius {
+                        continue;
+                    }
+                    let (r, c) = (row as isize + dy, col as isize + dx);
+                    if r < 0 || c < 0 || r >= image.height as isize || c >= image.width as isize {
+                        continue;
+                    }
+                    let (r, c) = (r as usize, c as usize);
+                    if bayer_color(bayer_phase, r, c) != color || is_bad(r, c) {
+                        continue;
+                    }
+                    for (plane, sum) in sums.iter_mut().enumerate() {
+                        *sum += image.get(r, c, plane);
+                    }
+                    count += 1;
+                }
+            }
+            if count > 0 {
+                for (plane, sum) in sums.iter().enumerate() {
+                    fixed.set(row, col, plane, sum / count as f32);
+                }
+                break;
+            }
+        }
+    }
+    fixed
+}
+
+/// FixBadPixelsConstant, pixels with the constant stored value are bad. Works on OpcodeList1 images,
+/// where values are fractions of 65535. The opcode is for CFA images (DNG spec 1.6 P102), which have
+/// a single plane, so images with more planes come back unchanged.
+pub fn fix_bad_pixels_constant(image: &LinearImage, constant: u32, bayer_phase: u32) -> LinearImage {
+    if image.planes != 1 {
+        return image.clone();
+    }
+    let is_bad = |row: usize, col: usize| (image.get(row, col, 0) * 65535.0).round() as u32 == constant;
+    let bad = (0..image.height)
+        .flat_map(|row| (0..image.width).map(move |col| (row, col)))
+        .filter(|&(row, col)| is_bad(row, col))
+        .collect::<Vec<(usize, usize)>>();
+    fix_bad_pixels(image, bayer_phase, &bad, is_bad)
+}
+
+/// FixBadPixelsList, single bad pixels as (row, column) and bad rectangles as (top, left, bottom, right).
+pub fn fix_bad_pixels_list(image: &LinearImage, bayer_phase: u32, points: &[(u32, u32)], rects: &[(u32, u32, u32, u32)]) -> LinearImage {
+    let mut bad = points.iter().map(|&(row, col)| (row as usize, col as usize)).collect::<Vec<(usize, usize)>>();
+    for &(top, left, bottom, right) in rects {
+        for row in top..bottom {
+            for col in left..right {
+                bad.push((row as usize, col as usize));
+            }
+        }
+    }
+    bad.retain(|&(row, col)| row < image.height && col < image.width);
+    let bad_set = bad.iter().cloned().collect::<HashSet<(usize, usize)>>();
+    fix_bad_pixels(image, bayer_phase, &bad, |row, col| bad_set.contains(&(row, col)))
+}
+
+// The median and a robust standard deviation (from the median absolute deviation) of some values
+fn median_and_sigma(values: &[f64]) -> (f64, f64) {
+    let median = |v: &mut Vec<f64>| {
+        v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        v[v.len() / 2]
+    };
+    let center = median(&mut values.to_vec());
+    let sigma = 1.4826 * median(&mut values.iter().map(|v| (v - center).abs()).collect());
+    (center, sigma)
+}
+
+// For each position in the CFA pattern, the offsets of the eight nearest pixels of the same color,
+// e.g. the ones 2 rows and/or columns away for red and blue in a Bayer pattern
+fn same_color_neighbors(cfa: &CfaPattern) -> Vec<Vec<(isize, isize)>> {
+    const COUNT: usize = 8;
+    let radius = MAX_SEARCH_RADIUS.min(cfa.rows.max(cfa.cols) as isize * 2);
+    (0..cfa.rows * cfa.cols).map(|i| {
+        let (row, col) = (i / cfa.cols, i % cfa.cols);
+        let plane = cfa.plane_at(row, col);
+        let mut offsets = (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dy, dx)))
+            .filter(|&(dy, dx)| (dy, dx) != (0, 0))
+            .filter(|&(dy, dx)| {
+                let r = (row as isize + dy).rem_euclid(cfa.rows as isize) as usize;
+                let c = (col as isize + dx).rem_euclid(cfa.cols as isize) as usize;
+                cfa.plane_at(r, c) == plane
+            })
+            .collect::<Vec<(isize, isize)>>();
+        offsets.sort_by_key(|&(dy, dx)| dy * dy + dx * dx);
+        offsets.truncate(COUNT);
+        offsets
+    }).collect()
+}
+
+/// Finds hot and dead pixels in a CFA raw image, as the ones that are more than `threshold` robust
+/// standard deviations away from the median of their eight nearest neighbors of the same color in `cfa`.
+pub fn detect_bad_pixels(raster: &Raster, cfa: &CfaPattern, threshold: f64) -> Vec<(u32, u32)> {
+    let neighbor_offsets = same_color_neighbors(cfa);
+    let mut deviations = Vec::with_capacity(raster.width * raster.height);
+    for row in 0..raster.height {
+        for col in 0..raster.width {
+            let mut neighbors = neighbor_offsets[(row % cfa.rows) * cfa.cols + col % cfa.cols].iter()
+                .map(|&(dy, dx)| (row as isize + dy, col as isize + dx))
+                .filter(|&(r, c)| r >= 0 && c >= 0 && r < raster.height as isize && c < raster.width as isize)
+                .map(|(r, c)| raster.get(r as usize, c as usize, 0))
+                .collect::<Vec<u16>>();
+            if neighbors.is_empty() {
+                deviations.push(0.0);
+                continue;
+            }
+            neighbors.sort();
+            let median = neighbors[neighbors.len() / 2] as f64;
+            deviations.push(raster.get(row, col, 0) as f64 - median);
+        }
+    }
+    let (center, sigma) = median_and_sigma(&deviations);
+    let limit = threshold * sigma.max(1.0);
+    deviations.iter().enumerate()
+        .filter(|(_, d)| (*d - center).abs() > limit)
+        .map(|(i, _)| ((i / raster.width) as u32, (i % raster.width) as u32))
+        .collect()
+}
+
+/// Finds hot pixels in a set of dark frames of the same size, as the ones whose average is more than
+/// `threshold` robust standard deviations above the average dark frame's median.
+pub fn detect_hot_pixels_in_dark_frames(frames: &[Raster], threshold: f64) -> Vec<(u32, u32)> {
+    let first = frames.first().expect("There are no dark frames!");
+    let mut averages = vec![0.0; first.width * first.height];
+    for frame in frames {
+        assert!(frame.width == first.width && frame.height == first.height, "The dark frames are different sizes!");
+        for (i, average) in averages.iter_mut().enumerate() {
+            *average += frame.data[i * frame.samples_per_pixel] as f64 / frames.len() as f64;
+        }
+    }
+    let (center, sigma) = median_and_sigma(&averages);
+    let limit = center + threshold * sigma.max(1.0 / frames.len() as f64);
+    averages.iter().enumerate()
+        .filter(|(_, a)| **a > limit)
+        .map(|(i, _)| ((i / first.width) as u32, (i % first.width) as u32))
+        .collect()
+}
+
+/// A FixBadPixelsList opcode for some detected pixels, to go in OpcodeList1.
+pub fn bad_pixels_opcode(points: Vec<(u32, u32)>, bayer_phase: u32) -> Opcode {
+    Opcode {
+        dng_version: [1, 3, 0, 0],
+        optional: false,
+        preview_skippable: true,
+        kind: OpcodeKind::FixBadPixelsList { bayer_phase, points, rects: Vec::new() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat_raster(value: u16) -> Raster {
+        Raster { width: 8, height: 8, samples_per_pixel: 1, bits_per_sample: 16, data: vec![value; 64] }
+    }
+
+    #[test]
+    fn detect_and_fix_hot_pixel() {
+        let mut raster = flat_raster(1000);
+        raster.data[3 * 8 + 4] = 60000;
+
+        let points = detect_bad_pixels(&raster, &CfaPattern::new(2, 2, vec![0, 1, 1, 2], vec![0, 1, 2]), 10.0);
+        assert_eq!(points, vec![(3, 4)]);
+
+        let image = LinearImage { width: 8, height: 8, planes: 1, data: raster.data.iter().map(|&v| v as f32 / 65535.0).collect() };
+        let fixed = fix_bad_pixels_list(&image, 0, &points, &[]);
+        assert_eq!(fixed.get(3, 4, 0), 1000.0 / 65535.0);
+    }
+
+    #[test]
+    fn same_color_neighbors_follow_the_pattern() {
+        // RGGB with bright red pixels, which would stand out against neighbors of the other colors
+        let bayer = CfaPattern::new(2, 2, vec![0, 1, 1, 2], vec![0, 1, 2]);
+        let mut raster = flat_raster(1000);
+        for row in (0..8).step_by(2) {
+            for col in (0..8).step_by(2) {
+                raster.data[row * 8 + col] = 4000;
+            }
+        }
+        assert!(detect_bad_pixels(&raster, &bayer, 10.0).is_empty());
+
+        // in a 3x3 pattern the same color repeats every 3 pixels
+        let stripes = CfaPattern::new(3, 3, vec![0, 1, 2, 0, 1, 2, 0, 1, 2], vec![0, 1, 2]);
+        let offsets = &same_color_neighbors(&stripes)[0];
+        assert!(offsets.iter().all(|&(_, dx)| dx % 3 == 0));
+        let mut raster = Raster { width: 9, height: 9, samples_per_pixel: 1, bits_per_sample: 16, data: (0..81).map(|i| [1000, 3000, 5000][i % 3]).collect() };
+        raster.data[4 * 9 + 4] = 60000;
+        assert_eq!(detect_bad_pixels(&raster, &stripes, 10.0), vec![(4, 4)]);
+    }
+
+    #[test]
+    fn constant_only_on_cfa() {
+        // a dead blue pixel in an RGGB image
+        let mut image = LinearImage { width: 4, height: 4, planes: 1, data: vec![0.5; 16] };
+        image.set(1, 1, 0, 0.0);
+        assert_eq!(fix_bad_pixels_constant(&image, 0, 0).data, vec![0.5; 16]);
+
+        // three planes aren't a CFA image, even with a zero in one of them
+        let mut rgb = LinearImage { width: 4, height: 4, planes: 3, data: vec![0.5; 48] };
+        rgb.set(1, 1, 1, 0.0);
+        assert_eq!(fix_bad_pixels_constant(&rgb, 0, 0).data, rgb.data);
+    }
+
+    #[test]
+    fn bayer_phase() {
+        // GBRG
+        let cfa = CfaPattern::new(2, 2, vec![1, 2, 0, 1], vec![0, 1, 2]);
+
+        assert_eq!(cfa.bayer_phase(), Some(2));
+    }
+}
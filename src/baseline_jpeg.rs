@@ -0,0 +1,314 @@
+// See ITU T.81 (JPEG), Annex F (sequential DCT-based mode of operation)
+// DNG spec 1.6 Compression P20, previews with compression = 7 are usually baseline JPEG
+
+use crate::lossless_jpeg::{marker_length, BitReader, HuffmanTable, DHT, DRI, EOI, SOI, SOS};
+
+pub(crate) const SOF0: u8 = 0xC0;
+pub(crate) const SOF1: u8 = 0xC1;
+const DQT: u8 = 0xDB;
+const APP14: u8 = 0xEE;
+
+// See T.81 Figure A.6, the order the coefficients are stored in
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5,
+    12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+pub(crate) struct BaselineJpeg {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) components: usize,
+    // row major with the components interleaved, RGB for three component images
+    pub(crate) samples: Vec<u8>,
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quantization: usize,
+    dc_table: usize,
+    ac_table: usize,
+    // decoded samples at the component's resolution, padded to whole MCUs
+    plane: Vec<u8>,
+    stride: usize,
+}
+
+/// The marker of the first frame, e.g. SOF0 for baseline and SOF3 for lossless JPEG data.
+pub(crate) fn frame_marker(data: &[u8]) -> Option<u8> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != SOI {
+        return None;
+    }
+    let mut position = 2;
+    while position + 3 < data.len() {
+        if data[position] != 0xFF || data[position + 1] == 0xFF {
+            position += 1;
+            continue;
+        }
+        let marker = data[position + 1];
+        match marker {
+            0xC0..=0xCF if marker != DHT && marker != 0xC8 && marker != 0xCC => return Some(marker),
+            SOS | EOI => return None,
+            _ => position += 2 + marker_length(data, position + 2),
+        }
+    }
+    None
+}
+
+// See T.81 A.3.3, the separable inverse DCT with the level shift of A.3.1
+fn inverse_dct(coefficients: &[i32; 64], cosines: &[[f32; 8]; 8], out: &mut [u8], stride: usize) {
+    let mut rows = [0f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            rows[v * 8 + x] = (0..8).map(|u| cosines[x][u] * coefficients[v * 8 + u] as f32).sum();
+        }
+    }
+    for y in 0..8 {
+        for x in 0..8 {
+            let value: f32 = (0..8).map(|v| cosines[y][v] * rows[v * 8 + x]).sum();
+            out[y * stride + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+pub(crate) fn decode(data: &[u8]) -> BaselineJpeg {
+    assert!(data.len() > 4 && data[0] == 0xFF && data[1] == SOI, "The data isn't a JPEG stream!");
+
+    let mut quantization = [[0i32; 64]; 4];
+    let mut dc_tables: [Option<HuffmanTable>; 4] = Default::default();
+    let mut ac_tables: [Option<HuffmanTable>; 4] = Default::default();
+    let mut restart_interval = 0usize;
+    let mut width = 0;
+    let mut height = 0;
+    let mut components: Vec<Component> = Vec::new();
+    // Adobe APP14 transform, 0 means the components are RGB rather than YCbCr
+    let mut transform = None;
+    let mut position = 2;
+
+    loop {
+        while data[position] != 0xFF || data[position + 1] == 0xFF {
+            position += 1;
+        }
+        let marker = data[position + 1];
+        position += 2;
+        match marker {
+            SOF0 | SOF1 => {
+                assert_eq!(data[position + 2], 8, "Only 8 bit baseline JPEG data is supported!");
+                height = marker_length(data, position + 3);
+                width = marker_length(data, position + 5);
+                let count = data[position + 7] as usize;
+                components = (0..count).map(|i| {
+                    let p = position + 8 + i * 3;
+                    Component {
+                        id: data[p],
+                        h: (data[p + 1] >> 4) as usize,
+                        v: (data[p + 1] & 0x0F) as usize,
+                        quantization: data[p + 2] as usize,
+                        dc_table: 0,
+                        ac_table: 0,
+                        plane: Vec::new(),
+                        stride: 0,
+                    }
+                }).collect();
+                position += marker_length(data, position);
+            },
+            0xC0..=0xCF if marker != DHT && marker != 0xC8 && marker != 0xCC => {
+                panic!("Only baseline (SOF0) and extended sequential (SOF1) JPEG data is supported!")
+            },
+            DQT => {
+                let end = position + marker_length(data, position);
+                let mut p = position + 2;
+                while p < end {
+                    let sixteen_bit = data[p] >> 4 == 1;
+                    let id = (data[p] & 0x0F) as usize;
+                    for k in 0..64 {
+                        quantization[id][k] = if sixteen_bit { marker_length(data, p + 1 + k * 2) as i32 } else { data[p + 1 + k] as i32 };
+                    }
+                    p += if sixteen_bit { 129 } else { 65 };
+                }
+                position = end;
+            },
+            DHT => {
+                let end = position + marker_length(data, position);
+                let mut p = position + 2;
+                while p < end {
+                    let class = data[p] >> 4;
+                    let id = (data[p] & 0x0F) as usize;
+                    let mut counts = [0u8; 16];
+                    counts.copy_from_slice(&data[p + 1..p + 17]);
+                    let total = counts.iter().map(|&c| c as usize).sum::<usize>();
+                    let table = Some(HuffmanTable::new(&counts, data[p + 17..p + 17 + total].to_vec()));
+                    if class == 0 { dc_tables[id] = table } else { ac_tables[id] = table }
+                    p += 17 + total;
+                }
+                position = end;
+            },
+            DRI => {
+                restart_interval = marker_length(data, position + 2);
+                position += marker_length(data, position);
+            },
+            APP14 => {
+                if marker_length(data, position) >= 14 && &data[position + 2..position + 7] == b"Adobe" {
+                    transform = Some(data[position + 13]);
+                }
+                position += marker_length(data, position);
+            },
+            SOS => {
+                let count = data[position + 2] as usize;
+                assert_eq!(count, components.len(), "Only single scan baseline JPEG data is supported!");
+                for i in 0..count {
+                    let id = data[position + 3 + i * 2];
+                    let tables = data[position + 4 + i * 2];
+                    let component = components.iter_mut().find(|c| c.id == id).expect("The scan references a missing component!");
+                    component.dc_table = (tables >> 4) as usize;
+                    component.ac_table = (tables & 0x0F) as usize;
+                }
+                position += marker_length(data, position);
+
+                decode_scan(&data[position..], &mut components, &quantization, &dc_tables, &ac_tables, width, height, restart_interval);
+                let samples = to_samples(&components, width, height, transform);
+                return BaselineJpeg { width, height, components: components.len(), samples };
+            },
+            EOI => panic!("Reached the end of the JPEG data without finding a scan!"),
+            _ => {
+                position += marker_length(data, position);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn decode_scan(
+    data: &[u8],
+    components: &mut [Component],
+    quantization: &[[i32; 64]; 4],
+    dc_tables: &[Option<HuffmanTable>; 4],
+    ac_tables: &[Option<HuffmanTable>; 4],
+    width: usize,
+    height: usize,
+    restart_interval: usize,
+) {
+    let h_max = components.iter().map(|c| c.h).max().unwrap();
+    let v_max = components.iter().map(|c| c.v).max().unwrap();
+    // See T.81 A.2, a single component scan isn't interleaved so its MCU is a single block
+    let (mcu_width, mcu_height) = if components.len() == 1 { (8, 8) } else { (8 * h_max, 8 * v_max) };
+    let mcus_across = width.div_ceil(mcu_width);
+    let mcus_down = height.div_ceil(mcu_height);
+    let single = components.len() == 1;
+    for component in components.iter_mut() {
+        let (h, v) = if single { (1, 1) } else { (component.h, component.v) };
+        component.stride = mcus_across * h * 8;
+        component.plane = vec![0; component.stride * mcus_down * v * 8];
+    }
+
+    let mut cosines = [[0f32; 8]; 8];
+    for (x, row) in cosines.iter_mut().enumerate() {
+        for (u, cosine) in row.iter_mut().enumerate() {
+            let scale = if u == 0 { 0.5 / 2f32.sqrt() } else { 0.5 };
+            *cosine = scale * ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos();
+        }
+    }
+
+    let mut reader = BitReader::new(data, 0);
+    let mut predictions = vec![0i32; components.len()];
+    for mcu in 0..mcus_across * mcus_down {
+        if restart_interval > 0 && mcu > 0 && mcu.is_multiple_of(restart_interval) {
+            reader.restart();
+            predictions.iter_mut().for_each(|p| *p = 0);
+        }
+        let (mcu_row, mcu_col) = (mcu / mcus_across, mcu % mcus_across);
+        for (c, component) in components.iter_mut().enumerate() {
+            let (h, v) = if single { (1, 1) } else { (component.h, component.v) };
+            let dc_table = dc_tables[component.dc_table].as_ref().expect("The scan references a missing Huffman table!");
+            let ac_table = ac_tables[component.ac_table].as_ref().expect("The scan references a missing Huffman table!");
+            let q = &quantization[component.quantization];
+            for block in 0..h * v {
+                // See T.81 F.2.2.1 and F.2.2.2
+                let mut coefficients = [0i32; 64];
+                let ssss = reader.decode(dc_table);
+                predictions[c] += reader.receive_extend(ssss);
+                coefficients[0] = predictions[c] * q[0];
+                let mut k = 1;
+                while k < 64 {
+                    let rs = reader.decode(ac_table);
+                    let (run, size) = ((rs >> 4) as usize, rs & 0x0F);
+                    if size == 0 {
+                        if run == 15 {
+                            k += 16;
+                            continue;
+                        }
+                        break;
+                    }
+                    k += run;
+                    if k > 63 {
+                        break;
+                    }
+                    coefficients[ZIGZAG[k]] = reader.receive_extend(size) * q[k];
+                    k += 1;
+                }
+
+                let row = (mcu_row * v + block / h) * 8;
+                let col = (mcu_col * h + block % h) * 8;
+                let stride = component.stride;
+                inverse_dct(&coefficients, &cosines, &mut component.plane[row * stride + col..], stride);
+            }
+        }
+    }
+}
+
+// Upsamples subsampled components and converts YCbCr to RGB, see JFIF 1.02 P3
+fn to_samples(components: &[Component], width: usize, height: usize, transform: Option<u8>) -> Vec<u8> {
+    let h_max = components.iter().map(|c| c.h).max().unwrap();
+    let v_max = components.iter().map(|c| c.v).max().unwrap();
+    let ycbcr = components.len() == 3 && transform != Some(0);
+    let mut samples = Vec::with_capacity(width * height * components.len());
+    for y in 0..height {
+        for x in 0..width {
+            let mut pixel = components.iter()
+                .map(|c| c.plane[(y * c.v / v_max) * c.stride + x * c.h / h_max] as f32)
+                .collect::<Vec<f32>>();
+            if ycbcr {
+                let (luma, cb, cr) = (pixel[0], pixel[1] - 128.0, pixel[2] - 128.0);
+                pixel = vec![luma + 1.402 * cr, luma - 0.344136 * cb - 0.714136 * cr, luma + 1.772 * cb];
+            }
+            samples.extend(pixel.iter().map(|&v| v.round().clamp(0.0, 255.0) as u8));
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::dc_only_jpeg;
+
+    #[test]
+    fn gray_with_restarts() {
+        // 16x16, one block per MCU and a restart after each one
+        let data = dc_only_jpeg(16, 16, &[(1, 1)], &[10, 250, 128, 0], 1);
+        assert_eq!(frame_marker(&data), Some(SOF0));
+
+        let jpeg = decode(&data);
+
+        assert_eq!((jpeg.width, jpeg.height, jpeg.components), (16, 16, 1));
+        assert_eq!(jpeg.samples[0], 10);
+        assert_eq!(jpeg.samples[15], 250);
+        assert_eq!(jpeg.samples[8 * 16], 128);
+        assert_eq!(jpeg.samples[16 * 16 - 1], 0);
+    }
+
+    #[test]
+    fn subsampled_ycbcr() {
+        // one MCU with two luma blocks side by side sharing the chroma blocks, Cr = 168 and Cb = 128
+        let data = dc_only_jpeg(16, 8, &[(2, 1), (1, 1), (1, 1)], &[100, 60, 128, 168], 0);
+
+        let jpeg = decode(&data);
+
+        assert_eq!((jpeg.width, jpeg.height, jpeg.components), (16, 8, 3));
+        assert_eq!(&jpeg.samples[..3], &[156, 71, 100]);
+        assert_eq!(&jpeg.samples[15 * 3..16 * 3], &[116, 31, 60]);
+        assert_eq!(&jpeg.samples[(7 * 16 + 7) * 3..(7 * 16 + 8) * 3], &[156, 71, 100]);
+    }
+}
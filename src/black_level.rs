@@ -0,0 +1,204 @@
+use crate::{geometry::Geometry, linearize::LinearizationInfo, raster::Raster};
+
+/// Statistics of a set of masked (optical black) samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlackStats {
+    pub mean: f64,
+    pub median: f64,
+    pub sigma: f64,
+    pub count: usize,
+}
+
+impl BlackStats {
+    fn from_samples(samples: &mut [f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let count = samples.len();
+        let mean = samples.iter().sum::<f64>() / count as f64;
+        let variance = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f64>() / count as f64;
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = if count.is_multiple_of(2) {
+            (samples[count / 2 - 1] + samples[count / 2]) / 2.0
+        } else {
+            samples[count / 2]
+        };
+        Some(Self { mean, median, sigma: variance.sqrt(), count })
+    }
+}
+
+/// Black levels measured from the pixels in the MaskedAreas.
+#[derive(Clone, Debug)]
+pub struct BlackLevelMeasurement {
+    pub repeat_rows: usize,
+    pub repeat_cols: usize,
+    pub samples_per_pixel: usize,
+    // indexed by [row][col][sample] of the repeat pattern, like BlackLevel
+    pub pattern: Vec<Option<BlackStats>>,
+    // what's left after removing the pattern, one per row / column of the active area and only for
+    // the rows / columns that the masked areas cover
+    pub rows: Vec<Option<BlackStats>>,
+    pub columns: Vec<Option<BlackStats>>,
+}
+
+/// Which black level to use when mapping raw values to linear values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlackLevelMode {
+    Tagged,
+    Measured,
+    // Uses the measured levels when any of them is further than this many raw units from the tagged one
+    MeasuredIfInvalid(f64),
+}
+
+impl BlackLevelMeasurement {
+    /// Measures the masked pixels with a repeat pattern of repeat_rows x repeat_cols, which is usually
+    /// the BlackLevelRepeatDim (or the CFA pattern size) and is relative to the active area's origin.
+    pub fn measure(raster: &Raster, geometry: &Geometry, repeat_rows: usize, repeat_cols: usize) -> Self {
+        let spp = raster.samples_per_pixel;
+        let active = geometry.active_area;
+        let cell = |row: usize, col: usize, sample: usize| {
+            let r = (row as isize - active.top as isize).rem_euclid(repeat_rows as isize) as usize;
+            let c = (col as isize - active.left as isize).rem_euclid(repeat_cols as isize) as usize;
+            (r * repeat_cols + c) * spp + sample
+        };
+
+        let mut pattern_samples = vec![Vec::new(); repeat_rows * repeat_cols * spp];
+        for area in &geometry.masked_areas {
+            for row in area.top..area.bottom {
+                for col in area.left..area.right {
+                    for sample in 0..spp {
+                        pattern_samples[cell(row, col, sample)].push(raster.get(row, col, sample) as f64);
+                    }
+                }
+            }
+        }
+        let pattern = pattern_samples.iter_mut().map(|s| BlackStats::from_samples(s)).collect::<Vec<Option<BlackStats>>>();
+
+        let mut row_samples = vec![Vec::new(); active.height()];
+        let mut column_samples = vec![Vec::new(); active.width()];
+        for area in &geometry.masked_areas {
+            for row in area.top..area.bottom {
+                for col in area.left..area.right {
+                    for sample in 0..spp {
+                        let residual = match pattern[cell(row, col, sample)] {
+                            Some(stats) => raster.get(row, col, sample) as f64 - stats.mean,
+                            None => continue,
+                        };
+                        if row >= active.top && row < active.bottom {
+                            row_samples[row - active.top].push(residual);
+                        }
+                        if col >= active.left && col < active.right {
+                            column_samples[col - active.left].push(residual);
+                        }
+                    }
+                }
+            }
+        }
+
+        Self {
+            repeat_rows,
+            repeat_cols,
+            samples_per_pixel: spp,
+            pattern,
+            rows: row_samples.iter_mut().map(|s| BlackStats::from_samples(s)).collect(),
+            columns: column_samples.iter_mut().map(|s| BlackStats::from_samples(s)).collect(),
+        }
+    }
+
+    /// How far the measured means are from the tagged black level, per pattern cell.
+    pub fn validate(&self, info: &LinearizationInfo) -> Vec<Option<f64>> {
+        let mut differences = Vec::with_capacity(self.pattern.len());
+        for r in 0..self.repeat_rows {
+            for c in 0..self.repeat_cols {
+                for sample in 0..self.samples_per_pixel {
+                    let tagged = info.black_level_at(info.active_area_top + r, info.active_area_left + c, sample);
+                    let measured = self.pattern[(r * self.repeat_cols + c) * self.samples_per_pixel + sample];
+                    differences.push(measured.map(|m| m.mean - tagged));
+                }
+            }
+        }
+        differences
+    }
+
+    /// Replaces the tagged black levels with the measured ones. Pattern cells the masked areas don't
+    /// cover keep their tagged level, and the per row / column deltas are only replaced when the masked
+    /// areas cover every row / column of the active area.
+    pub fn apply_to(&self, info: &mut LinearizationInfo) {
+        let mut black_level = Vec::with_capacity(self.pattern.len());
+        for r in 0..self.repeat_rows {
+            for c in 0..self.repeat_cols {
+                for sample in 0..self.samples_per_pixel {
+                    black_level.push(match self.pattern[(r * self.repeat_cols + c) * self.samples_per_pixel + sample] {
+                        Some(stats) => stats.mean,
+                        None => info.pattern_black_level(info.active_area_top + r, info.active_area_left + c, sample),
+                    });
+                }
+            }
+        }
+        info.black_level_repeat_rows = self.repeat_rows;
+        info.black_level_repeat_cols = self.repeat_cols;
+        info.black_level = black_level;
+        if self.rows.iter().all(|r| r.is_some()) {
+            info.black_level_delta_v = self.rows.iter().map(|r| r.unwrap().mean).collect();
+        }
+        if self.columns.iter().all(|c| c.is_some()) {
+            info.black_level_delta_h = self.columns.iter().map(|c| c.unwrap().mean).collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Rect;
+
+    #[test]
+    fn measure_masked_columns() {
+        // 2 masked columns on the left of a 4x4 image with a 2x2 black pattern
+        let mut data = vec![500u16; 16];
+        for row in 0..4 {
+            data[row * 4] = if row % 2 == 0 { 60 } else { 64 };
+            data[row * 4 + 1] = if row % 2 == 0 { 62 } else { 66 };
+        }
+        let raster = Raster { width: 4, height: 4, samples_per_pixel: 1, bits_per_sample: 16, data };
+        let geometry = Geometry {
+            masked_areas: vec![Rect { top: 0, left: 0, bottom: 4, right: 2 }],
+            ..Geometry::new(4, 4, Rect { top: 0, left: 2, bottom: 4, right: 4 })
+        };
+
+        let measurement = BlackLevelMeasurement::measure(&raster, &geometry, 2, 2);
+
+        let means = measurement.pattern.iter().map(|p| p.unwrap().mean).collect::<Vec<f64>>();
+        assert_eq!(means, vec![60.0, 62.0, 64.0, 66.0]);
+        assert!(measurement.rows.iter().all(|r| r.unwrap().mean == 0.0));
+        assert!(measurement.columns.iter().all(|c| c.is_none()));
+    }
+
+    #[test]
+    fn uncovered_cells_keep_tagged_levels() {
+        // one masked row above the active area only covers one row of the 2x2 pattern and none of
+        // the active area's rows
+        let raster = Raster { width: 2, height: 3, samples_per_pixel: 1, bits_per_sample: 16, data: vec![60, 62, 500, 500, 500, 500] };
+        let geometry = Geometry {
+            masked_areas: vec![Rect { top: 0, left: 0, bottom: 1, right: 2 }],
+            ..Geometry::new(2, 3, Rect { top: 1, left: 0, bottom: 3, right: 2 })
+        };
+        let mut info = LinearizationInfo {
+            black_level_delta_v: vec![1.0, 2.0],
+            active_area_top: 1,
+            ..LinearizationInfo::new(vec![50.0], vec![1000.0])
+        };
+
+        BlackLevelMeasurement::measure(&raster, &geometry, 2, 2).apply_to(&mut info);
+
+        // the masked row is row 1 of the pattern since the pattern starts at the active area's top
+        assert_eq!(info.black_level, vec![50.0, 50.0, 60.0, 62.0]);
+        assert_eq!(info.black_level_delta_v, vec![1.0, 2.0]);
+        assert_eq!(info.black_level_delta_h, vec![0.0, 0.0]);
+
+        // without masked areas nothing changes
+        let unmasked = Geometry { masked_areas: Vec::new(), ..geometry };
+        BlackLevelMeasurement::measure(&raster, &unmasked, 2, 2).apply_to(&mut info);
+        assert_eq!(info.black_level, vec![50.0, 50.0, 60.0, 62.0]);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{tags::Tag, Endian, IFD};
+
+/// The color codes used by CFAPattern and CFAPlaneColor, see TIFF/EP CFAPattern.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CfaColor {
+    Red,
+    Green,
+    Blue,
+    Cyan,
+    Magenta,
+    Yellow,
+    White,
+    Other(u8),
+}
+
+impl CfaColor {
+    fn from_code(code: u8) -> Self {
+        use CfaColor::*;
+        match code {
+            0 => Red,
+            1 => Green,
+            2 => Blue,
+            3 => Cyan,
+            4 => Magenta,
+            5 => Yellow,
+            6 => White,
+            _ => Other(code),
+        }
+    }
+}
+
+/// DNG spec 1.6 CFALayout P38, where the sample centers are relative to a rectangular grid.
+/// The spec counts rows and columns from 1, so its even rows and columns are the odd ones here.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CfaLayout {
+    Rectangular,
+    // even columns are offset down 1/2 row
+    StaggeredA,
+    // even columns are offset up 1/2 row
+    StaggeredB,
+    // even rows are offset right 1/2 column
+    StaggeredC,
+    // even rows are offset left 1/2 column
+    StaggeredD,
+    // even rows are offset up 1/2 row, even columns are offset left 1/2 column
+    StaggeredE,
+    // even rows are offset up 1/2 row, even columns are offset right 1/2 column
+    StaggeredF,
+    // even rows are offset down 1/2 row, even columns are offset left 1/2 column
+    StaggeredG,
+    // even rows are offset down 1/2 row, even columns are offset right 1/2 column
+    StaggeredH,
+}
+
+impl CfaLayout {
+    fn from_value(value: u16) -> Self {
+        use CfaLayout::*;
+        match value {
+            1 => Rectangular,
+            2 => StaggeredA,
+            3 => StaggeredB,
+            4 => StaggeredC,
+            5 => StaggeredD,
+            6 => StaggeredE,
+            7 => StaggeredF,
+            8 => StaggeredG,
+            9 => StaggeredH,
+            _ => panic!("That isn't a valid CFALayout!")
+        }
+    }
+}
+
+/// The color filter array, an N x M grid of colors that repeats across the active area.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CfaPattern {
+    pub rows: usize,
+    pub cols: usize,
+    // color codes, row major
+    pub pattern: Vec<u8>,
+    // the color of each plane, defaults to red, green, blue
+    pub plane_colors: Vec<u8>,
+    pub layout: CfaLayout,
+}
+
+impl CfaPattern {
+    pub fn new(rows: usize, cols: usize, pattern: Vec<u8>, plane_colors: Vec<u8>) -> Self {
+        assert_eq!(pattern.len(), rows * cols, "The CFA pattern doesn't match its dimensions!");
+        for code in &pattern {
+            assert!(plane_colors.contains(code), "The CFA pattern uses a color that isn't one of the planes!");
+        }
+        Self { rows, cols, pattern, plane_colors, layout: CfaLayout::Rectangular }
+    }
+
+    // None for IFDs that aren't CFA images
+    pub(crate) fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian) -> Option<Self> {
+        let pattern = ifd.get_values(Tag::CFAPattern_33422, buffer, endian)?.to_vec().iter().map(|f| f.to_u8()).collect::<Vec<u8>>();
+        let (rows, cols) = match ifd.get_values(Tag::CFARepeatPatternDim_33421, buffer, endian) {
+            Some(dim) => {
+                let dim = dim.to_vec();
+                (dim[0].to_usize(), dim[1].to_usize())
+            },
+            None => (2, 2),
+        };
+        let plane_colors = match ifd.get_values(Tag::CFAPlaneColor_50710, buffer, endian) {
+            Some(colors) => colors.to_vec().iter().map(|f| f.to_u8()).collect(),
+            None => vec![0, 1, 2],
+        };
+        let mut cfa = Self::new(rows, cols, pattern, plane_colors);
+        if let Some(layout) = ifd.get_values(Tag::CFALayout_50711, buffer, endian) {
+            cfa.layout = CfaLayout::from_value(layout.to_value().to_u16());
+        }
+        Some(cfa)
+    }
+
+    pub fn planes(&self) -> usize {
+        self.plane_colors.len()
+    }
+
+    fn code_at(&self, row: usize, col: usize) -> u8 {
+        self.pattern[(row % self.rows) * self.cols + col % self.cols]
+    }
+
+    /// The color at a position relative to the top left of the active area.
+    pub fn color_at(&self, row: usize, col: usize) -> CfaColor {
+        CfaColor::from_code(self.code_at(row, col))
+    }
+
+    /// Which plane the sample at a position relative to the top left of the active area belongs to.
+    pub fn plane_at(&self, row: usize, col: usize) -> usize {
+        let code = self.code_at(row, col);
+        self.plane_colors.iter().position(|&c| c == code).unwrap()
+    }
+
+    pub fn plane_color(&self, plane: usize) -> CfaColor {
+        CfaColor::from_code(self.plane_colors[plane])
+    }
+
+    /// The same pattern as seen from a different origin, e.g. the default crop's, which is `top` rows
+    /// down and `left` columns right of the current one. Negative values move up and left.
+    pub fn offset(&self, top: isize, left: isize) -> Self {
+        let mut pattern = Vec::with_capacity(self.pattern.len());
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                let row = (r as isize + top).rem_euclid(self.rows as isize) as usize;
+                let col = (c as isize + left).rem_euclid(self.cols as isize) as usize;
+                pattern.push(self.code_at(row, col));
+            }
+        }
+        Self { pattern, ..self.clone() }
+    }
+
+    /// Where the center of a sample is on the sensor in units of rows and columns, which is only
+    /// different from the position in the image for the staggered layouts.
+    pub fn sample_center(&self, row: usize, col: usize) -> (f64, f64) {
+        use CfaLayout::*;
+        // even counting from 1
+        let even_row = !row.is_multiple_of(2);
+        let even_col = !col.is_multiple_of(2);
+        let half = |even: bool, offset: f64| if even { offset } else { 0.0 };
+        let (dy, dx) = match self.layout {
+            Rectangular => (0.0, 0.0),
+            StaggeredA => (half(even_col, 0.5), 0.0),
+            StaggeredB => (half(even_col, -0.5), 0.0),
+            StaggeredC => (0.0, half(even_row, 0.5)),
+            StaggeredD => (0.0, half(even_row, -0.5)),
+            StaggeredE => (half(even_row, -0.5), half(even_col, -0.5)),
+            StaggeredF => (half(even_row, -0.5), half(even_col, 0.5)),
+            StaggeredG => (half(even_row, 0.5), half(even_col, -0.5)),
+            StaggeredH => (half(even_row, 0.5), half(even_col, 0.5)),
+        };
+        (row as f64 + dy, col as f64 + dx)
+    }
+
+    /// A 2x2 red, green, blue pattern on a rectangular grid.
+    pub fn is_bayer(&self) -> bool {
+        self.rows == 2 && self.cols == 2 && self.layout == CfaLayout::Rectangular && self.planes() == 3
+            && self.pattern.iter().filter(|&&c| c == self.plane_colors[1]).count() == 2
+    }
+
+    /// Fujifilm's 6x6 red, green, blue pattern, where 20 of the 36 samples are green and every row
+    /// and column has all three colors.
+    pub fn is_xtrans(&self) -> bool {
+        if self.rows != 6 || self.cols != 6 || self.layout != CfaLayout::Rectangular || self.planes() != 3 {
+            return false;
+        }
+        let every_color = |codes: &[u8]| self.plane_colors.iter().all(|c| codes.contains(c));
+        let rows_ok = (0..6).all(|r| every_color(&(0..6).map(|c| self.code_at(r, c)).collect::<Vec<u8>>()));
+        let cols_ok = (0..6).all(|c| every_color(&(0..6).map(|r| self.code_at(r, c)).collect::<Vec<u8>>()));
+        rows_ok && cols_ok && self.pattern.iter().filter(|&&c| c == self.plane_colors[1]).count() == 20
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bayer() {
+        // RGGB
+        let cfa = CfaPattern::new(2, 2, vec![0, 1, 1, 2], vec![0, 1, 2]);
+
+        assert!(cfa.is_bayer());
+        assert_eq!(cfa.color_at(0, 0), CfaColor::Red);
+        assert_eq!(cfa.color_at(3, 5), CfaColor::Blue);
+        assert_eq!(cfa.plane_at(2, 1), 1);
+        assert_eq!(cfa.offset(1, 1).pattern, vec![2, 1, 1, 0]);
+        assert_eq!(cfa.offset(-1, 0).pattern, vec![1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn xtrans() {
+        let pattern = vec![
+            1, 1, 0, 1, 1, 2,
+            1, 1, 2, 1, 1, 0,
+            2, 0, 1, 0, 2, 1,
+            1, 1, 2, 1, 1, 0,
+            1, 1, 0, 1, 1, 2,
+            0, 2, 1, 2, 0, 1,
+        ];
+        let cfa = CfaPattern::new(6, 6, pattern, vec![0, 1, 2]);
+
+        assert!(cfa.is_xtrans());
+        assert!(!cfa.is_bayer());
+        assert_eq!(cfa.color_at(8, 7), CfaColor::Red);
+
+        // a Bayer pattern repeated out to 6x6 has rows without blue
+        let tiled = (0..36).map(|i| [0, 1, 1, 2][(i / 6 % 2) * 2 + i % 2]).collect();
+        assert!(!CfaPattern::new(6, 6, tiled, vec![0, 1, 2]).is_xtrans());
+    }
+
+    #[test]
+    fn staggered_centers() {
+        // layout A offsets the spec's even columns, the 2nd, 4th.. counting from 1, down 1/2 row
+        let mut cfa = CfaPattern::new(2, 2, vec![0, 1, 1, 2], vec![0, 1, 2]);
+        cfa.layout = CfaLayout::StaggeredA;
+        assert_eq!(cfa.sample_center(0, 0), (0.0, 0.0));
+        assert_eq!(cfa.sample_center(0, 1), (0.5, 1.0));
+        assert_eq!(cfa.sample_center(3, 2), (3.0, 2.0));
+
+        // layout H offsets the spec's even rows down and its even columns right
+        cfa.layout = CfaLayout::StaggeredH;
+        assert_eq!(cfa.sample_center(0, 0), (0.0, 0.0));
+        assert_eq!(cfa.sample_center(1, 1), (1.5, 1.5));
+    }
+
+    #[test]
+    fn cmy_planes() {
+        let cfa = CfaPattern::new(2, 2, vec![3, 4, 4, 5], vec![3, 4, 5]);
+
+        assert_eq!(cfa.plane_color(2), CfaColor::Yellow);
+        assert_eq!(cfa.plane_at(1, 1), 2);
+    }
+}
@@ -0,0 +1,331 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{baseline_jpeg, deflate, Endian, lossless_jpeg};
+
+/// Describes the strip or tile that's being decoded or encoded.
+pub struct ChunkInfo {
+    pub width: usize,
+    pub length: usize,
+    pub samples_per_pixel: usize,
+    pub bits_per_sample: usize,
+    pub endian: Endian,
+}
+
+impl ChunkInfo {
+    fn samples_per_row(&self) -> usize {
+        self.width * self.samples_per_pixel
+    }
+}
+
+/// Why a strip or tile couldn't be decoded or encoded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CodecError {
+    // there's no codec for the Compression_259 value
+    UnknownCompression(u16),
+    // the codec only decodes
+    EncodeUnsupported,
+    // the data doesn't fit the strip or tile it's for
+    Invalid(String),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::UnknownCompression(compression) => write!(f, "There's no codec registered for compression {}", compression),
+            CodecError::EncodeUnsupported => write!(f, "This codec can't encode"),
+            CodecError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// Converts between the stored (compressed) bytes of a strip or tile and the bytes it would occupy
+/// if it were stored uncompressed, see TIFF6.0 P30 and DNG spec 1.6 P20.
+pub trait Codec {
+    fn decode(&self, data: &[u8], chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError>;
+
+    fn encode(&self, _data: &[u8], _chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        Err(CodecError::EncodeUnsupported)
+    }
+}
+
+/// Compression = 1
+pub struct Uncompressed;
+
+impl Codec for Uncompressed {
+    fn decode(&self, data: &[u8], _chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        Ok(data.to_vec())
+    }
+
+    fn encode(&self, data: &[u8], _chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        Ok(data.to_vec())
+    }
+}
+
+/// Compression = 7, the lossless (SOF3) flavor that DNG uses for raw data
+pub struct LosslessJpeg;
+
+impl Codec for LosslessJpeg {
+    fn decode(&self, data: &[u8], chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        let jpeg = lossless_jpeg::decode(data);
+        // DNG spec 1.6 P20, the JPEG width * components can differ from the tile's as long as the
+        // sample sequence is the same
+        let expected = chunk.samples_per_row() * chunk.length;
+        if jpeg.width * jpeg.components * jpeg.height < expected {
+            return Err(CodecError::Invalid("The JPEG data has fewer samples than the tile".to_string()));
+        }
+        Ok(pack_samples(&jpeg.samples[..expected], chunk))
+    }
+
+    fn encode(&self, data: &[u8], chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        let samples = unpack_samples(data, chunk);
+        Ok(lossless_jpeg::encode(&samples, chunk.width, chunk.length, chunk.samples_per_pixel, chunk.bits_per_sample))
+    }
+}
+
+/// 8 bit baseline (SOF0) and extended sequential (SOF1) JPEG, which is what previews use. Three
+/// component data comes out as RGB, converted from YCbCr unless an Adobe marker says it's RGB.
+pub struct BaselineJpeg;
+
+impl Codec for BaselineJpeg {
+    fn decode(&self, data: &[u8], chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        if chunk.bits_per_sample != 8 {
+            return Err(CodecError::Invalid("Baseline JPEG data has 8 bit samples".to_string()));
+        }
+        let jpeg = baseline_jpeg::decode(data);
+        if jpeg.components != chunk.samples_per_pixel {
+            return Err(CodecError::Invalid("The JPEG data doesn't have the tile's samples per pixel".to_string()));
+        }
+        if jpeg.width < chunk.width || jpeg.height < chunk.length {
+            return Err(CodecError::Invalid("The JPEG data is smaller than the tile".to_string()));
+        }
+        let row_length = jpeg.width * jpeg.components;
+        Ok(jpeg.samples.chunks(row_length).take(chunk.length)
+            .flat_map(|row| &row[..chunk.samples_per_row()])
+            .copied()
+            .collect())
+    }
+}
+
+/// Compression = 7 (and the lossy JPEG compression = 34892 of DNG spec 1.6 P20), which is either
+/// lossless or baseline JPEG depending on the frame marker. Encoding is always lossless.
+pub struct Jpeg;
+
+impl Codec for Jpeg {
+    fn decode(&self, data: &[u8], chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        match baseline_jpeg::frame_marker(data) {
+            Some(baseline_jpeg::SOF0 | baseline_jpeg::SOF1) => BaselineJpeg.decode(data, chunk),
+            _ => LosslessJpeg.decode(data, chunk),
+        }
+    }
+
+    fn encode(&self, data: &[u8], chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        LosslessJpeg.encode(data, chunk)
+    }
+}
+
+/// Compression = 8 and the older 32946, zlib wrapped Deflate. Any Predictor is undone after decoding.
+pub struct Deflate;
+
+impl Codec for Deflate {
+    fn decode(&self, data: &[u8], _chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        Ok(deflate::inflate(data))
+    }
+}
+
+/// The codecs available for decoding and encoding image data, keyed by Compression_259 value.
+pub struct CodecRegistry {
+    codecs: HashMap<u16, Box<dyn Codec>>,
+}
+
+impl CodecRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self { codecs: HashMap::new() };
+        registry.register(1, Box::new(Uncompressed));
+        registry.register(7, Box::new(Jpeg));
+        registry.register(8, Box::new(Deflate));
+        registry.register(32946, Box::new(Deflate));
+        registry.register(34892, Box::new(Jpeg));
+        registry
+    }
+
+    /// Adds a codec, replacing whatever was registered for that compression before.
+    pub fn register(&mut self, compression: u16, codec: Box<dyn Codec>) {
+        self.codecs.insert(compression, codec);
+    }
+
+    pub fn get(&self, compression: u16) -> Option<&dyn Codec> {
+        self.codecs.get(&compression).map(|c| c.as_ref())
+    }
+
+    pub fn decode(&self, compression: u16, data: &[u8], chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        match self.get(compression) {
+            Some(codec) => codec.decode(data, chunk),
+            None => Err(CodecError::UnknownCompression(compression)),
+        }
+    }
+
+    pub fn encode(&self, compression: u16, data: &[u8], chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+        match self.get(compression) {
+            Some(codec) => codec.encode(data, chunk),
+            None => Err(CodecError::UnknownCompression(compression)),
+        }
+    }
+}
+
+impl Default for CodecRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// TIFF6.0 P15, 8 and 16 bit samples use the file's byte order, everything else is packed
+// most significant bit first with every row starting on a byte boundary (DNG spec 1.6 P17)
+pub(crate) fn pack_samples(samples: &[u16], chunk: &ChunkInfo) -> Vec<u8> {
+    match chunk.bits_per_sample {
+        8 => samples.iter().map(|&s| s as u8).collect(),
+        16 => {
+            let mut data = Vec::with_capacity(samples.len() * 2);
+            for &sample in samples {
+                match chunk.endian {
+                    Endian::Big => data.extend(sample.to_be_bytes()),
+                    Endian::Little => data.extend(sample.to_le_bytes()),
+                }
+            }
+            data
+        },
+        bits => {
+            let row_bytes = (chunk.samples_per_row() * bits).div_ceil(8);
+            let mut data = Vec::with_capacity(row_bytes * chunk.length);
+            for row in samples.chunks(chunk.samples_per_row()) {
+                let mut buffer = 0u32;
+                let mut count = 0;
+                for &sample in row {
+                    buffer = (buffer << bits) | (sample as u32 & ((1 << bits) - 1));
+                    count += bits;
+                    while count >= 8 {
+                        data.push((buffer >> (count - 8)) as u8);
+                        count -= 8;
+                    }
+                }
+                if count > 0 {
+                    data.push((buffer << (8 - count)) as u8);
+                }
+            }
+            data
+        }
+    }
+}
+
+pub(crate) fn unpack_samples(data: &[u8], chunk: &ChunkInfo) -> Vec<u16> {
+    let count = chunk.samples_per_row() * chunk.length;
+    match chunk.bits_per_sample {
+        8 => data[..count].iter().map(|&b| b as u16).collect(),
+        16 => data[..count * 2].chunks(2).map(|b| match chunk.endian {
+            Endian::Big => u16::from_be_bytes([b[0], b[1]]),
+            Endian::Little => u16::from_le_bytes([b[0], b[1]]),
+        }).collect(),
+        bits => {
+            let row_bytes = (chunk.samples_per_row() * bits).div_ceil(8);
+            let mut samples = Vec::with_capacity(count);
+            for row in data.chunks(row_bytes).take(chunk.length) {
+                let mut buffer = 0u32;
+                let mut available = 0;
+                let mut bytes = row.iter();
+                for _ in 0..chunk.samples_per_row() {
+                    while available < bits {
+                        buffer = (buffer << 8) | *bytes.next().unwrap_or(&0) as u32;
+                        available += 8;
+                    }
+                    samples.push(((buffer >> (available - bits)) & ((1 << bits) - 1)) as u16);
+                    available -= bits;
+                }
+            }
+            samples
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::dc_only_jpeg;
+
+    struct Inverted;
+
+    impl Codec for Inverted {
+        fn decode(&self, data: &[u8], _chunk: &ChunkInfo) -> Result<Vec<u8>, CodecError> {
+            Ok(data.iter().map(|b| !b).collect())
+        }
+    }
+
+    fn chunk(width: usize, length: usize, samples_per_pixel: usize, bits_per_sample: usize, endian: Endian) -> ChunkInfo {
+        ChunkInfo { width, length, samples_per_pixel, bits_per_sample, endian }
+    }
+
+    #[test]
+    fn register_and_override() {
+        let mut registry = CodecRegistry::new();
+        let info = chunk(2, 1, 1, 8, Endian::Little);
+        assert!(registry.get(5).is_none());
+
+        registry.register(5, Box::new(Inverted));
+        assert_eq!(registry.decode(5, &[0x00, 0x0F], &info), Ok(vec![0xFF, 0xF0]));
+        // Inverted doesn't encode
+        assert_eq!(registry.encode(5, &[0x00], &info), Err(CodecError::EncodeUnsupported));
+
+        registry.register(1, Box::new(Inverted));
+        assert_eq!(registry.decode(1, &[0x00, 0x0F], &info), Ok(vec![0xFF, 0xF0]));
+    }
+
+    #[test]
+    fn unknown_compression() {
+        let registry = CodecRegistry::new();
+        let info = chunk(1, 1, 1, 8, Endian::Big);
+
+        assert_eq!(registry.decode(5, &[0], &info), Err(CodecError::UnknownCompression(5)));
+        assert_eq!(registry.encode(5, &[0], &info), Err(CodecError::UnknownCompression(5)));
+    }
+
+    #[test]
+    fn pack_round_trips() {
+        // every depth packs into the bytes unpack reads back, rows of 3 samples don't end on a byte
+        // boundary for 10, 12 and 14 bits
+        for bits in [8, 10, 12, 14, 16] {
+            for endian in [Endian::Big, Endian::Little] {
+                let info = chunk(3, 2, 1, bits, endian);
+                let max = ((1u32 << bits) - 1) as u16;
+                let samples = vec![0, max, 1, max / 3, max - 1, 0x55 & max];
+
+                let packed = pack_samples(&samples, &info);
+
+                assert_eq!(packed.len(), (3 * bits).div_ceil(8) * 2);
+                assert_eq!(unpack_samples(&packed, &info), samples);
+            }
+        }
+    }
+
+    #[test]
+    fn packed_byte_orders() {
+        assert_eq!(pack_samples(&[0x0102], &chunk(1, 1, 1, 16, Endian::Big)), vec![0x01, 0x02]);
+        assert_eq!(pack_samples(&[0x0102], &chunk(1, 1, 1, 16, Endian::Little)), vec![0x02, 0x01]);
+        // 12 bit samples are packed most significant bit first whatever the byte order
+        assert_eq!(pack_samples(&[0xABC, 0x123], &chunk(2, 1, 1, 12, Endian::Little)), vec![0xAB, 0xC1, 0x23]);
+    }
+
+    #[test]
+    fn jpeg_dispatch() {
+        let registry = CodecRegistry::new();
+        let info = chunk(2, 2, 1, 16, Endian::Little);
+        let samples = [1000u16, 2000, 3000, 4000];
+        let lossless = registry.encode(7, &pack_samples(&samples, &info), &info).unwrap();
+        assert_eq!(unpack_samples(&registry.decode(7, &lossless, &info).unwrap(), &info), samples);
+
+        // a baseline JPEG tile that's bigger than the part of it in the image
+        let baseline = dc_only_jpeg(8, 8, &[(1, 1)], &[200], 0);
+        assert_eq!(registry.decode(7, &baseline, &chunk(3, 2, 1, 8, Endian::Little)), Ok(vec![200; 6]));
+        // and one that's smaller
+        assert!(matches!(registry.decode(7, &baseline, &chunk(9, 2, 1, 8, Endian::Little)), Err(CodecError::Invalid(_))));
+    }
+}
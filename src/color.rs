@@ -0,0 +1,457 @@
+use crate::{linearize::LinearImage, matrix::Matrix, tags::Tag, Endian, IFD};
+
+// The profile connection space is XYZ with a D50 white, see DNG spec 1.6 Chapter 6
+pub const D50: (f64, f64) = (0.3457, 0.3585);
+pub const D65: (f64, f64) = (0.3127, 0.3290);
+
+pub fn xy_to_xyz(xy: (f64, f64)) -> [f64; 3] {
+    let (x, y) = xy;
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+pub fn xyz_to_xy(xyz: &[f64]) -> (f64, f64) {
+    let total = xyz[0] + xyz[1] + xyz[2];
+    if total > 0.0 {
+        (xyz[0] / total, xyz[1] / total)
+    } else {
+        D50
+    }
+}
+
+/// The linearized Bradford transform that maps colors seen under one white to colors seen under another.
+pub fn adaptation_matrix(from: (f64, f64), to: (f64, f64)) -> Matrix {
+    let bradford = Matrix::new(3, 3, vec![
+        0.8951, 0.2664, -0.1614,
+        -0.7502, 1.7135, 0.0367,
+        0.0389, -0.0685, 1.0296,
+    ]);
+    let w1 = bradford.apply(&xy_to_xyz(from));
+    let w2 = bradford.apply(&xy_to_xyz(to));
+    // negative whites are meaningless, and the scaling is limited to something reasonable
+    let gains = (0..3)
+        .map(|i| if w1[i].max(0.0) > 0.0 { (w2[i].max(0.0) / w1[i]).clamp(0.1, 10.0) } else { 10.0 })
+        .collect::<Vec<f64>>();
+    bradford.inverse() * Matrix::diagonal(&gains) * bradford
+}
+
+// Robertson's isotemperature lines: reciprocal megakelvin, u, v and the slope of the line
+const TEMPERATURE_TABLE: [[f64; 4]; 31] = [
+    [0.0, 0.18006, 0.26352, -0.24341],
+    [10.0, 0.18066, 0.26589, -0.25479],
+    [20.0, 0.18133, 0.26846, -0.26876],
+    [30.0, 0.18208, 0.27119, -0.28539],
+    [40.0, 0.18293, 0.27407, -0.30470],
+    [50.0, 0.18388, 0.27709, -0.32675],
+    [60.0, 0.18494, 0.28021, -0.35156],
+    [70.0, 0.18611, 0.28342, -0.37915],
+    [80.0, 0.18740, 0.28668, -0.40955],
+    [90.0, 0.18880, 0.28997, -0.44278],
+    [100.0, 0.19032, 0.29326, -0.47888],
+    [125.0, 0.19462, 0.30141, -0.58204],
+    [150.0, 0.19962, 0.30921, -0.70471],
+    [175.0, 0.20525, 0.31647, -0.84901],
+    [200.0, 0.21142, 0.32312, -1.0182],
+    [225.0, 0.21807, 0.32909, -1.2168],
+    [250.0, 0.22511, 0.33439, -1.4512],
+    [275.0, 0.23247, 0.33904, -1.7298],
+    [300.0, 0.24010, 0.34308, -2.0637],
+    [325.0, 0.24702, 0.34655, -2.4681],
+    [350.0, 0.25591, 0.34951, -2.9641],
+    [375.0, 0.26400, 0.35200, -3.5814],
+    [400.0, 0.27218, 0.35407, -4.3633],
+    [425.0, 0.28039, 0.35577, -5.3762],
+    [450.0, 0.28863, 0.35714, -6.7262],
+    [475.0, 0.29685, 0.35823, -8.5955],
+    [500.0, 0.30505, 0.35907, -11.324],
+    [525.0, 0.31320, 0.35968, -15.628],
+    [550.0, 0.32129, 0.36011, -23.325],
+    [575.0, 0.32931, 0.36038, -40.770],
+    [600.0, 0.33724, 0.36051, -116.45],
+];
+
+// Scales the distance from the black body curve in uv to Adobe's tint units
+const TINT_SCALE: f64 = -3000.0;
+
+/// The correlated color temperature and tint of a white, using Robertson's method.
+pub fn xy_to_temperature(xy: (f64, f64)) -> (f64, f64) {
+    let (x, y) = xy;
+    let u = 2.0 * x / (1.5 - x + 6.0 * y);
+    let v = 3.0 * y / (1.5 - x + 6.0 * y);
+
+    let (mut last_dt, mut last_du, mut last_dv) = (0.0, 0.0, 0.0);
+    for index in 1..TEMPERATURE_TABLE.len() {
+        let [r, line_u, line_v, slope] = TEMPERATURE_TABLE[index];
+        let len = (1.0 + slope * slope).sqrt();
+        let (du, dv) = (1.0 / len, slope / len);
+        // the distance above or below the isotemperature line
+        let dt = -(u - line_u) * dv + (v - line_v) * du;
+        if dt <= 0.0 || index == TEMPERATURE_TABLE.len() - 1 {
+            let dt = -dt.min(0.0);
+            let f = if index == 1 { 0.0 } else { dt / (last_dt + dt) };
+            let [last_r, last_u, last_v, _] = TEMPERATURE_TABLE[index - 1];
+            let temperature = 1.0e6 / (last_r * f + r * (1.0 - f));
+
+            let uu = u - (last_u * f + line_u * (1.0 - f));
+            let vv = v - (last_v * f + line_v * (1.0 - f));
+            let du = du * (1.0 - f) + last_du * f;
+            let dv = dv * (1.0 - f) + last_dv * f;
+            let len = (du * du + dv * dv).sqrt();
+            let tint = (uu * du / len + vv * dv / len) * TINT_SCALE;
+            return (temperature, tint);
+        }
+        last_dt = dt;
+        last_du = du;
+        last_dv = dv;
+    }
+    unreachable!()
+}
+
+/// The white with a correlated color temperature and tint, the inverse of xy_to_temperature.
+pub fn temperature_to_xy(temperature: f64, tint: f64) -> (f64, f64) {
+    let r = 1.0e6 / temperature;
+    let offset = tint / TINT_SCALE;
+    let last = TEMPERATURE_TABLE.len() - 2;
+    for index in 0..=last {
+        let [r1, u1, v1, slope1] = TEMPERATURE_TABLE[index];
+        let [r2, u2, v2, slope2] = TEMPERATURE_TABLE[index + 1];
+        if r < r2 || index == last {
+            let f = (r2 - r) / (r2 - r1);
+            let mut u = u1 * f + u2 * (1.0 - f);
+            let mut v = v1 * f + v2 * (1.0 - f);
+
+            let len1 = (1.0 + slope1 * slope1).sqrt();
+            let len2 = (1.0 + slope2 * slope2).sqrt();
+            let du = f / len1 + (1.0 - f) / len2;
+            let dv = slope1 / len1 * f + slope2 / len2 * (1.0 - f);
+            let len = (du * du + dv * dv).sqrt();
+            u += du / len * offset;
+            v += dv / len * offset;
+
+            return (1.5 * u / (u - 4.0 * v + 2.0), v / (u - 4.0 * v + 2.0));
+        }
+    }
+    unreachable!()
+}
+
+/// The correlated color temperature of an EXIF LightSource, 0.0 for unknown or other light sources.
+pub fn illuminant_temperature(light_source: u16) -> f64 {
+    match light_source {
+        // standard light A, tungsten
+        17 | 3 => 2850.0,
+        // ISO studio tungsten
+        24 => 3200.0,
+        // D50
+        23 => 5000.0,
+        // D55, daylight, fine weather, flash, standard light B
+        20 | 1 | 9 | 4 | 18 => 5500.0,
+        // D65, standard light C, cloudy weather
+        21 | 19 | 10 => 6500.0,
+        // D75, shade
+        22 | 11 => 7500.0,
+        // daylight fluorescent
+        12 => 6430.0,
+        // day white fluorescent
+        13 => 5000.0,
+        // cool white fluorescent, fluorescent
+        14 | 2 => 4150.0,
+        // white fluorescent
+        15 => 3450.0,
+        // warm white fluorescent
+        16 => 2940.0,
+        _ => 0.0,
+    }
+}
+
+/// The color calibration tags from IFD 0, the second set is only there for dual illuminant profiles.
+#[derive(Clone, Debug)]
+pub struct ColorCalibration {
+    pub planes: usize,
+    pub calibration_illuminant: [u16; 2],
+    // XYZ to reference camera native values, N x 3
+    pub color_matrix: [Option<Matrix>; 2],
+    // reference camera to individual camera native values, N x N
+    pub camera_calibration: [Option<Matrix>; 2],
+    // white balanced camera values to XYZ D50, 3 x N
+    pub forward_matrix: [Option<Matrix>; 2],
+    // for cameras with more than three planes, 3 x N
+    pub reduction_matrix: [Option<Matrix>; 2],
+    pub analog_balance: Vec<f64>,
+}
+
+// What the calibration looks like for a particular white, see DNG spec 1.6 P88
+struct Interpolated {
+    // AnalogBalance * CameraCalibration * ColorMatrix
+    xyz_to_camera: Matrix,
+    camera_calibration: Matrix,
+    forward_matrix: Option<Matrix>,
+    reduction_matrix: Option<Matrix>,
+}
+
+impl ColorCalibration {
+    // None when there's no ColorMatrix1
+    pub(crate) fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian) -> Option<Self> {
+        let f64s = |tag: Tag| ifd.get_values(tag, buffer, endian).map(|v| v.to_vec().iter().map(|f| f.to_f64()).collect::<Vec<f64>>());
+
+        let color_matrix_1 = f64s(Tag::ColorMatrix_50721)?;
+        let planes = color_matrix_1.len() / 3;
+        let matrix = |tag: Tag, rows: usize, cols: usize| f64s(tag).map(|values| Matrix::new(rows, cols, values));
+        let illuminant = |tag: Tag| ifd.get_values(tag, buffer, endian).map(|v| v.to_value().to_u16()).unwrap_or(0);
+
+        Some(Self {
+            planes,
+            calibration_illuminant: [illuminant(Tag::CalibrationIlluminant_50778), illuminant(Tag::CalibrationIlluminant_50779)],
+            color_matrix: [Some(Matrix::new(planes, 3, color_matrix_1)), matrix(Tag::ColorMatrix_50722, planes, 3)],
+            camera_calibration: [matrix(Tag::CameraCalibration_50723, planes, planes), matrix(Tag::CameraCalibration_50724, planes, planes)],
+            forward_matrix: [
+                matrix(Tag::ForwardMatrix_50964, 3, planes).map(|m| normalize_forward_matrix(&m)),
+                matrix(Tag::ForwardMatrix_50965, 3, planes).map(|m| normalize_forward_matrix(&m)),
+            ],
+            reduction_matrix: [matrix(Tag::ReductionMatrix_50725, 3, planes), matrix(Tag::ReductionMatrix_50726, 3, planes)],
+            analog_balance: f64s(Tag::AnalogBalance_50727).unwrap_or_else(|| vec![1.0; planes]),
+        })
+    }
+
+    /// The calibration illuminants' temperatures, lowest first, or None for single illuminant profiles.
+    pub fn temperatures(&self) -> Option<(f64, f64)> {
+        let t1 = illuminant_temperature(self.calibration_illuminant[0]);
+        let t2 = illuminant_temperature(self.calibration_illuminant[1]);
+        if self.color_matrix[1].is_none() || t1 <= 0.0 || t2 <= 0.0 || t1 == t2 {
+            None
+        } else {
+            Some((t1.min(t2), t1.max(t2)))
+        }
+    }
+
+    /// How much of the first calibration to use for a white, interpolated linearly in inverse temperature.
+    pub fn first_calibration_weight(&self, white: (f64, f64)) -> f64 {
+        let (t1, t2) = match self.temperatures() {
+            Some(temperatures) => temperatures,
+            None => return 1.0,
+        };
+        let (temperature, _) = xy_to_temperature(white);
+        let low_weight = if temperature <= t1 {
+            1.0
+        } else if temperature >= t2 {
+            0.0
+        } else {
+            (1.0 / temperature - 1.0 / t2) / (1.0 / t1 - 1.0 / t2)
+        };
+        // the weight is for the lower temperature, which isn't necessarily the first illuminant
+        if illuminant_temperature(self.calibration_illuminant[0]) < illuminant_temperature(self.calibration_illuminant[1]) {
+            low_weight
+        } else {
+            1.0 - low_weight
+        }
+    }
+
+    fn interpolate(&self, white: (f64, f64)) -> Interpolated {
+        let g = self.first_calibration_weight(white);
+        let mix = |pair: &[Option<Matrix>; 2]| match pair {
+            [Some(m1), Some(m2)] => Some(&m1.scale(g) + &m2.scale(1.0 - g)),
+            [Some(m), None] | [None, Some(m)] => Some(m.clone()),
+            [None, None] => None,
+        };
+        let color_matrix = mix(&self.color_matrix).unwrap();
+        let camera_calibration = mix(&self.camera_calibration).unwrap_or_else(|| Matrix::identity(self.planes));
+        let xyz_to_camera = Matrix::diagonal(&self.analog_balance) * camera_calibration.clone() * color_matrix;
+        Interpolated {
+            xyz_to_camera,
+            camera_calibration,
+            forward_matrix: mix(&self.forward_matrix),
+            reduction_matrix: mix(&self.reduction_matrix),
+        }
+    }
+
+    /// The white that a camera neutral corresponds to. The matrices depend on the white, so this
+    /// iterates until the white stops changing, see DNG spec 1.6 P88.
+    pub fn neutral_to_xy(&self, neutral: &[f64]) -> (f64, f64) {
+        const MAX_PASSES: usize = 30;
+        let mut last = D50;
+        for pass in 0..MAX_PASSES {
+            let interpolated = self.interpolate(last);
+            let camera_to_xyz = interpolated.xyz_to_camera.inverse_with(interpolated.reduction_matrix.as_ref());
+            let mut next = xyz_to_xy(&camera_to_xyz.apply(neutral));
+            if (next.0 - last.0).abs() + (next.1 - last.1).abs() < 1e-7 {
+                return next;
+            }
+            // not converging usually means oscillating between two values, so settle on the average
+            if pass == MAX_PASSES - 1 {
+                next = ((last.0 + next.0) / 2.0, (last.1 + next.1) / 2.0);
+            }
+            last = next;
+        }
+        last
+    }
+
+    /// The camera neutral of a white, normalized so the largest plane is 1.0.
+    pub fn xy_to_neutral(&self, white: (f64, f64)) -> Vec<f64> {
+        let neutral = self.interpolate(white).xyz_to_camera.apply(&xy_to_xyz(white));
+        let max = neutral.iter().cloned().fold(f64::MIN, f64::max);
+        neutral.iter().map(|n| n / max).collect()
+    }
+
+    /// The transform from camera values to XYZ D50 when the scene is lit by `white`.
+    pub fn color_spec(&self, white: (f64, f64)) -> ColorSpec {
+        let interpolated = self.interpolate(white);
+        let camera_white = self.xy_to_neutral(white);
+
+        let camera_to_xyz = match &interpolated.forward_matrix {
+            Some(forward_matrix) => {
+                let individual_to_reference = (Matrix::diagonal(&self.analog_balance) * interpolated.camera_calibration).inverse();
+                let reference_white = individual_to_reference.apply(&camera_white);
+                forward_matrix * &(Matrix::diagonal(&reference_white).inverse() * individual_to_reference)
+            },
+            None => {
+                // scaled so the D50 white is only reached when the first plane saturates
+                let xyz_to_camera = &interpolated.xyz_to_camera * &adaptation_matrix(D50, white);
+                let scale = xyz_to_camera.apply(&xy_to_xyz(D50)).iter().cloned().fold(f64::MIN, f64::max);
+                xyz_to_camera.scale(1.0 / scale).inverse_with(interpolated.reduction_matrix.as_ref())
+            },
+        };
+
+        ColorSpec { white, camera_white, camera_to_xyz }
+    }
+}
+
+// Scales the rows so a camera value of all ones maps to the D50 white, see DNG spec 1.6 ForwardMatrix1 P72
+fn normalize_forward_matrix(matrix: &Matrix) -> Matrix {
+    let xyz = matrix.apply(&vec![1.0; matrix.cols]);
+    let d50 = xy_to_xyz(D50);
+    let scales = (0..3).map(|i| if xyz[i] != 0.0 { d50[i] / xyz[i] } else { 1.0 }).collect::<Vec<f64>>();
+    &Matrix::diagonal(&scales) * matrix
+}
+
+/// The color calibration evaluated for a particular white.
+#[derive(Clone, Debug)]
+pub struct ColorSpec {
+    pub white: (f64, f64),
+    // the camera neutral of the white, the largest plane is 1.0
+    pub camera_white: Vec<f64>,
+    // 3 x N, white balanced and chromatically adapted to D50
+    pub camera_to_xyz: Matrix,
+}
+
+impl ColorSpec {
+    pub fn camera_to_rgb(&self, space: ColorSpace) -> Matrix {
+        &space.xyz_to_rgb() * &self.camera_to_xyz
+    }
+}
+
+/// The RGB spaces that images can be rendered into, all with linear values until they are encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    AdobeRgb,
+    DisplayP3,
+    Rec2020,
+    ProPhoto,
+}
+
+impl ColorSpace {
+    // red, green, blue and white chromaticities
+    fn primaries(&self) -> [(f64, f64); 4] {
+        use ColorSpace::*;
+        match self {
+            Srgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+            AdobeRgb => [(0.64, 0.33), (0.21, 0.71), (0.15, 0.06), D65],
+            DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060), D65],
+            Rec2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046), D65],
+            ProPhoto => [(0.7347, 0.2653), (0.1596, 0.8404), (0.0366, 0.0001), D50],
+        }
+    }
+
+    /// The space's RGB to XYZ D50 matrix, Bradford adapted from the space's own white.
+    pub fn rgb_to_xyz(&self) -> Matrix {
+        let [red, green, blue, white] = self.primaries();
+        let columns = [red, green, blue].map(xy_to_xyz);
+        let primaries = Matrix::new(3, 3, (0..3).flat_map(|row| columns.iter().map(move |c| c[row])).collect());
+        let scales = primaries.inverse().apply(&xy_to_xyz(white));
+        adaptation_matrix(white, D50) * primaries * Matrix::diagonal(&scales)
+    }
+
+    pub fn xyz_to_rgb(&self) -> Matrix {
+        self.rgb_to_xyz().inverse()
+    }
+
+    /// The space's transfer function, from linear values to encoded ones.
+    pub fn encode(&self, linear: f32) -> f32 {
+        use ColorSpace::*;
+        let v = linear.clamp(0.0, 1.0);
+        match self {
+            Srgb | DisplayP3 => if v <= 0.0031308 { 12.92 * v } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 },
+            AdobeRgb => v.powf(256.0 / 563.0),
+            Rec2020 => if v < 0.018053968 { 4.5 * v } else { 1.0993 * v.powf(0.45) - 0.0993 },
+            ProPhoto => if v < 1.0 / 512.0 { 16.0 * v } else { v.powf(1.0 / 1.8) },
+        }
+    }
+}
+
+impl LinearImage {
+    /// Multiplies every pixel by a matrix, which needs as many columns as the image has planes.
+    pub fn transform(&self, matrix: &Matrix) -> LinearImage {
+        assert_eq!(matrix.cols, self.planes, "The matrix doesn't match the image's planes!");
+        let mut transformed = LinearImage::new(self.width, self.height, matrix.rows);
+        for (pixel, out) in self.data.chunks(self.planes).zip(transformed.data.chunks_mut(matrix.rows)) {
+            let pixel = pixel.iter().map(|&v| v as f64).collect::<Vec<f64>>();
+            for (o, v) in out.iter_mut().zip(matrix.apply(&pixel)) {
+                *o = v as f32;
+            }
+        }
+        transformed
+    }
+}
+
+/// Camera values to XYZ D50.
+pub fn camera_to_xyz(image: &LinearImage, spec: &ColorSpec) -> LinearImage {
+    image.transform(&spec.camera_to_xyz)
+}
+
+/// Camera values to linear RGB in one of the output spaces.
+pub fn camera_to_rgb(image: &LinearImage, spec: &ColorSpec, space: ColorSpace) -> LinearImage {
+    image.transform(&spec.camera_to_rgb(space))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    #[test]
+    fn temperature_round_trip() {
+        let (temperature, tint) = xy_to_temperature(D65);
+        assert!(close(temperature, 6504.0, 10.0));
+
+        let (x, y) = temperature_to_xy(temperature, tint);
+        assert!(close(x, D65.0, 1e-4) && close(y, D65.1, 1e-4));
+    }
+
+    #[test]
+    fn srgb_white() {
+        let rgb = ColorSpace::Srgb.xyz_to_rgb().apply(&xy_to_xyz(D50));
+
+        assert!(rgb.iter().all(|&v| close(v, 1.0, 1e-6)));
+    }
+
+    #[test]
+    fn neutral_maps_to_d50() {
+        // a made up camera that sees XYZ with a bit of a blue cast
+        let calibration = ColorCalibration {
+            planes: 3,
+            calibration_illuminant: [21, 0],
+            color_matrix: [Some(Matrix::new(3, 3, vec![0.9, 0.1, 0.0, 0.1, 0.8, 0.1, 0.0, 0.2, 1.2])), None],
+            camera_calibration: [None, None],
+            forward_matrix: [None, None],
+            reduction_matrix: [None, None],
+            analog_balance: vec![1.0; 3],
+        };
+        let neutral = calibration.xy_to_neutral(D65);
+        let spec = calibration.color_spec(calibration.neutral_to_xy(&neutral));
+
+        let xyz = spec.camera_to_xyz.apply(&neutral);
+        let (x, y) = xyz_to_xy(&xyz);
+        assert!(close(x, D50.0, 1e-6) && close(y, D50.1, 1e-6));
+    }
+}
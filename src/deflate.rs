@@ -0,0 +1,205 @@
+// See RFC 1950 (zlib) and RFC 1951 (Deflate)
+// Adobe Photoshop TIFF Technical Notes P3 and DNG spec 1.6 Compression P20 use this for compression = 8
+
+// RFC 1951 3.2.5, the base lengths and distances of each code and how many extra bits follow it
+const LENGTH_BASES: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASES: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// RFC 1951 3.2.7, the order the code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// RFC 1951 3.2.2, a canonical Huffman code from its code lengths
+struct HuffmanTable {
+    // how many codes there are of each length
+    counts: [u16; 16],
+    // the symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl HuffmanTable {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+}
+
+// Deflate packs bits starting at the least significant bit of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bits: u32,
+    bit_count: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read_bits(&mut self, count: usize) -> u32 {
+        while self.bit_count < count {
+            let byte = *self.data.get(self.position).expect("The Deflate data ended early!");
+            self.bits |= (byte as u32) << self.bit_count;
+            self.position += 1;
+            self.bit_count += 8;
+        }
+        let value = self.bits & ((1u64 << count) - 1) as u32;
+        self.bits >>= count;
+        self.bit_count -= count;
+        value
+    }
+
+    // Huffman codes are packed starting with their most significant bit, see RFC 1951 3.1.1
+    fn decode(&mut self, table: &HuffmanTable) -> u16 {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for length in 1..16 {
+            code |= self.read_bits(1) as i32;
+            let count = table.counts[length] as i32;
+            if code - first < count {
+                return table.symbols[(index + code - first) as usize];
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        panic!("Invalid Huffman code in the Deflate data!")
+    }
+
+    fn align(&mut self) {
+        self.bits = 0;
+        self.bit_count = 0;
+    }
+}
+
+// RFC 1951 3.2.6
+fn fixed_tables() -> (HuffmanTable, HuffmanTable) {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (HuffmanTable::new(&lengths), HuffmanTable::new(&[5; 30]))
+}
+
+// RFC 1951 3.2.7
+fn dynamic_tables(reader: &mut BitReader) -> (HuffmanTable, HuffmanTable) {
+    let literal_count = reader.read_bits(5) as usize + 257;
+    let distance_count = reader.read_bits(5) as usize + 1;
+    let code_length_count = reader.read_bits(4) as usize + 4;
+    let mut code_length_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_length_lengths[i] = reader.read_bits(3) as u8;
+    }
+    let code_lengths = HuffmanTable::new(&code_length_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        match reader.decode(&code_lengths) {
+            symbol @ 0..=15 => lengths.push(symbol as u8),
+            16 => {
+                let previous = *lengths.last().expect("The Deflate data repeats a code length before the first one!");
+                let repeat = 3 + reader.read_bits(2) as usize;
+                lengths.extend(std::iter::repeat_n(previous, repeat));
+            },
+            17 => {
+                let repeat = 3 + reader.read_bits(3) as usize;
+                lengths.extend(std::iter::repeat_n(0, repeat));
+            },
+            _ => {
+                let repeat = 11 + reader.read_bits(7) as usize;
+                lengths.extend(std::iter::repeat_n(0, repeat));
+            },
+        }
+    }
+    (HuffmanTable::new(&lengths[..literal_count]), HuffmanTable::new(&lengths[literal_count..]))
+}
+
+/// Decompresses zlib wrapped Deflate data.
+pub(crate) fn inflate(data: &[u8]) -> Vec<u8> {
+    // RFC 1950 2.2, compression method 8 and a header that's a multiple of 31
+    assert!(data.len() > 2 && data[0] & 0x0F == 8 && (((data[0] as u16) << 8) | data[1] as u16).is_multiple_of(31), "The data isn't zlib wrapped Deflate data!");
+    assert_eq!(data[1] & 0x20, 0, "Deflate data with a preset dictionary isn't supported!");
+
+    let mut reader = BitReader { data, position: 2, bits: 0, bit_count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.read_bits(1) == 1;
+        match reader.read_bits(2) {
+            // RFC 1951 3.2.4, a stored block starts on a byte boundary with its length and its complement
+            0 => {
+                reader.align();
+                let p = reader.position;
+                let length = u16::from_le_bytes([data[p], data[p + 1]]) as usize;
+                assert_eq!(length as u16, !u16::from_le_bytes([data[p + 2], data[p + 3]]), "The Deflate stored block length is corrupt!");
+                out.extend(&data[p + 4..p + 4 + length]);
+                reader.position = p + 4 + length;
+            },
+            block_type @ (1 | 2) => {
+                let (literals, distances) = if block_type == 1 { fixed_tables() } else { dynamic_tables(&mut reader) };
+                loop {
+                    let symbol = reader.decode(&literals) as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let length = LENGTH_BASES[symbol - 257] as usize + reader.read_bits(LENGTH_EXTRA[symbol - 257] as usize) as usize;
+                    let code = reader.decode(&distances) as usize;
+                    let distance = DISTANCE_BASES[code] as usize + reader.read_bits(DISTANCE_EXTRA[code] as usize) as usize;
+                    assert!(distance <= out.len(), "The Deflate data refers back past its start!");
+                    // the copy can overlap what it's writing, e.g. a distance of 1 repeats a byte
+                    let start = out.len() - distance;
+                    for i in 0..length {
+                        out.push(out[start + i]);
+                    }
+                }
+            },
+            _ => panic!("Invalid Deflate block type!"),
+        }
+        if last {
+            return out;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_types() {
+        // zlib.compress(data, level) from Python, level 0 gives a stored block and the short text a
+        // fixed Huffman block
+        let stored = [0x78, 0x01, 0x01, 0x12, 0x00, 0xED, 0xFF, 0x68, 0x65, 0x6C, 0x6C, 0x6F, 0x20, 0x68, 0x65, 0x6C, 0x6C, 0x6F, 0x20, 0x68, 0x65, 0x6C, 0x6C, 0x6F, 0x21, 0x40, 0xCC, 0x06, 0x9E];
+        let fixed = [0x78, 0xDA, 0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x90, 0x8A, 0x00, 0x40, 0xCC, 0x06, 0x9E];
+        assert_eq!(inflate(&stored), b"hello hello hello!");
+        assert_eq!(inflate(&fixed), b"hello hello hello!");
+    }
+
+    #[test]
+    fn dynamic_block() {
+        let text = "a dynamic block needs enough text with skewed letter frequencies, so zlib builds its own codes. ".repeat(2);
+        let compressed = [
+            0x78, 0xDA, 0xB5, 0xCC, 0x8B, 0x0D, 0x83, 0x30, 0x0C, 0x05, 0xC0, 0x55, 0xDE, 0x00, 0xA8, 0x3B, 0xE5, 0xF3,
+            0x00, 0x8B, 0xD4, 0x16, 0xB1, 0xA3, 0x40, 0xA7, 0x2F, 0x4B, 0x30, 0xC0, 0x5D, 0x42, 0xBD, 0x35, 0x7D, 0xA5,
+            0x20, 0x37, 0x2B, 0x07, 0x94, 0xAC, 0x0E, 0xAA, 0x8D, 0x6D, 0x47, 0xF0, 0x0A, 0x4C, 0x89, 0x1D, 0x7E, 0x70,
+            0xB2, 0xA2, 0x31, 0x82, 0x1D, 0x6B, 0xE7, 0x39, 0xA8, 0x45, 0xE8, 0x0B, 0xDC, 0xF0, 0x6B, 0x92, 0x91, 0x87,
+            0xB4, 0x87, 0x4A, 0x38, 0x6C, 0x2A, 0x8A, 0x55, 0xFA, 0x07, 0xE9, 0xE5, 0xFF, 0x0F, 0xB1, 0x66, 0x46, 0x6D,
+        ];
+        assert_eq!(inflate(&compressed), text.as_bytes());
+    }
+}
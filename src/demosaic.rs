@@ -0,0 +1,404 @@
+use crate::{cfa::CfaPattern, linearize::LinearImage};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DemosaicAlgorithm {
+    // averages the closest samples of each plane, works with any pattern
+    Bilinear,
+    // Hirakawa and Parks' adaptive homogeneity-directed interpolation, Bayer patterns only
+    Ahd,
+    // a single pass of Markesteijn's directional interpolation, X-Trans patterns only
+    Markesteijn,
+}
+
+impl DemosaicAlgorithm {
+    /// The best algorithm available for a pattern.
+    pub fn best_for(cfa: &CfaPattern) -> Self {
+        if cfa.is_bayer() {
+            DemosaicAlgorithm::Ahd
+        } else if cfa.is_xtrans() {
+            DemosaicAlgorithm::Markesteijn
+        } else {
+            DemosaicAlgorithm::Bilinear
+        }
+    }
+}
+
+/// Turns a single plane CFA image into one with a plane per CFA color (camera RGB for the usual
+/// patterns). The pattern has to start at the top left of the mosaic.
+pub fn demosaic(mosaic: &LinearImage, cfa: &CfaPattern, algorithm: DemosaicAlgorithm) -> LinearImage {
+    assert_eq!(mosaic.planes, 1, "Only single plane (CFA) images can be demosaiced!");
+    match algorithm {
+        DemosaicAlgorithm::Bilinear => bilinear(mosaic, cfa),
+        DemosaicAlgorithm::Ahd => {
+            assert!(cfa.is_bayer(), "AHD only works with Bayer patterns!");
+            ahd(mosaic, cfa)
+        },
+        DemosaicAlgorithm::Markesteijn => {
+            assert!(cfa.is_xtrans(), "Markesteijn only works with X-Trans patterns!");
+            markesteijn(mosaic, cfa)
+        },
+    }
+}
+
+// The planes of the mosaic's samples, so the pattern only needs to be looked up once
+fn plane_map(mosaic: &LinearImage, cfa: &CfaPattern) -> Vec<usize> {
+    let mut planes = Vec::with_capacity(mosaic.width * mosaic.height);
+    for row in 0..mosaic.height {
+        for col in 0..mosaic.width {
+            planes.push(cfa.plane_at(row, col));
+        }
+    }
+    planes
+}
+
+fn bilinear(mosaic: &LinearImage, cfa: &CfaPattern) -> LinearImage {
+    let (width, height) = (mosaic.width as isize, mosaic.height as isize);
+    let planes = plane_map(mosaic, cfa);
+    let max_radius = cfa.rows.max(cfa.cols) as isize;
+    let mut rgb = LinearImage::new(mosaic.width, mosaic.height, cfa.planes());
+
+    for row in 0..height {
+        for col in 0..width {
+            let index = (row * width + col) as usize;
+            for plane in 0..cfa.planes() {
+                if planes[index] == plane {
+                    rgb.data[index * cfa.planes() + plane] = mosaic.data[index];
+                    continue;
+                }
+                // grow the neighborhood until it has samples of this plane, 3x3 is enough for Bayer
+                for radius in 1..=max_radius {
+                    let mut sum = 0.0;
+                    let mut count = 0;
+                    for r in (row - radius).max(0)..=(row + radius).min(height - 1) {
+                        for c in (col - radius).max(0)..=(col + radius).min(width - 1) {
+                            let neighbor = (r * width + c) as usize;
+                            if planes[neighbor] == plane {
+                                sum += mosaic.data[neighbor];
+                                count += 1;
+                            }
+                        }
+                    }
+                    if count > 0 {
+                        rgb.data[index * cfa.planes() + plane] = sum / count as f32;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    rgb
+}
+
+// Everything after green is interpolated from color differences, so each direction gets its own
+// full color image: a plane's value is the green plus the average difference of the 3x3 (or 5x5)
+// neighbors that sampled that plane
+fn fill_from_green(mosaic: &LinearImage, planes: &[usize], green: &[f32], radius: isize) -> Vec<[f32; 3]> {
+    let (width, height) = (mosaic.width as isize, mosaic.height as isize);
+    let mut rgb = vec![[0.0f32; 3]; green.len()];
+    for row in 0..height {
+        for col in 0..width {
+            let index = (row * width + col) as usize;
+            rgb[index][1] = green[index];
+            for plane in [0, 2] {
+                if planes[index] == plane {
+                    rgb[index][plane] = mosaic.data[index];
+                    continue;
+                }
+                let mut sum = 0.0;
+                let mut weights = 0.0;
+                for r in (row - radius).max(0)..=(row + radius).min(height - 1) {
+                    for c in (col - radius).max(0)..=(col + radius).min(width - 1) {
+                        let neighbor = (r * width + c) as usize;
+                        if planes[neighbor] == plane {
+                            let weight = 1.0 / ((r - row).pow(2) + (c - col).pow(2)) as f32;
+                            sum += (mosaic.data[neighbor] - green[neighbor]) * weight;
+                            weights += weight;
+                        }
+                    }
+                }
+                rgb[index][plane] = if weights > 0.0 { green[index] + sum / weights } else { green[index] };
+            }
+        }
+    }
+    rgb
+}
+
+// A rough CIELab, the camera's colors are treated as sRGB since all that matters is how similar
+// neighboring pixels are
+fn to_lab(rgb: &[f32; 3]) -> [f32; 3] {
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let x = (0.4124 * rgb[0] + 0.3576 * rgb[1] + 0.1805 * rgb[2]) / 0.95047;
+    let y = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+    let z = (0.0193 * rgb[0] + 0.1192 * rgb[1] + 0.9505 * rgb[2]) / 1.08883;
+    let (fx, fy, fz) = (f(x.max(0.0)), f(y.max(0.0)), f(z.max(0.0)));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn ahd(mosaic: &LinearImage, cfa: &CfaPattern) -> LinearImage {
+    let mut output = bilinear(mosaic, cfa);
+    let (width, height) = (mosaic.width, mosaic.height);
+    if width < 8 || height < 8 {
+        return output;
+    }
+    let planes = plane_map(mosaic, cfa);
+    let m = &mosaic.data;
+
+    // green along rows and along columns, with a second order correction from the sample's own
+    // color that's clamped to the neighboring greens
+    let mut green_h = m.clone();
+    let mut green_v = m.clone();
+    for row in 2..height - 2 {
+        for col in 2..width - 2 {
+            let i = row * width + col;
+            if planes[i] == 1 {
+                continue;
+            }
+            let interpolate = |a: usize, b: usize, far_a: usize, far_b: usize| {
+                let estimate = (m[a] + m[b]) / 2.0 + (2.0 * m[i] - m[far_a] - m[far_b]) / 4.0;
+                estimate.clamp(m[a].min(m[b]), m[a].max(m[b]))
+            };
+            green_h[i] = interpolate(i - 1, i + 1, i - 2, i + 2);
+            green_v[i] = interpolate(i - width, i + width, i - 2 * width, i + 2 * width);
+        }
+    }
+
+    let rgb = [fill_from_green(mosaic, &planes, &green_h, 1), fill_from_green(mosaic, &planes, &green_v, 1)];
+    let lab = [
+        rgb[0].iter().map(to_lab).collect::<Vec<[f32; 3]>>(),
+        rgb[1].iter().map(to_lab).collect::<Vec<[f32; 3]>>(),
+    ];
+
+    // how many of a pixel's 4 neighbors are within the luminance and chrominance differences that
+    // can be expected without crossing an edge
+    let mut homogeneity = [vec![0u8; width * height], vec![0u8; width * height]];
+    for row in 3..height - 3 {
+        for col in 3..width - 3 {
+            let i = row * width + col;
+            let neighbors = [i - 1, i + 1, i - width, i + width];
+            let mut l_diff = [[0.0f32; 4]; 2];
+            let mut ab_diff = [[0.0f32; 4]; 2];
+            for d in 0..2 {
+                for (k, &n) in neighbors.iter().enumerate() {
+                    l_diff[d][k] = (lab[d][i][0] - lab[d][n][0]).abs();
+                    ab_diff[d][k] = (lab[d][i][1] - lab[d][n][1]).powi(2) + (lab[d][i][2] - lab[d][n][2]).powi(2);
+                }
+            }
+            let l_epsilon = l_diff[0][0].max(l_diff[0][1]).min(l_diff[1][2].max(l_diff[1][3]));
+            let ab_epsilon = ab_diff[0][0].max(ab_diff[0][1]).min(ab_diff[1][2].max(ab_diff[1][3]));
+            for d in 0..2 {
+                homogeneity[d][i] = (0..4).filter(|&k| l_diff[d][k] <= l_epsilon && ab_diff[d][k] <= ab_epsilon).count() as u8;
+            }
+        }
+    }
+
+    for row in 4..height - 4 {
+        for col in 4..width - 4 {
+            let i = row * width + col;
+            let mut scores = [0u32; 2];
+            for (d, score) in scores.iter_mut().enumerate() {
+                for r in row - 1..=row + 1 {
+                    for c in col - 1..=col + 1 {
+                        *score += homogeneity[d][r * width + c] as u32;
+                    }
+                }
+            }
+            for (plane, value) in output.data[i * 3..i * 3 + 3].iter_mut().enumerate() {
+                *value = match scores[0].cmp(&scores[1]) {
+                    std::cmp::Ordering::Greater => rgb[0][i][plane],
+                    std::cmp::Ordering::Less => rgb[1][i][plane],
+                    std::cmp::Ordering::Equal => (rgb[0][i][plane] + rgb[1][i][plane]) / 2.0,
+                };
+            }
+        }
+    }
+    output
+}
+
+fn to_ypbpr(rgb: &[f32; 3]) -> [f32; 3] {
+    let y = 0.2627 * rgb[0] + 0.678 * rgb[1] + 0.0593 * rgb[2];
+    [y, (rgb[2] - y) * 0.56433, (rgb[0] - y) * 0.67815]
+}
+
+fn markesteijn(mosaic: &LinearImage, cfa: &CfaPattern) -> LinearImage {
+    const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+    let mut output = bilinear(mosaic, cfa);
+    let (width, height) = (mosaic.width as isize, mosaic.height as isize);
+    if width < 12 || height < 12 {
+        return output;
+    }
+    let planes = plane_map(mosaic, cfa);
+    let m = &mosaic.data;
+    let fallback_green = output.data.iter().skip(1).step_by(3).cloned().collect::<Vec<f32>>();
+    let at = |row: isize, col: isize| (row * width + col) as usize;
+
+    // green along each direction from the closest green sample on either side, X-Trans always has
+    // one within two pixels for most directions
+    let mut rgb = Vec::with_capacity(DIRECTIONS.len());
+    for &(dr, dc) in &DIRECTIONS {
+        let mut green = m.clone();
+        for row in 2..height - 2 {
+            for col in 2..width - 2 {
+                let i = at(row, col);
+                if planes[i] == 1 {
+                    continue;
+                }
+                let closest = |sign: isize| (1..=2).find(|s| planes[at(row + sign * s * dr, col + sign * s * dc)] == 1)
+                    .map(|s| (s as f32, m[at(row + sign * s * dr, col + sign * s * dc)]));
+                green[i] = match (closest(1), closest(-1)) {
+                    (Some((d1, g1)), Some((d2, g2))) => (g1 * d2 + g2 * d1) / (d1 + d2),
+                    (Some((_, g)), None) | (None, Some((_, g))) => g,
+                    (None, None) => fallback_green[i],
+                };
+            }
+        }
+        rgb.push(fill_from_green(mosaic, &planes, &green, 2));
+    }
+
+    // how much each direction's result changes along that direction
+    let ypbpr = rgb.iter().map(|image| image.iter().map(to_ypbpr).collect::<Vec<[f32; 3]>>()).collect::<Vec<Vec<[f32; 3]>>>();
+    let mut derivatives = vec![vec![f32::MAX; m.len()]; DIRECTIONS.len()];
+    for (d, &(dr, dc)) in DIRECTIONS.iter().enumerate() {
+        for row in 3..height - 3 {
+            for col in 3..width - 3 {
+                let i = at(row, col);
+                let mut sum = 0.0;
+                for n in [at(row + dr, col + dc), at(row - dr, col - dc)] {
+                    sum += (0..3).map(|k| (ypbpr[d][i][k] - ypbpr[d][n][k]).powi(2)).sum::<f32>();
+                }
+                derivatives[d][i] = sum;
+            }
+        }
+    }
+
+    let mut homogeneity = vec![vec![0u8; m.len()]; DIRECTIONS.len()];
+    for row in 4..height - 4 {
+        for col in 4..width - 4 {
+            let i = at(row, col);
+            let threshold = 8.0 * (0..DIRECTIONS.len()).map(|d| derivatives[d][i]).fold(f32::MAX, f32::min);
+            for d in 0..DIRECTIONS.len() {
+                let mut count = 0;
+                for r in row - 1..=row + 1 {
+                    for c in col - 1..=col + 1 {
+                        if derivatives[d][at(r, c)] <= threshold {
+                            count += 1;
+                        }
+                    }
+                }
+                homogeneity[d][i] = count;
+            }
+        }
+    }
+
+    // average every direction that's nearly as homogeneous as the best one over a 5x5 window
+    for row in 6..height - 6 {
+        for col in 6..width - 6 {
+            let i = at(row, col);
+            let mut scores = [0u32; 4];
+            for (d, score) in scores.iter_mut().enumerate() {
+                for r in row - 2..=row + 2 {
+                    for c in col - 2..=col + 2 {
+                        *score += homogeneity[d][at(r, c)] as u32;
+                    }
+                }
+            }
+            let best = *scores.iter().max().unwrap();
+            let threshold = best - best / 8;
+            let chosen = (0..DIRECTIONS.len()).filter(|&d| scores[d] >= threshold).collect::<Vec<usize>>();
+            for (plane, value) in output.data[i * 3..i * 3 + 3].iter_mut().enumerate() {
+                *value = chosen.iter().map(|&d| rgb[d][i][plane]).sum::<f32>() / chosen.len() as f32;
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat_mosaic(cfa: &CfaPattern, size: usize, color: [f32; 3]) -> LinearImage {
+        let mut mosaic = LinearImage::new(size, size, 1);
+        for row in 0..size {
+            for col in 0..size {
+                mosaic.set(row, col, 0, color[cfa.plane_at(row, col)]);
+            }
+        }
+        mosaic
+    }
+
+    fn assert_flat(rgb: &LinearImage, color: [f32; 3]) {
+        for pixel in rgb.data.chunks(3) {
+            for plane in 0..3 {
+                assert!((pixel[plane] - color[plane]).abs() < 1e-5, "{:?} isn't {:?}", pixel, color);
+            }
+        }
+    }
+
+    // Red rises to the right, blue falls downwards and green stays put, so a swapped plane or a pattern
+    // read a pixel out of phase puts the wrong values in every pixel
+    fn gradient(row: usize, col: usize) -> [f32; 3] {
+        [0.1 + 0.02 * col as f32, 0.5, 0.8 - 0.03 * row as f32]
+    }
+
+    fn gradient_mosaic(cfa: &CfaPattern, size: usize) -> LinearImage {
+        let mut mosaic = LinearImage::new(size, size, 1);
+        for row in 0..size {
+            for col in 0..size {
+                mosaic.set(row, col, 0, gradient(row, col)[cfa.plane_at(row, col)]);
+            }
+        }
+        mosaic
+    }
+
+    // Linear gradients come back exactly away from the edges, where the neighborhoods are symmetric
+    fn assert_gradient(rgb: &LinearImage, margin: usize) {
+        for row in margin..rgb.height - margin {
+            for col in margin..rgb.width - margin {
+                let expected = gradient(row, col);
+                for (plane, value) in expected.iter().enumerate() {
+                    let actual = rgb.get(row, col, plane);
+                    assert!((actual - value).abs() < 1e-4, "plane {} at ({}, {}) is {} rather than {}", plane, row, col, actual, value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flat_bayer() {
+        let cfa = CfaPattern::new(2, 2, vec![1, 0, 2, 1], vec![0, 1, 2]);
+        let color = [0.2, 0.5, 0.7];
+        let mosaic = flat_mosaic(&cfa, 16, color);
+
+        assert_flat(&demosaic(&mosaic, &cfa, DemosaicAlgorithm::Bilinear), color);
+        assert_flat(&demosaic(&mosaic, &cfa, DemosaicAlgorithm::Ahd), color);
+    }
+
+    #[test]
+    fn flat_xtrans() {
+        let pattern = vec![
+            1, 1, 0, 1, 1, 2,
+            1, 1, 2, 1, 1, 0,
+            2, 0, 1, 0, 2, 1,
+            1, 1, 2, 1, 1, 0,
+            1, 1, 0, 1, 1, 2,
+            0, 2, 1, 2, 0, 1,
+        ];
+        let cfa = CfaPattern::new(6, 6, pattern, vec![0, 1, 2]);
+        let color = [0.3, 0.6, 0.1];
+        let mosaic = flat_mosaic(&cfa, 24, color);
+
+        assert_flat(&demosaic(&mosaic, &cfa, DemosaicAlgorithm::Markesteijn), color);
+    }
+
+    #[test]
+    fn gradient_bayer() {
+        // every phase, from RGGB to BGGR
+        for pattern in [[0, 1, 1, 2], [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]] {
+            let cfa = CfaPattern::new(2, 2, pattern.to_vec(), vec![0, 1, 2]);
+            let mosaic = gradient_mosaic(&cfa, 16);
+
+            assert_gradient(&demosaic(&mosaic, &cfa, DemosaicAlgorithm::Bilinear), 1);
+            assert_gradient(&demosaic(&mosaic, &cfa, DemosaicAlgorithm::Ahd), 4);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{geometry::Geometry, linearize::LinearImage, raster::Raster, tags::Tag, Endian, IFD};
+
+/// How depth samples map to distances, DNG spec 1.5 DepthFormat.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthFormat {
+    Unknown,
+    // evenly spaced in distance between near and far
+    Linear,
+    // evenly spaced in inverse distance between near and far
+    Inverse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthUnits {
+    Unknown,
+    Meters,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthMeasureType {
+    Unknown,
+    // distance along the optical axis, like a z buffer
+    OpticalAxis,
+    // distance along the ray through the pixel
+    OpticalRay,
+}
+
+/// The depth tags from IFD 0 that describe the depth map (NewSubFileType = 8).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthInfo {
+    pub format: DepthFormat,
+    // None when unknown, far can be infinite
+    pub near: Option<f64>,
+    pub far: Option<f64>,
+    pub units: DepthUnits,
+    pub measure_type: DepthMeasureType,
+}
+
+impl DepthInfo {
+    pub(crate) fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian) -> Self {
+        let short = |tag: Tag| ifd.get_values(tag, buffer, endian).map(|v| v.to_value().to_u16()).unwrap_or(0);
+        // 0/0 means unknown
+        let distance = |tag: Tag| ifd.get_values(tag, buffer, endian).map(|v| v.to_value().to_f64()).filter(|d| !d.is_nan());
+        Self {
+            format: match short(Tag::DepthFormat_51177) {
+                1 => DepthFormat::Linear,
+                2 => DepthFormat::Inverse,
+                _ => DepthFormat::Unknown,
+            },
+            near: distance(Tag::DepthNear_51178),
+            far: distance(Tag::DepthFar_51179),
+            units: match short(Tag::DepthUnits_51180) {
+                1 => DepthUnits::Meters,
+                _ => DepthUnits::Unknown,
+            },
+            measure_type: match short(Tag::DepthMeasureType_51181) {
+                1 => DepthMeasureType::OpticalAxis,
+                2 => DepthMeasureType::OpticalRay,
+                _ => DepthMeasureType::Unknown,
+            },
+        }
+    }
+
+    /// The distance of a depth sample normalized to [0, 1], None without a known format, near and far.
+    pub fn distance(&self, normalized: f64) -> Option<f64> {
+        let (near, far) = (self.near?, self.far?);
+        match self.format {
+            DepthFormat::Linear => Some(near + normalized * (far - near)),
+            // written so an infinite far works
+            DepthFormat::Inverse => Some(1.0 / ((1.0 - normalized) / near + normalized / far)),
+            DepthFormat::Unknown => None,
+        }
+    }
+}
+
+/// A depth map's samples normalized to [0, 1].
+pub fn normalize_depth(raster: &Raster) -> LinearImage {
+    let max = ((1u64 << raster.bits_per_sample) - 1) as f32;
+    LinearImage {
+        width: raster.width,
+        height: raster.height,
+        planes: 1,
+        data: raster.data.iter().step_by(raster.samples_per_pixel).map(|&v| v as f32 / max).collect(),
+    }
+}
+
+/// Converts normalized depth samples to distances in DepthUnits.
+pub fn depth_to_distance(depth: &LinearImage, info: &DepthInfo) -> Option<LinearImage> {
+    let data = depth.data.iter().map(|&d| info.distance(d as f64).map(|z| z as f32)).collect::<Option<Vec<f32>>>()?;
+    Some(LinearImage { data, ..depth.clone() })
+}
+
+/// The depth map covers the main image's default crop, usually at a lower resolution. This resizes it
+/// to the default crop's size so it lines up pixel for pixel with a render.
+pub fn align_depth(depth: &LinearImage, geometry: &Geometry) -> LinearImage {
+    let crop = geometry.default_crop();
+    depth.resize_nearest(crop.width(), crop.height())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse_depth() {
+        let info = DepthInfo {
+            format: DepthFormat::Inverse,
+            near: Some(0.5),
+            far: Some(f64::INFINITY),
+            units: DepthUnits::Meters,
+            measure_type: DepthMeasureType::OpticalAxis,
+        };
+
+        assert_eq!(info.distance(0.0), Some(0.5));
+        assert_eq!(info.distance(0.5), Some(1.0));
+        assert_eq!(info.distance(1.0), Some(f64::INFINITY));
+        assert_eq!(DepthInfo { format: DepthFormat::Linear, far: Some(10.0), ..info }.distance(0.5), Some(5.25));
+    }
+}
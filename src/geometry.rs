@@ -0,0 +1,264 @@
+use crate::{linearize::LinearImage, orientation::Orientation, raster::Raster, tags::Tag, Endian, IFD};
+
+/// A rectangle in raw image pixel coordinates, bottom and right are exclusive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub top: usize,
+    pub left: usize,
+    pub bottom: usize,
+    pub right: usize,
+}
+
+impl Rect {
+    pub fn width(&self) -> usize {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> usize {
+        self.bottom - self.top
+    }
+
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        row >= self.top && row < self.bottom && col >= self.left && col < self.right
+    }
+
+    /// The rectangle in the coordinates of a width x height image whose top left is at `origin`
+    /// (top, left), clipped to it. For cropping after TrimBounds, see opcode::trim_origin.
+    pub fn relative_to(&self, origin: (usize, usize), width: usize, height: usize) -> Rect {
+        let top = self.top.saturating_sub(origin.0).min(height);
+        let left = self.left.saturating_sub(origin.1).min(width);
+        Rect {
+            top,
+            left,
+            bottom: self.bottom.saturating_sub(origin.0).clamp(top, height),
+            right: self.right.saturating_sub(origin.1).clamp(left, width),
+        }
+    }
+
+    // See DNG spec 1.6 ActiveArea P44, the four values are top, left, bottom, right
+    fn from_values(values: &[usize]) -> Self {
+        Self { top: values[0], left: values[1], bottom: values[2], right: values[3] }
+    }
+}
+
+/// Where the interesting parts of the raw image are and how big the final image should be.
+#[derive(Clone, Debug)]
+pub struct Geometry {
+    pub width: usize,
+    pub height: usize,
+    pub active_area: Rect,
+    pub masked_areas: Vec<Rect>,
+    // DefaultCropOrigin and DefaultCropSize are horizontal then vertical, relative to the active area
+    pub default_crop_origin: (f64, f64),
+    pub default_crop_size: (f64, f64),
+    pub default_scale: (f64, f64),
+    pub best_quality_scale: f64,
+    // top, left, bottom, right, relative to the default crop
+    pub default_user_crop: (f64, f64, f64, f64),
+}
+
+impl Geometry {
+    /// A raw image with only an active area, the default crop covers all of it and nothing is masked.
+    pub fn new(width: usize, height: usize, active_area: Rect) -> Self {
+        Self {
+            width,
+            height,
+            active_area,
+            masked_areas: Vec::new(),
+            default_crop_origin: (0.0, 0.0),
+            default_crop_size: (active_area.width() as f64, active_area.height() as f64),
+            default_scale: (1.0, 1.0),
+            best_quality_scale: 1.0,
+            default_user_crop: (0.0, 0.0, 1.0, 1.0),
+        }
+    }
+
+    pub(crate) fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian) -> Self {
+        let usizes = |tag: Tag| ifd.get_values(tag, buffer, endian).map(|v| v.to_vec().iter().map(|f| f.to_usize()).collect::<Vec<usize>>());
+        let f64s = |tag: Tag| ifd.get_values(tag, buffer, endian).map(|v| v.to_vec().iter().map(|f| f.to_f64()).collect::<Vec<f64>>());
+
+        let width = usizes(Tag::ImageWidth_256).expect("The IFD has no ImageWidth!")[0];
+        let height = usizes(Tag::ImageLength_257).expect("The IFD has no ImageLength!")[0];
+        let active_area = match usizes(Tag::ActiveArea_50829) {
+            Some(area) => Rect::from_values(&area),
+            None => Rect { top: 0, left: 0, bottom: height, right: width },
+        };
+        let masked_areas = match usizes(Tag::MaskedAreas_50830) {
+            Some(areas) => areas.chunks(4).map(Rect::from_values).collect(),
+            None => Vec::new(),
+        };
+        let pair = |values: Option<Vec<f64>>, default: (f64, f64)| match values {
+            Some(v) => (v[0], v[1]),
+            None => default,
+        };
+        let default_user_crop = match f64s(Tag::DefaultUserCrop_51125) {
+            Some(crop) => (crop[0], crop[1], crop[2], crop[3]),
+            None => (0.0, 0.0, 1.0, 1.0),
+        };
+
+        Self {
+            width,
+            height,
+            active_area,
+            masked_areas,
+            default_crop_origin: pair(f64s(Tag::DefaultCropOrigin_50719), (0.0, 0.0)),
+            default_crop_size: pair(f64s(Tag::DefaultCropSize_50720), (active_area.width() as f64, active_area.height() as f64)),
+            default_scale: pair(f64s(Tag::DefaultScale_50718), (1.0, 1.0)),
+            best_quality_scale: f64s(Tag::BestQualityScale_50780).map(|s| s[0]).unwrap_or(1.0),
+            default_user_crop,
+        }
+    }
+
+    /// The DefaultCrop rectangle in raw image coordinates, with fractional origins rounded.
+    pub fn default_crop(&self) -> Rect {
+        let top = self.active_area.top + self.default_crop_origin.1.round() as usize;
+        let left = self.active_area.left + self.default_crop_origin.0.round() as usize;
+        Rect {
+            top,
+            left,
+            bottom: (top + self.default_crop_size.1.round() as usize).min(self.active_area.bottom),
+            right: (left + self.default_crop_size.0.round() as usize).min(self.active_area.right),
+        }
+    }
+
+    /// The DefaultCrop rectangle relative to the top left of the active area.
+    pub fn default_crop_in_active_area(&self) -> Rect {
+        let crop = self.default_crop();
+        let active = self.active_area;
+        Rect { top: crop.top - active.top, left: crop.left - active.left, bottom: crop.bottom - active.top, right: crop.right - active.left }
+    }
+
+    /// The DefaultUserCrop applied on top of the DefaultCrop, in raw image coordinates.
+    pub fn user_crop(&self) -> Rect {
+        let crop = self.default_crop();
+        let (top, left, bottom, right) = self.default_user_crop;
+        let rows = crop.height() as f64;
+        let cols = crop.width() as f64;
+        Rect {
+            top: crop.top + (top * rows).round() as usize,
+            left: crop.left + (left * cols).round() as usize,
+            bottom: crop.top + (bottom * rows).round() as usize,
+            right: crop.left + (right * cols).round() as usize,
+        }
+    }
+
+    /// The DefaultCrop rectangle in the coordinates of the raw image once it's displayed the right way up.
+    pub fn display_default_crop(&self, orientation: Orientation) -> Rect {
+        orientation.map_rect(&self.default_crop(), self.width, self.height)
+    }
+
+    /// The width and height of the final image, DefaultScale stretches non-square pixels back to square
+    /// ones, see DNG spec 1.6 DefaultScale P46.
+    pub fn default_final_size(&self) -> (usize, usize) {
+        (
+            (self.default_crop_size.0 * self.default_scale.0).round() as usize,
+            (self.default_crop_size.1 * self.default_scale.1).round() as usize,
+        )
+    }
+
+    // DNG spec 1.6 BestQualityScale P57
+    pub fn best_quality_final_size(&self) -> (usize, usize) {
+        (
+            (self.default_crop_size.0 * self.default_scale.0 * self.best_quality_scale).round() as usize,
+            (self.default_crop_size.1 * self.default_scale.1 * self.best_quality_scale).round() as usize,
+        )
+    }
+}
+
+impl Raster {
+    pub fn crop(&self, rect: &Rect) -> Raster {
+        let spp = self.samples_per_pixel;
+        let mut data = Vec::with_capacity(rect.width() * rect.height() * spp);
+        for row in rect.top..rect.bottom {
+            let start = (row * self.width + rect.left) * spp;
+            data.extend_from_slice(&self.data[start..start + rect.width() * spp]);
+        }
+        Raster { width: rect.width(), height: rect.height(), samples_per_pixel: spp, bits_per_sample: self.bits_per_sample, data }
+    }
+}
+
+impl LinearImage {
+    pub fn crop(&self, rect: &Rect) -> LinearImage {
+        let planes = self.planes;
+        let mut data = Vec::with_capacity(rect.width() * rect.height() * planes);
+        for row in rect.top..rect.bottom {
+            let start = (row * self.width + rect.left) * planes;
+            data.extend_from_slice(&self.data[start..start + rect.width() * planes]);
+        }
+        LinearImage { width: rect.width(), height: rect.height(), planes, data }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_value, test_utils::{build_tiff, TestIfd, Value}};
+
+    // A 220x120 raw image with an 8 row / 10 column border outside the active area
+    fn read(ifd: TestIfd) -> Geometry {
+        let ifd = ifd
+            .tag(Tag::ImageWidth_256, Value::Short(vec![220]))
+            .tag(Tag::ImageLength_257, Value::Short(vec![120]))
+            .tag(Tag::ActiveArea_50829, Value::Long(vec![8, 10, 108, 210]))
+            .strips(vec![Vec::new()]);
+        let buffer = build_tiff(Endian::Little, ifd, Vec::new());
+        let ifd = IFD::parse_ifd(&buffer, get_value::long(&buffer, 4, &Endian::Little) as usize, &Endian::Little);
+        Geometry::read(&buffer, &ifd, &Endian::Little)
+    }
+
+    #[test]
+    fn default_crop_in_active_area() {
+        // the origin is horizontal then vertical and relative to the active area, 2.5 rounds up
+        let geometry = read(TestIfd::new()
+            .tag(Tag::DefaultCropOrigin_50719, Value::Rational(vec![(4, 1), (5, 2)]))
+            .tag(Tag::DefaultCropSize_50720, Value::Short(vec![180, 90])));
+
+        assert_eq!(geometry.active_area, Rect { top: 8, left: 10, bottom: 108, right: 210 });
+        assert_eq!(geometry.default_crop(), Rect { top: 11, left: 14, bottom: 101, right: 194 });
+        assert_eq!(geometry.default_crop_in_active_area(), Rect { top: 3, left: 4, bottom: 93, right: 184 });
+    }
+
+    #[test]
+    fn default_crop_is_clipped_to_the_active_area() {
+        let geometry = read(TestIfd::new()
+            .tag(Tag::DefaultCropOrigin_50719, Value::Short(vec![150, 60]))
+            .tag(Tag::DefaultCropSize_50720, Value::Short(vec![100, 100])));
+
+        assert_eq!(geometry.default_crop(), Rect { top: 68, left: 160, bottom: 108, right: 210 });
+
+        // without the tags the default crop is the whole active area
+        let whole = read(TestIfd::new());
+        assert_eq!(whole.default_crop(), whole.active_area);
+        assert_eq!(whole.default_final_size(), (200, 100));
+    }
+
+    #[test]
+    fn non_square_pixels() {
+        // pixels that are half as tall as they're wide get stretched vertically
+        let geometry = read(TestIfd::new()
+            .tag(Tag::DefaultCropSize_50720, Value::Short(vec![180, 90]))
+            .tag(Tag::DefaultScale_50718, Value::Rational(vec![(1, 1), (3, 2)]))
+            .tag(Tag::BestQualityScale_50780, Value::Rational(vec![(5, 4)])));
+
+        assert_eq!(geometry.default_final_size(), (180, 135));
+        assert_eq!(geometry.best_quality_final_size(), (225, 169));
+    }
+
+    #[test]
+    fn user_crop() {
+        // top, left, bottom, right as fractions of the default crop
+        let geometry = read(TestIfd::new()
+            .tag(Tag::DefaultCropOrigin_50719, Value::Short(vec![4, 2]))
+            .tag(Tag::DefaultCropSize_50720, Value::Short(vec![180, 90]))
+            .tag(Tag::DefaultUserCrop_51125, Value::Rational(vec![(1, 10), (1, 4), (9, 10), (3, 4)])));
+
+        assert_eq!(geometry.user_crop(), Rect { top: 19, left: 59, bottom: 91, right: 149 });
+    }
+
+    #[test]
+    fn relative_rect() {
+        let rect = Rect { top: 10, left: 4, bottom: 30, right: 50 };
+
+        assert_eq!(rect.relative_to((5, 6), 40, 20), Rect { top: 5, left: 0, bottom: 20, right: 40 });
+    }
+}
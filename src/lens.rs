@@ -0,0 +1,214 @@
+use crate::{
+    linearize::LinearImage,
+    opcode::{Rectilinear2Coefficients, RectilinearCoefficients},
+};
+
+// The image's optical center in pixels and the distance from it to the furthest corner, which the
+// lens opcodes normalize radii by, see DNG spec 1.6 WarpRectilinear P100
+fn center_and_scale(image: &LinearImage, center: (f64, f64)) -> ((f64, f64), f64) {
+    let cx = center.0 * (image.width - 1) as f64;
+    let cy = center.1 * (image.height - 1) as f64;
+    let dx = cx.max((image.width - 1) as f64 - cx);
+    let dy = cy.max((image.height - 1) as f64 - cy);
+    ((cx, cy), (dx * dx + dy * dy).sqrt().max(1.0))
+}
+
+// Catmull-Rom weights for a fractional offset
+fn cubic_weights(t: f64) -> [f64; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        (-t3 + 2.0 * t2 - t) / 2.0,
+        (3.0 * t3 - 5.0 * t2 + 2.0) / 2.0,
+        (-3.0 * t3 + 4.0 * t2 + t) / 2.0,
+        (t3 - t2) / 2.0,
+    ]
+}
+
+impl LinearImage {
+    /// Bicubic interpolation at a fractional position, clamped to the edges of the image.
+    pub fn sample(&self, row: f64, col: f64, plane: usize) -> f32 {
+        let r0 = row.floor();
+        let c0 = col.floor();
+        let wr = cubic_weights(row - r0);
+        let wc = cubic_weights(col - c0);
+        let clamp = |v: f64, max: usize| (v.max(0.0) as usize).min(max - 1);
+        let mut value = 0.0;
+        for (i, w_row) in wr.iter().enumerate() {
+            let r = clamp(r0 + i as f64 - 1.0, self.height);
+            for (j, w_col) in wc.iter().enumerate() {
+                let c = clamp(c0 + j as f64 - 1.0, self.width);
+                value += w_row * w_col * self.get(r, c, plane) as f64;
+            }
+        }
+        value as f32
+    }
+
+    // Fills every plane of the output by sampling where `source` says the ideal pixel came from
+    fn warp<F: Fn(usize, f64, f64) -> (f64, f64)>(&self, source: F) -> LinearImage {
+        let mut warped = LinearImage::new(self.width, self.height, self.planes);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                for plane in 0..self.planes {
+                    let (x, y) = source(plane, col as f64, row as f64);
+                    warped.set(row, col, plane, self.sample(y, x, plane));
+                }
+            }
+        }
+        warped
+    }
+}
+
+// With one set of coefficients it applies to every plane
+fn for_plane<T>(planes: &[T], plane: usize) -> &T {
+    &planes[plane.min(planes.len() - 1)]
+}
+
+/// WarpRectilinear, corrects radial and tangential distortion and lateral chromatic aberration of
+/// rectilinear lenses.
+pub fn warp_rectilinear(image: &LinearImage, planes: &[RectilinearCoefficients], center: (f64, f64)) -> LinearImage {
+    let ((cx, cy), m) = center_and_scale(image, center);
+    image.warp(|plane, x, y| {
+        let k = for_plane(planes, plane);
+        let dx = (x - cx) / m;
+        let dy = (y - cy) / m;
+        let r2 = dx * dx + dy * dy;
+        let f = k.radial[0] + r2 * (k.radial[1] + r2 * (k.radial[2] + r2 * k.radial[3]));
+        let [kt0, kt1] = k.tangential;
+        let tx = kt0 * 2.0 * dx * dy + kt1 * (r2 + 2.0 * dx * dx);
+        let ty = kt1 * 2.0 * dx * dy + kt0 * (r2 + 2.0 * dy * dy);
+        (cx + m * (dx * f + tx), cy + m * (dy * f + ty))
+    })
+}
+
+/// WarpRectilinear2 from DNG 1.6, a polynomial in r up to r^14 which can be reciprocal.
+pub fn warp_rectilinear2(image: &LinearImage, planes: &[Rectilinear2Coefficients], center: (f64, f64), reciprocal_radial: bool) -> LinearImage {
+    let ((cx, cy), m) = center_and_scale(image, center);
+    image.warp(|plane, x, y| {
+        let k = for_plane(planes, plane);
+        let dx = (x - cx) / m;
+        let dy = (y - cy) / m;
+        let r2 = dx * dx + dy * dy;
+        let r = r2.sqrt().clamp(k.min_valid_radius, k.max_valid_radius);
+        let mut f = k.radial.iter().rev().fold(0.0, |acc, c| acc * r + c);
+        if reciprocal_radial && f != 0.0 {
+            f = 1.0 / f;
+        }
+        let [kt0, kt1] = k.tangential;
+        let tx = kt0 * 2.0 * dx * dy + kt1 * (r2 + 2.0 * dx * dx);
+        let ty = kt1 * 2.0 * dx * dy + kt0 * (r2 + 2.0 * dy * dy);
+        (cx + m * (dx * f + tx), cy + m * (dy * f + ty))
+    })
+}
+
+/// WarpFisheye, maps a fisheye image to a rectilinear one.
+pub fn warp_fisheye(image: &LinearImage, planes: &[[f64; 4]], center: (f64, f64)) -> LinearImage {
+    let ((cx, cy), m) = center_and_scale(image, center);
+    image.warp(|plane, x, y| {
+        let k = for_plane(planes, plane);
+        let dx = (x - cx) / m;
+        let dy = (y - cy) / m;
+        let r = (dx * dx + dy * dy).sqrt();
+        if r == 0.0 {
+            return (cx, cy);
+        }
+        let t = r.atan();
+        let t2 = t * t;
+        let rd = t * (k[0] + t2 * (k[1] + t2 * (k[2] + t2 * k[3])));
+        (cx + m * dx * rd / r, cy + m * dy * rd / r)
+    })
+}
+
+/// FixVignetteRadial, a radial gain of 1 + k0 r^2 + k1 r^4 + ... + k4 r^10 on every plane.
+pub fn fix_vignette_radial(image: &LinearImage, k: &[f64; 5], center: (f64, f64)) -> LinearImage {
+    let ((cx, cy), m) = center_and_scale(image, center);
+    let mut fixed = image.clone();
+    for row in 0..image.height {
+        let dy = (row as f64 - cy) / m;
+        for col in 0..image.width {
+            let dx = (col as f64 - cx) / m;
+            let r2 = dx * dx + dy * dy;
+            let gain = 1.0 + r2 * (k[0] + r2 * (k[1] + r2 * (k[2] + r2 * (k[3] + r2 * k[4]))));
+            for plane in 0..image.planes {
+                fixed.set(row, col, plane, (image.get(row, col, plane) as f64 * gain) as f32);
+            }
+        }
+    }
+    fixed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp() -> LinearImage {
+        let mut image = LinearImage::new(9, 7, 1);
+        for row in 0..7 {
+            for col in 0..9 {
+                image.set(row, col, 0, (row * 9 + col) as f32 / 63.0);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn identity_warp() {
+        let image = ramp();
+        let identity = RectilinearCoefficients { radial: [1.0, 0.0, 0.0, 0.0], tangential: [0.0, 0.0] };
+
+        let warped = warp_rectilinear(&image, &[identity], (0.5, 0.5));
+
+        for (a, b) in warped.data.iter().zip(&image.data) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    // Catmull-Rom reproduces a linear ramp, so every pixel of a warped column ramp is the x it was
+    // sampled from. The image center is (4, 3) and radii are normalized by 5.
+    fn column_ramp() -> LinearImage {
+        let mut image = LinearImage::new(9, 7, 1);
+        for row in 0..7 {
+            for col in 0..9 {
+                image.set(row, col, 0, col as f32);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn displaced_samples() {
+        let image = column_ramp();
+        let close = |a: f32, b: f64| assert!((a as f64 - b).abs() < 1e-4, "{} != {}", a, b);
+
+        // (3, 6) is at r = 0.4 on the x axis, f = 1 + 0.5 r^2 = 1.08 and kt1 adds 3 r^2 = 0.48 * 0.1
+        // (1, 2) is at (-0.4, -0.4), f = 1.16 and the tangential terms are 0.05 * 0.32 + 0.1 * 0.64
+        let k = RectilinearCoefficients { radial: [1.0, 0.5, 0.0, 0.0], tangential: [0.05, 0.1] };
+        let warped = warp_rectilinear(&image, &[k], (0.5, 0.5));
+        close(warped.get(3, 6, 0), 4.0 + 5.0 * (0.4 * 1.08 + 0.048));
+        close(warped.get(1, 2, 0), 4.0 + 5.0 * (-0.4 * 1.16 + 0.016 + 0.064));
+        close(warped.get(3, 4, 0), 4.0);
+
+        // the reciprocal of 1 + 0.5 r^2
+        let mut radial = [0.0; 15];
+        radial[0] = 1.0;
+        radial[2] = 0.5;
+        let k = Rectilinear2Coefficients { radial, tangential: [0.0, 0.0], min_valid_radius: 0.0, max_valid_radius: 1.0 };
+        let warped = warp_rectilinear2(&image, &[k], (0.5, 0.5), true);
+        close(warped.get(3, 6, 0), 4.0 + 5.0 * 0.4 / 1.08);
+
+        // an ideal fisheye samples at atan(r)
+        let warped = warp_fisheye(&image, &[[1.0, 0.0, 0.0, 0.0]], (0.5, 0.5));
+        close(warped.get(3, 6, 0), 4.0 + 5.0 * 0.4f64.atan());
+    }
+
+    #[test]
+    fn vignette_gain() {
+        let image = LinearImage { width: 3, height: 3, planes: 1, data: vec![0.5; 9] };
+
+        let fixed = fix_vignette_radial(&image, &[1.0, 0.0, 0.0, 0.0, 0.0], (0.5, 0.5));
+
+        // no gain in the center, double in the corners
+        assert_eq!(fixed.get(1, 1, 0), 0.5);
+        assert!((fixed.get(0, 0, 0) - 1.0).abs() < 1e-6);
+    }
+}
@@ -5,12 +5,61 @@ use std::path::PathBuf;
 use image::Image;
 
 use jpeg;
+mod area;
+mod bad_pixels;
+mod baseline_jpeg;
+mod black_level;
+mod cfa;
+mod codec;
+mod color;
+mod demosaic;
+mod depth;
+mod deflate;
 mod dng_utils;
+mod geometry;
+mod linearize;
+mod lens;
+mod lossless_jpeg;
+mod mask;
+mod matrix;
+mod opcode;
+mod orientation;
+mod predictor;
+mod preview;
+mod profile;
+mod raster;
+mod render;
+mod sample_format;
+mod semantic;
 mod tags;
+mod white_balance;
 mod get_value;
 mod trial;
+#[cfg(test)]
+mod test_utils;
 
 use tags::Tag;
+pub use area::GainMap;
+pub use bad_pixels::{bad_pixels_opcode, detect_bad_pixels, detect_hot_pixels_in_dark_frames};
+pub use black_level::{BlackLevelMeasurement, BlackLevelMode, BlackStats};
+pub use cfa::{CfaColor, CfaLayout, CfaPattern};
+pub use codec::{BaselineJpeg, ChunkInfo, Codec, CodecError, CodecRegistry, Deflate, Jpeg, LosslessJpeg, Uncompressed};
+pub use color::{camera_to_rgb, camera_to_xyz, temperature_to_xy, xy_to_temperature, ColorCalibration, ColorSpace, ColorSpec};
+pub use depth::{align_depth, depth_to_distance, normalize_depth, DepthFormat, DepthInfo, DepthMeasureType, DepthUnits};
+pub use demosaic::{demosaic, DemosaicAlgorithm};
+pub use geometry::{Geometry, Rect};
+pub use linearize::{linearize, linearize_float, LinearImage, LinearizationInfo};
+pub use mask::{alpha_to_u16, alpha_to_u8, apply_matte, mask_to_alpha};
+pub use matrix::Matrix;
+pub use opcode::{apply_opcodes, apply_opcodes_to_float_raster, apply_opcodes_to_raster, encode_opcode_list, parse_opcode_list, trim_origin, AreaSpec, Opcode, OpcodeError, OpcodeKind, OpcodeListStage, Rectilinear2Coefficients, RectilinearCoefficients};
+pub use orientation::Orientation;
+pub use preview::{best_preview, Preview, PreviewColorSpace};
+pub use profile::{CameraProfile, HueSatMap, ToneCurve};
+pub use raster::Raster;
+pub use render::{RenderError, RenderOptions};
+pub use sample_format::{f16_to_f32, fp24_to_f32, PixelBuffer, SampleFormat};
+pub use semantic::SemanticMask;
+pub use white_balance::{AsShotWhite, WhiteBalance};
 
 // See TIFF6.0 P15/16
 enum EntryData {
@@ -124,6 +173,23 @@ impl DataType {
         }
     }
 
+    fn to_f64(&self) -> f64{
+        use DataType::*;
+        match self {
+            Byte(u) | Ascii(u) | Undefined(u) => *u as f64,
+            Short(u) => *u as f64,
+            Long(u) => *u as f64,
+            Rational([n, d]) => *n as f64 / *d as f64,
+            Sbyte(i) => *i as f64,
+            Sshort(i) => *i as f64,
+            Slong(i) => *i as f64,
+            Srational([n, d]) => *n as f64 / *d as f64,
+            Float(f) => *f as f64,
+            Double(f) => *f,
+            _ => panic!("This type can't be cast to a f64!")
+        }
+    }
+
     pub(crate) fn get_bytes_per_value(data_type: u16) -> u16 {
         match data_type {
             1 | 2 | 6 | 7 => 1,
@@ -155,7 +221,7 @@ impl DataType {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-enum Endian {
+pub enum Endian {
     Big,
     Little,
 }
@@ -207,6 +273,7 @@ impl IFDs {
         ifds.insert_subifds(buffer, &image_file_header.endian);
 
         ifds.thumbnail = ifds.get_thumbnail_offset(buffer, &image_file_header.endian);
+        ifds.raw_image = ifds.get_raw_image_offset(buffer, &image_file_header.endian);
     
         ifds
     }
@@ -258,6 +325,36 @@ impl IFDs {
             None => None
         }
     }
+
+    // DNG spec 1.6 NewSubFileType P18, the main image has a NewSubFileType of 0 (which is also the default)
+    fn get_raw_image_offset(&self, buffer: &Vec<u8>, endian: &Endian) -> Option<usize> {
+        self.get_ifds_of_type(0, buffer, endian).first().map(|(offset, _)| *offset)
+    }
+
+    // DNG spec 1.6 NewSubFileType P18, an enhanced raw image made from the main one has a NewSubFileType of 16
+    fn get_enhanced_raw_image_offset(&self, buffer: &Vec<u8>, endian: &Endian) -> Option<usize> {
+        self.get_ifds_of_type(ENHANCED_RAW_IMAGE_TYPE, buffer, endian).first().map(|(offset, _)| *offset)
+    }
+
+    // Every IFD with a NewSubFileType of `new_sub_file_type`, by offset
+    fn get_ifds_of_type(&self, new_sub_file_type: u32, buffer: &Vec<u8>, endian: &Endian) -> Vec<(usize, &IFD)> {
+        let mut ifds = self.ifds.iter()
+            .filter(|(_, ifd)| match ifd.get_values(Tag::NewSubFileType_254, buffer, endian) {
+                Some(value) => value.to_value().to_u32() == new_sub_file_type,
+                None => new_sub_file_type == 0,
+            })
+            .map(|(offset, ifd)| (*offset, ifd))
+            .collect::<Vec<(usize, &IFD)>>();
+        ifds.sort_by_key(|(offset, _)| *offset);
+        ifds
+    }
+
+    fn get_raw_image_idf(&self) -> Option<&IFD> {
+        match self.raw_image {
+            Some(offset) => self.ifds.get(&offset),
+            None => None
+        }
+    }
 }
 
 struct IFD {
@@ -286,6 +383,10 @@ impl IFD {
             // next_ifd_offset: get_value::long(buffer, offset + 2 + entry_count * 12, endian) as usize,
         }
     }
+
+    fn get_values(&self, tag: Tag, buffer: &Vec<u8>, endian: &Endian) -> Option<EntryData> {
+        self.entries.get(&(tag as u16)).map(|entry| entry.get_entry_values(buffer, endian))
+    }
 }
 
 struct DirectoryEntry {
@@ -304,8 +405,9 @@ impl DirectoryEntry {
             count if count > 1 => {
                 let mut multiple = Vec::with_capacity(self.count);
                 if total_used_bytes <= 4 {
+                    let buffer = self.get_inline_bytes(endian);
                     for i in 0..self.count {
-                        multiple.push(DataType::get_entry_value(&self.value_or_offset.to_be_bytes().to_vec(), self.data_type, i * bytes_per_value, endian));
+                        multiple.push(DataType::get_entry_value(&buffer, self.data_type, i * bytes_per_value, endian));
                     }
                 } else {
                     for i in 0..self.count {
@@ -316,8 +418,8 @@ impl DirectoryEntry {
             },
             _ => {
                 if total_used_bytes <= 4 {
-                    let buffer = self.value_or_offset.to_be_bytes()[4-bytes_per_value..4].to_vec();
-                    EntryData::Single(DataType::get_entry_value(&buffer, self.data_type, 0, &Endian::Big))
+                    let buffer = self.get_inline_bytes(endian);
+                    EntryData::Single(DataType::get_entry_value(&buffer, self.data_type, 0, endian))
                 } else {
                     let buffer = buffer[self.value_or_offset as usize..self.value_or_offset as usize + bytes_per_value].to_vec();
                     EntryData::Single(DataType::get_entry_value(&buffer, self.data_type, 0, endian))
@@ -325,12 +427,33 @@ impl DirectoryEntry {
             }
         }    
     }
+
+    // TIFF6.0 P15, values that fit in 4 bytes are stored left justified in the value's place,
+    // so put the bytes back in the order they were in the file
+    fn get_inline_bytes(&self, endian: &Endian) -> Vec<u8> {
+        match endian {
+            Endian::Big => self.value_or_offset.to_be_bytes().to_vec(),
+            Endian::Little => self.value_or_offset.to_le_bytes().to_vec(),
+        }
+    }
 }
 
-struct DNG {
+const ENHANCED_RAW_IMAGE_TYPE: u32 = 16;
+const PHOTOMETRIC_LINEAR_RAW: u16 = 34892;
+
+/// Which of a DNG's raw images to decode. Enhanced ones (DNG 1.6) are usually demosaiced and denoised
+/// LinearRaw images made from the original CFA image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RawImageKind {
+    Original,
+    Enhanced,
+}
+
+pub struct DNG {
     encoded_image: Vec<u8>,
     image_file_header: ImageFileHeader,
-    ifds: IFDs
+    ifds: IFDs,
+    codecs: CodecRegistry,
 }
 
 impl DNG {
@@ -347,51 +470,508 @@ impl DNG {
             encoded_image,
             image_file_header,
             ifds,
+            codecs: CodecRegistry::new(),
+        }
+    }
+
+    // Lets downstream crates handle vendor specific or experimental compression schemes
+    pub fn register_codec(&mut self, compression: u16, codec: Box<dyn Codec>) {
+        self.codecs.register(compression, codec);
+    }
+
+    // The raw images in the DNG, the original one first
+    pub fn raw_image_kinds(&self) -> Vec<RawImageKind> {
+        let endian = &self.image_file_header.endian;
+        let mut kinds = Vec::new();
+        if self.ifds.get_raw_image_offset(&self.encoded_image, endian).is_some() {
+            kinds.push(RawImageKind::Original);
+        }
+        if self.ifds.get_enhanced_raw_image_offset(&self.encoded_image, endian).is_some() {
+            kinds.push(RawImageKind::Enhanced);
+        }
+        kinds
+    }
+
+    // Switches every method that reads the raw image, its tags or renders it over to the given raw image.
+    // Returns false and leaves the selection alone if the DNG doesn't have one.
+    pub fn select_raw_image(&mut self, kind: RawImageKind) -> bool {
+        let endian = &self.image_file_header.endian;
+        let offset = match kind {
+            RawImageKind::Original => self.ifds.get_raw_image_offset(&self.encoded_image, endian),
+            RawImageKind::Enhanced => self.ifds.get_enhanced_raw_image_offset(&self.encoded_image, endian),
+        };
+        if offset.is_some() {
+            self.ifds.raw_image = offset;
+        }
+        offset.is_some()
+    }
+
+    pub fn selected_raw_image(&self) -> RawImageKind {
+        let endian = &self.image_file_header.endian;
+        let enhanced = self.ifds.get_enhanced_raw_image_offset(&self.encoded_image, endian);
+        if enhanced.is_some() && self.ifds.raw_image == enhanced {
+            RawImageKind::Enhanced
+        } else {
+            RawImageKind::Original
+        }
+    }
+
+    // How the enhanced raw image was made, from its EnhanceParams tag
+    pub fn get_enhance_params(&self) -> Option<String> {
+        let endian = &self.image_file_header.endian;
+        let offset = self.ifds.get_enhanced_raw_image_offset(&self.encoded_image, endian)?;
+        preview::read_string(&self.encoded_image, &self.ifds.ifds[&offset], endian, Tag::EnhanceParams_51182)
+    }
+
+    // IFD 0 as 8 bit RGB, None when there's no thumbnail or it isn't 8 bit RGB or gray
+    pub fn get_thumbnail(&self) -> Option<Image> {
+        let thumbnail_ifd = self.ifds.get_thumbnail_idf()?;
+        let endian = &self.image_file_header.endian;
+
+        let bits_per_sample = thumbnail_ifd.get_values(Tag::BitsPerSample_258, &self.encoded_image, endian)?.to_vec();
+        let photometric_interpretation = thumbnail_ifd.get_values(Tag::PhotometricInterpretation_262, &self.encoded_image, endian)?.to_value().to_u16();
+        let samples_per_pixel = thumbnail_ifd.get_values(Tag::SamplesPerPixel_277, &self.encoded_image, endian)?.to_value().to_u16();
+
+        // monochrome DNGs can have a BlackIsZero (1) gray thumbnail, which ends up in all three channels
+        if !bits_per_sample.iter().all(|f| f.to_u16() == 8) {
+            return None;
+        }
+        if (photometric_interpretation, samples_per_pixel) != (2, 3) && (photometric_interpretation, samples_per_pixel) != (1, 1) {
+            return None;
+        }
+
+        let raster = raster::read_raster(&self.encoded_image, thumbnail_ifd, endian, &self.codecs);
+        let copies = if samples_per_pixel == 1 { 3 } else { 1 };
+
+        Some(Image {
+            data: raster.data.iter().flat_map(|&s| std::iter::repeat_n(s as u8, copies)).collect(),
+            width: raster.width as u32,
+            height: raster.height as u32,
+        })
+    }
+
+    // The thumbnail rotated and flipped the right way up, get_thumbnail is the way it's stored
+    pub fn get_display_thumbnail(&self) -> Option<Image> {
+        Some(self.get_orientation().orient_rgb(&self.get_thumbnail()?))
+    }
+
+    // Every reduced resolution image, smallest first
+    pub fn previews(&self) -> Vec<Preview> {
+        let endian = &self.image_file_header.endian;
+        let mut previews = self.ifds.get_ifds_of_type(1, &self.encoded_image, endian).iter()
+            .map(|(offset, ifd)| Preview::read(&self.encoded_image, *offset, ifd, endian))
+            .collect::<Vec<Preview>>();
+        previews.sort_by_key(|p| p.width * p.height);
+        previews
+    }
+
+    pub fn best_preview(&self, min_width: usize, max_width: usize) -> Option<Preview> {
+        best_preview(&self.previews(), min_width, max_width).cloned()
+    }
+
+    // The preview as 8 bit RGB the way it's stored, or None when it can't be decoded: there's no codec
+    // for its compression, it's JPEG data that isn't baseline or lossless, or it isn't gray, RGB or
+    // JPEG compressed YCbCr (which the JPEG codec turns into RGB).
+    pub fn get_preview_image(&self, preview: &Preview) -> Option<Image> {
+        let ifd = &self.ifds.ifds[&preview.offset];
+        let endian = &self.image_file_header.endian;
+        self.codecs.get(preview.compression)?;
+        let jpeg = preview.compression == 7 || preview.compression == 34892;
+        if jpeg {
+            let marker = raster::first_chunk(&self.encoded_image, ifd, endian).and_then(baseline_jpeg::frame_marker);
+            if !matches!(marker, Some(baseline_jpeg::SOF0 | baseline_jpeg::SOF1 | lossless_jpeg::SOF3)) {
+                return None;
+            }
+        }
+        // TIFF6.0 PhotometricInterpretation P37, WhiteIsZero (0) gray gets inverted
+        match (preview.photometric_interpretation, preview.samples_per_pixel) {
+            (0 | 1, 1) | (2, 3..) => {},
+            (6, 3) if jpeg => {},
+            _ => return None,
+        }
+
+        let raster = raster::read_raster(&self.encoded_image, ifd, endian, &self.codecs);
+        let shift = raster.bits_per_sample.saturating_sub(8);
+        let data = match raster.samples_per_pixel {
+            1 => raster.data.iter()
+                .map(|&s| (s >> shift) as u8)
+                .flat_map(|s| [if preview.photometric_interpretation == 0 { 255 - s } else { s }; 3])
+                .collect(),
+            _ => raster.data.chunks(raster.samples_per_pixel).flat_map(|p| [p[0], p[1], p[2]].map(|s| (s >> shift) as u8)).collect(),
+        };
+        Some(Image { data, width: raster.width as u32, height: raster.height as u32 })
+    }
+
+    pub fn get_display_preview_image(&self, preview: &Preview) -> Option<Image> {
+        self.get_preview_image(preview).map(|image| self.get_orientation().orient_rgb(&image))
+    }
+
+    // The thumbnail's samples split up by plane, for code that would rather work with planar data
+    pub fn get_thumbnail_planes(&self) -> Option<Vec<Vec<u16>>> {
+        let thumbnail_ifd = self.ifds.get_thumbnail_idf()?;
+        Some(raster::read_raster(&self.encoded_image, thumbnail_ifd, &self.image_file_header.endian, &self.codecs).planes())
+    }
+
+    // The main (NewSubFileType = 0) image's samples, interleaved
+    pub fn get_raw_image(&self) -> Raster {
+        let raw_ifd = self.ifds.get_raw_image_idf().expect("The DNG doesn't have a raw image!");
+        raster::read_raster(&self.encoded_image, raw_ifd, &self.image_file_header.endian, &self.codecs)
+    }
+
+    // The raw image's samples in the type they're stored as, get_raw_image has them all as 16 bit
+    pub fn get_raw_pixels(&self) -> PixelBuffer {
+        let raw_ifd = self.ifds.get_raw_image_idf().expect("The DNG doesn't have a raw image!");
+        raster::read_pixel_buffer(&self.encoded_image, raw_ifd, &self.image_file_header.endian, &self.codecs)
+    }
+
+    pub fn get_raw_planes(&self) -> Vec<Vec<u16>> {
+        self.get_raw_image().planes()
+    }
+
+    pub fn get_linearization_info(&self) -> LinearizationInfo {
+        let raw_ifd = self.ifds.get_raw_image_idf().expect("The DNG doesn't have a raw image!");
+        let endian = &self.image_file_header.endian;
+        let samples_per_pixel = match raw_ifd.get_values(Tag::SamplesPerPixel_277, &self.encoded_image, endian) {
+            Some(spp) => spp.to_value().to_usize(),
+            None => 1,
+        };
+        let bits_per_sample = raw_ifd.get_values(Tag::BitsPerSample_258, &self.encoded_image, endian).expect("The raw image has no BitsPerSample!").to_vec()[0].to_usize();
+        // the levels are in the units of the stored samples, so they get scaled like the samples do
+        let (scale, _, white_bits) = SampleFormat::read(&self.encoded_image, raw_ifd, endian).raster_conversion(bits_per_sample);
+        let mut info = LinearizationInfo::read(&self.encoded_image, raw_ifd, endian, samples_per_pixel, white_bits);
+        if scale != 1.0 {
+            info.scale_levels(scale);
+        }
+        info
+    }
+
+    pub fn get_geometry(&self) -> Geometry {
+        let raw_ifd = self.ifds.get_raw_image_idf().expect("The DNG doesn't have a raw image!");
+        Geometry::read(&self.encoded_image, raw_ifd, &self.image_file_header.endian)
+    }
+
+    // Just the ActiveArea of the raw image, without the masked pixels around it
+    pub fn get_active_raw_image(&self) -> Raster {
+        self.get_raw_image().crop(&self.get_geometry().active_area)
+    }
+
+    pub fn get_default_crop_raw_image(&self) -> Raster {
+        self.get_raw_image().crop(&self.get_geometry().default_crop())
+    }
+
+    pub fn get_masked_raw_images(&self) -> Vec<Raster> {
+        let raw_image = self.get_raw_image();
+        self.get_geometry().masked_areas.iter().map(|area| raw_image.crop(area)).collect()
+    }
+
+    // None when the raw image isn't a CFA image. The pattern starts at the top left of the active area.
+    pub fn get_cfa_pattern(&self) -> Option<CfaPattern> {
+        if self.is_linear_raw() {
+            return None;
+        }
+        let raw_ifd = self.ifds.get_raw_image_idf().expect("The DNG doesn't have a raw image!");
+        CfaPattern::read(&self.encoded_image, raw_ifd, &self.image_file_header.endian)
+    }
+
+    // Which of the raw image's samples are the color planes, in the order of the ColorMatrix rows.
+    // DNG spec 1.6 PhotometricInterpretation P17, a LinearRaw image's samples are the planes in that
+    // order. ExtraSamples (TIFF6.0 P31) come last and aren't planes, and neither are any other samples
+    // past the ColorMatrix's rows. CFA images are demosaiced into their CFAPlaneColor order.
+    pub fn color_planes(&self) -> Vec<usize> {
+        let raw_ifd = self.ifds.get_raw_image_idf().expect("The DNG doesn't have a raw image!");
+        let endian = &self.image_file_header.endian;
+        if !self.is_linear_raw() {
+            return (0..self.get_cfa_pattern().map_or(1, |cfa| cfa.planes())).collect();
+        }
+        let samples_per_pixel = match raw_ifd.get_values(Tag::SamplesPerPixel_277, &self.encoded_image, endian) {
+            Some(spp) => spp.to_value().to_usize(),
+            None => 1,
+        };
+        let extra_samples = match raw_ifd.get_values(Tag::ExtraSamples_338, &self.encoded_image, endian) {
+            Some(extra) => extra.to_vec().len(),
+            None => 0,
+        };
+        let color_samples = samples_per_pixel.saturating_sub(extra_samples);
+        let planes = match self.get_color_calibration() {
+            Some(calibration) => {
+                assert!(calibration.planes <= color_samples, "The LinearRaw image has fewer samples than the ColorMatrix has rows!");
+                calibration.planes
+            },
+            None => color_samples.min(1),
+        };
+        (0..planes).collect()
+    }
+
+    // DNG spec 1.6 PhotometricInterpretation P17, LinearRaw images are already demosaiced with one sample
+    // per color plane, see color_planes
+    pub fn is_linear_raw(&self) -> bool {
+        let raw_ifd = self.ifds.get_raw_image_idf().expect("The DNG doesn't have a raw image!");
+        match raw_ifd.get_values(Tag::PhotometricInterpretation_262, &self.encoded_image, &self.image_file_header.endian) {
+            Some(photometric_interpretation) => photometric_interpretation.to_value().to_u16() == PHOTOMETRIC_LINEAR_RAW,
+            None => false,
+        }
+    }
+
+    // Camera values of the default crop with all three opcode lists applied, demosaiced unless the
+    // raw image is LinearRaw. This is where render starts its color work. The crops after each list
+    // allow for any TrimBounds in it.
+    pub fn get_camera_image(&self, options: &RenderOptions) -> Result<LinearImage, OpcodeError> {
+        let geometry = self.get_geometry();
+        let info = self.get_linearization_info_with(options.black_level);
+        let raw_opcodes = self.get_opcode_list(OpcodeListStage::Raw)?;
+        let linear = self.linearize_raw(&raw_opcodes, &info)?;
+        let active_area = geometry.active_area.relative_to(trim_origin(&raw_opcodes), linear.width, linear.height);
+        let linear_opcodes = self.get_opcode_list(OpcodeListStage::Linear)?;
+        let linear = apply_opcodes(&linear.crop(&active_area), &linear_opcodes)?;
+
+        let camera = match self.get_cfa_pattern() {
+            Some(cfa) => demosaic(&linear, &cfa, options.demosaic.unwrap_or_else(|| DemosaicAlgorithm::best_for(&cfa))),
+            None => linear,
+        };
+        let demosaiced_opcodes = self.get_opcode_list(OpcodeListStage::Demosaiced)?;
+        let camera = apply_opcodes(&camera, &demosaiced_opcodes)?.select_planes(&self.color_planes());
+        // cropping before the color work saves converting pixels that would be thrown away
+        let (linear_top, linear_left) = trim_origin(&linear_opcodes);
+        let (demosaiced_top, demosaiced_left) = trim_origin(&demosaiced_opcodes);
+        let origin = (linear_top + demosaiced_top, linear_left + demosaiced_left);
+        Ok(camera.crop(&geometry.default_crop_in_active_area().relative_to(origin, camera.width, camera.height)))
+    }
+
+    // Measures the optical black pixels using the BlackLevelRepeatDim pattern, after the
+    // LinearizationTable so that the results are in the same units as BlackLevel
+    pub fn measure_black_level(&self) -> BlackLevelMeasurement {
+        let info = self.get_linearization_info();
+        let raw = info.apply_table(&self.get_raw_image());
+        BlackLevelMeasurement::measure(&raw, &self.get_geometry(), info.black_level_repeat_rows, info.black_level_repeat_cols)
+    }
+
+    pub fn get_linearization_info_with(&self, mode: BlackLevelMode) -> LinearizationInfo {
+        let mut info = self.get_linearization_info();
+        match mode {
+            BlackLevelMode::Tagged => {},
+            BlackLevelMode::Measured => self.measure_black_level().apply_to(&mut info),
+            BlackLevelMode::MeasuredIfInvalid(tolerance) => {
+                let measurement = self.measure_black_level();
+                if measurement.validate(&info).iter().flatten().any(|d| d.abs() > tolerance) {
+                    measurement.apply_to(&mut info);
+                }
+            }
+        }
+        info
+    }
+
+    // The raw image mapped to linear reference values, see DNG spec 1.6 Chapter 5
+    pub fn get_linear_image(&self) -> LinearImage {
+        self.get_linear_image_with(BlackLevelMode::Tagged)
+    }
+
+    pub fn get_linear_image_with(&self, mode: BlackLevelMode) -> LinearImage {
+        self.linearize_raw(&[], &self.get_linearization_info_with(mode)).expect("There are no opcodes to fail!")
+    }
+
+    // The raw image with `raw_opcodes` (OpcodeList1) applied, linearized. Float samples don't go
+    // through the 16 bit Raster, so they keep any headroom above the white level.
+    fn linearize_raw(&self, raw_opcodes: &[Opcode], info: &LinearizationInfo) -> Result<LinearImage, OpcodeError> {
+        let pixels = self.get_raw_pixels();
+        Ok(match pixels.sample_format() {
+            SampleFormat::Float => linearize_float(&apply_opcodes_to_float_raster(&pixels.to_f32_raster(), raw_opcodes)?, info),
+            _ => linearize(&apply_opcodes_to_raster(&pixels.into_raster(), raw_opcodes)?, info),
+        })
+    }
+
+    // The active area of a CFA raw image as linear camera RGB
+    pub fn get_demosaiced_image(&self, algorithm: DemosaicAlgorithm) -> LinearImage {
+        let cfa = self.get_cfa_pattern().expect("The raw image isn't a CFA image!");
+        let active = self.get_linear_image().crop(&self.get_geometry().active_area);
+        demosaic(&active, &cfa, algorithm)
+    }
+
+    // OpcodeList1, 2 or 3 of the raw image, empty when the list isn't there and an error when it's cut short
+    pub fn get_opcode_list(&self, stage: OpcodeListStage) -> Result<Vec<Opcode>, OpcodeError> {
+        let raw_ifd = self.ifds.get_raw_image_idf().expect("The DNG doesn't have a raw image!");
+        opcode::read_opcode_list(&self.encoded_image, raw_ifd, &self.image_file_header.endian, stage)
+    }
+
+    // IFD 0, which holds the tags that describe the whole file like the color calibration
+    fn get_main_ifd(&self) -> &IFD {
+        &self.ifds.ifds[&self.image_file_header.ifd_offset]
+    }
+
+    // None when the DNG has no ColorMatrix1, see DNG spec 1.6 Chapter 6
+    pub fn get_color_calibration(&self) -> Option<ColorCalibration> {
+        ColorCalibration::read(&self.encoded_image, self.get_main_ifd(), &self.image_file_header.endian)
+    }
+
+    // None when the DNG has neither AsShotNeutral nor AsShotWhiteXY
+    pub fn get_as_shot_white(&self) -> Option<AsShotWhite> {
+        AsShotWhite::read(&self.encoded_image, self.get_main_ifd(), &self.image_file_header.endian)
+    }
+
+    // The as shot white balance as a camera neutral, converting AsShotWhiteXY when that's all there is
+    pub fn get_as_shot_neutral(&self) -> Option<Vec<f64>> {
+        match self.get_as_shot_white()? {
+            AsShotWhite::Neutral(neutral) => Some(neutral),
+            AsShotWhite::Xy(xy) => Some(self.get_color_calibration()?.xy_to_neutral(xy)),
+        }
+    }
+
+    // The as shot white balance as correlated color temperature and tint
+    pub fn get_as_shot_temperature(&self) -> Option<(f64, f64)> {
+        let calibration = self.get_color_calibration()?;
+        let xy = calibration.white_balance_xy(&WhiteBalance::AsShot, Some(&self.get_as_shot_white()?));
+        Some(xy_to_temperature(xy))
+    }
+
+    // BaselineExposure plus BaselineExposureOffset, in EV
+    pub fn get_baseline_exposure(&self) -> f64 {
+        let ifd = self.get_main_ifd();
+        let endian = &self.image_file_header.endian;
+        [Tag::BaselineExposure_50730, Tag::BaselineExposureOffset_51109].into_iter()
+            .filter_map(|tag| ifd.get_values(tag, &self.encoded_image, endian))
+            .map(|v| v.to_value().to_f64())
+            .sum()
+    }
+
+    // The Orientation in IFD 0, which applies to the main image and the previews
+    pub fn get_orientation(&self) -> Orientation {
+        match self.get_main_ifd().get_values(Tag::Orientation_274, &self.encoded_image, &self.image_file_header.endian) {
+            Some(orientation) => Orientation::from_value(orientation.to_value().to_u16()),
+            None => Orientation::Normal,
+        }
+    }
+
+    // The HueSatMap, LookTable and ProfileToneCurve of the embedded camera profile
+    pub fn get_camera_profile(&self) -> CameraProfile {
+        CameraProfile::read(&self.encoded_image, self.get_main_ifd(), &self.image_file_header.endian)
+    }
+
+    pub fn get_color_spec(&self, white_balance: &WhiteBalance) -> Option<ColorSpec> {
+        let calibration = self.get_color_calibration()?;
+        let xy = calibration.white_balance_xy(white_balance, self.get_as_shot_white().as_ref());
+        Some(calibration.color_spec(xy))
+    }
+
+    // The whole raw processing pipeline, see DNG spec 1.6 Chapter 5 and 6. It fails when an opcode
+    // that isn't optional can't be applied or a color image has no usable ColorMatrix.
+    pub fn render(&self, options: &RenderOptions) -> Result<Image, RenderError> {
+        let image = render::encode(&self.render_linear(options)?, options.color_space);
+        Ok(match (options.matte, self.get_render_alpha(options)) {
+            (Some(matte), Some(alpha)) => apply_matte(&image, &alpha, matte),
+            _ => image,
+        })
+    }
+
+    // 16 bit gray encoded with the color space's transfer function, the luminance for color images
+    pub fn render_gray16(&self, options: &RenderOptions) -> Result<Raster, RenderError> {
+        Ok(render::encode_gray16(&self.render_linear(options)?, options.color_space))
+    }
+
+    // Single plane raw images, like the LinearRaw images of monochrome cameras
+    pub fn is_monochrome(&self) -> bool {
+        let raw_ifd = self.ifds.get_raw_image_idf().expect("The DNG doesn't have a raw image!");
+        let samples_per_pixel = match raw_ifd.get_values(Tag::SamplesPerPixel_277, &self.encoded_image, &self.image_file_header.endian) {
+            Some(spp) => spp.to_value().to_usize(),
+            None => 1,
+        };
+        samples_per_pixel == 1 && self.get_cfa_pattern().is_none()
+    }
+
+    // Linear values in the output space, everything render does but the encoding. Monochrome images
+    // stay a single plane of gray.
+    fn render_linear(&self, options: &RenderOptions) -> Result<LinearImage, RenderError> {
+        let camera = self.get_camera_image(options)?;
+        let mut output = if camera.planes == 1 { self.render_gray(&camera, options) } else { self.render_color(&camera, options)? };
+        if options.display_oriented {
+            output = self.get_orientation().orient_image(&output);
         }
+        Ok(output)
     }
 
-    pub fn get_thumbnail(&self) -> Image {
-        let thumbnail_ifd = self.ifds.get_thumbnail_idf().unwrap();
+    // DNG spec 1.6 ColorMatrix1 P65 isn't required for monochrome images, their linear values are
+    // already the luminance, so there's no white balance or color transform to do
+    fn render_gray(&self, camera: &LinearImage, options: &RenderOptions) -> LinearImage {
+        let mut gray = render::apply_exposure(camera, self.get_baseline_exposure() + options.exposure);
+        if options.use_profile {
+            if let Some(tone_curve) = &self.get_camera_profile().tone_curve {
+                gray = tone_curve.apply(&gray);
+            }
+        }
+        gray
+    }
+
+    fn render_color(&self, camera: &LinearImage, options: &RenderOptions) -> Result<LinearImage, RenderError> {
+        // the profile's tables work on linear ProPhoto RGB
+        let calibration = self.get_color_calibration().ok_or(RenderError::NoColorMatrix)?;
+        if camera.planes != calibration.planes {
+            return Err(RenderError::PlaneMismatch { raw: camera.planes, color_matrix: calibration.planes });
+        }
+        let white = calibration.white_balance_xy(&options.white_balance, self.get_as_shot_white().as_ref());
+        let spec = calibration.color_spec(white);
+        let mut rgb = camera_to_rgb(camera, &spec, ColorSpace::ProPhoto);
+
+        let profile = if options.use_profile { self.get_camera_profile() } else { CameraProfile::default() };
+        if let Some(hue_sat_map) = profile.hue_sat_map_for(calibration.first_calibration_weight(white)) {
+            rgb = hue_sat_map.apply(&rgb);
+        }
+        rgb = render::apply_exposure(&rgb, self.get_baseline_exposure() + options.exposure);
+        if let Some(look_table) = &profile.look_table {
+            rgb = look_table.apply(&rgb);
+        }
+        if let Some(tone_curve) = &profile.tone_curve {
+            rgb = tone_curve.apply(&rgb);
+        }
 
-        let width = thumbnail_ifd.entries[&(Tag::ImageWidth_256 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_u32();
-        let length = thumbnail_ifd.entries[&(Tag::ImageLength_257 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_u32();
-        
-        let bits_per_sample = thumbnail_ifd.entries[&(Tag::BitsPerSample_258 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_vec();
-        let compression = thumbnail_ifd.entries[&(Tag::Compression_259 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_u16();
-        let photometric_interpretation = thumbnail_ifd.entries[&(Tag::PhotometricInterpretation_262 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_u16();
-        
-        let strip_offsets = thumbnail_ifd.entries[&(Tag::StripOffsets_273 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_usize();
-        let orientation = thumbnail_ifd.entries[&(Tag::Orientation_274 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_u16();
-        let samples_per_pixel = thumbnail_ifd.entries[&(Tag::SamplesPerPixel_277 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_u16();
-        let rows_per_strip = thumbnail_ifd.entries[&(Tag::RowsPerStrip_278 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_u32();
-        
-        let strip_byte_count = thumbnail_ifd.entries[&(Tag::StripByteCounts_279 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_usize();
-        
-        let planar_configuration = thumbnail_ifd.entries[&(Tag::PlanarConfiguration_284 as u16)].get_entry_values(&self.encoded_image, &self.image_file_header.endian).to_value().to_u32();
+        let to_output = &options.color_space.xyz_to_rgb() * &ColorSpace::ProPhoto.rgb_to_xyz();
+        Ok(rgb.transform(&to_output))
+    }
 
-        assert_eq!(bits_per_sample.iter().map(|f| f.to_u16()).collect::<Vec<u16>>(), vec![8, 8, 8]);
-        assert_eq!(compression, 1);
-        assert_eq!(photometric_interpretation, 2);        
-        assert_eq!(orientation, 1);        
-        assert_eq!(samples_per_pixel, 3);     
-        assert_eq!(rows_per_strip, length);   
-        assert_eq!(planar_configuration, 1);   
+    // The transparency mask (NewSubFileType = 4) as it's stored
+    pub fn get_transparency_mask(&self) -> Option<Raster> {
+        let endian = &self.image_file_header.endian;
+        let (_, ifd) = *self.ifds.get_ifds_of_type(4, &self.encoded_image, endian).first()?;
+        Some(raster::read_raster(&self.encoded_image, ifd, endian, &self.codecs))
+    }
 
-        let image_data = &dng_utils::get_image_chunks(&self.encoded_image, &vec![strip_offsets], &vec![strip_byte_count])[0];
+    // The depth map (NewSubFileType = 8) as it's stored
+    pub fn get_depth_map(&self) -> Option<Raster> {
+        let endian = &self.image_file_header.endian;
+        let (_, ifd) = *self.ifds.get_ifds_of_type(8, &self.encoded_image, endian).first()?;
+        Some(raster::read_raster(&self.encoded_image, ifd, endian, &self.codecs))
+    }
 
-        let mut data = Vec::with_capacity(image_data.len());
-        for &i in image_data.iter() {
-            data.push(i);
+    // Every semantic mask (NewSubFileType = 0x10004) decoded, keyed by SemanticName, with one entry per
+    // SemanticInstanceID in IFD order
+    pub fn get_semantic_masks(&self) -> HashMap<String, Vec<SemanticMask>> {
+        let endian = &self.image_file_header.endian;
+        let mut masks: HashMap<String, Vec<SemanticMask>> = HashMap::new();
+        for (_, ifd) in self.ifds.get_ifds_of_type(semantic::SEMANTIC_MASK_TYPE, &self.encoded_image, endian) {
+            let raster = raster::read_raster(&self.encoded_image, ifd, endian, &self.codecs);
+            let mask = SemanticMask::read(&self.encoded_image, ifd, endian, &raster);
+            masks.entry(mask.name.clone()).or_default().push(mask);
         }
+        masks
+    }
+
+    pub fn get_depth_info(&self) -> DepthInfo {
+        DepthInfo::read(&self.encoded_image, self.get_main_ifd(), &self.image_file_header.endian)
+    }
 
-        let mut tags = thumbnail_ifd.entries.keys().collect::<Vec<&u16>>();
-        tags.sort();
+    // Distances in DepthUnits, the size of the default crop. None without a depth map or when the
+    // depth tags don't say how to convert it.
+    pub fn get_depth_distances(&self) -> Option<LinearImage> {
+        let depth = normalize_depth(&self.get_depth_map()?);
+        let distances = depth_to_distance(&depth, &self.get_depth_info())?;
+        Some(align_depth(&distances, &self.get_geometry()))
+    }
 
-        Image {
-            data,
-            width,
-            height: length,
+    // 8 bit alpha that lines up with what render returns for the same options, for exports
+    pub fn get_render_alpha(&self, options: &RenderOptions) -> Option<Vec<u8>> {
+        let geometry = self.get_geometry();
+        let mut alpha = mask_to_alpha(&self.get_transparency_mask()?, &geometry).crop(&geometry.default_crop_in_active_area());
+        if options.display_oriented {
+            alpha = self.get_orientation().orient_image(&alpha);
         }
+        Some(alpha_to_u8(&alpha))
     }
 }
 
@@ -400,6 +980,249 @@ mod tests {
     use std::env;
 
     use super::*;
+    use test_utils::{build_tiff, dc_only_jpeg, TestIfd, Value};
+
+    // IFD 0 is a 1x1 RGB thumbnail, `raw` is its only SubIFD
+    fn dng_with_raw(raw: TestIfd) -> DNG {
+        dng_with_sub_ifds(vec![raw])
+    }
+
+    fn dng_with_sub_ifds(sub_ifds: Vec<TestIfd>) -> DNG {
+        DNG::from_encoded_vec(build_tiff(Endian::Little, thumbnail_ifd(), sub_ifds))
+    }
+
+    fn thumbnail_ifd() -> TestIfd {
+        TestIfd::new()
+            .tag(Tag::NewSubFileType_254, Value::Long(vec![1]))
+            .tag(Tag::ImageWidth_256, Value::Short(vec![1]))
+            .tag(Tag::ImageLength_257, Value::Short(vec![1]))
+            .tag(Tag::BitsPerSample_258, Value::Short(vec![8, 8, 8]))
+            .tag(Tag::PhotometricInterpretation_262, Value::Short(vec![2]))
+            .tag(Tag::SamplesPerPixel_277, Value::Short(vec![3]))
+            .strips(vec![vec![1, 2, 3]])
+    }
+
+    fn gray_ifd(width: u16, height: u16, samples: Vec<u8>) -> TestIfd {
+        TestIfd::new()
+            .tag(Tag::ImageWidth_256, Value::Short(vec![width]))
+            .tag(Tag::ImageLength_257, Value::Short(vec![height]))
+            .tag(Tag::BitsPerSample_258, Value::Short(vec![8]))
+            .strips(vec![samples])
+    }
+
+    // Values of up to 4 bytes are stored in the entry, starting at its first byte whatever the byte order
+    #[test]
+    fn inline_values() {
+        for endian in [Endian::Little, Endian::Big] {
+            let ifd = TestIfd::new()
+                .tag(Tag::ImageWidth_256, Value::Short(vec![300]))
+                .tag(Tag::ImageLength_257, Value::Long(vec![70000]))
+                .tag(Tag::BitsPerSample_258, Value::Short(vec![12, 16]))
+                .tag(Tag::CFAPattern_33422, Value::Byte(vec![0, 1, 2]));
+            let buffer = build_tiff(endian.clone(), ifd, Vec::new());
+            let ifd = IFD::parse_ifd(&buffer, get_value::long(&buffer, 4, &endian) as usize, &endian);
+            let values = |tag: Tag| ifd.get_values(tag, &buffer, &endian).unwrap().to_vec().iter().map(|v| v.to_u32()).collect::<Vec<u32>>();
+
+            assert_eq!(values(Tag::ImageWidth_256), vec![300]);
+            assert_eq!(values(Tag::ImageLength_257), vec![70000]);
+            assert_eq!(values(Tag::BitsPerSample_258), vec![12, 16]);
+            assert_eq!(values(Tag::CFAPattern_33422), vec![0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn raw_image_without_new_sub_file_type() {
+        let dng = dng_with_raw(gray_ifd(2, 1, vec![7, 9]));
+
+        assert_eq!(dng.get_raw_image().data, vec![7, 9]);
+    }
+
+    #[test]
+    fn raw_image_selection() {
+        let enhanced = gray_ifd(2, 1, vec![8, 10])
+            .tag(Tag::NewSubFileType_254, Value::Long(vec![ENHANCED_RAW_IMAGE_TYPE]));
+        let mut dng = dng_with_sub_ifds(vec![enhanced, gray_ifd(2, 1, vec![7, 9]).tag(Tag::NewSubFileType_254, Value::Long(vec![0]))]);
+
+        // the main image is selected by default whatever order the SubIFDs are in
+        assert_eq!(dng.raw_image_kinds(), vec![RawImageKind::Original, RawImageKind::Enhanced]);
+        assert_eq!(dng.selected_raw_image(), RawImageKind::Original);
+        assert_eq!(dng.get_raw_image().data, vec![7, 9]);
+
+        assert!(dng.select_raw_image(RawImageKind::Enhanced));
+        assert_eq!(dng.selected_raw_image(), RawImageKind::Enhanced);
+        assert_eq!(dng.get_raw_image().data, vec![8, 10]);
+
+        assert!(dng.select_raw_image(RawImageKind::Original));
+        assert_eq!(dng.get_raw_image().data, vec![7, 9]);
+
+        // without an enhanced image the selection stays on the main one
+        let mut dng = dng_with_raw(gray_ifd(2, 1, vec![7, 9]));
+        assert_eq!(dng.raw_image_kinds(), vec![RawImageKind::Original]);
+        assert!(!dng.select_raw_image(RawImageKind::Enhanced));
+        assert_eq!(dng.selected_raw_image(), RawImageKind::Original);
+        assert_eq!(dng.get_raw_image().data, vec![7, 9]);
+    }
+
+    fn jpeg_preview_ifd(compression: u16, data: Vec<u8>) -> TestIfd {
+        TestIfd::new()
+            .tag(Tag::NewSubFileType_254, Value::Long(vec![1]))
+            .tag(Tag::ImageWidth_256, Value::Short(vec![16]))
+            .tag(Tag::ImageLength_257, Value::Short(vec![8]))
+            .tag(Tag::BitsPerSample_258, Value::Short(vec![8, 8, 8]))
+            .tag(Tag::Compression_259, Value::Short(vec![compression]))
+            .tag(Tag::PhotometricInterpretation_262, Value::Short(vec![6]))
+            .tag(Tag::SamplesPerPixel_277, Value::Short(vec![3]))
+            .strips(vec![data])
+    }
+
+    #[test]
+    fn jpeg_previews() {
+        // Y = 100 on the left and 60 on the right, Cb = 128 and Cr = 168
+        let baseline = dc_only_jpeg(16, 8, &[(2, 1), (1, 1), (1, 1)], &[100, 60, 128, 168], 0);
+        let mut progressive = baseline.clone();
+        let sof = progressive.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        progressive[sof + 1] = 0xC2;
+        let dng = dng_with_sub_ifds(vec![
+            gray_ifd(2, 1, vec![7, 9]),
+            jpeg_preview_ifd(7, baseline),
+            jpeg_preview_ifd(34892, progressive),
+            jpeg_preview_ifd(5, vec![0; 16]),
+        ]);
+        let previews = dng.previews();
+        let preview = |compression: u16| previews.iter().find(|p| p.compression == compression).unwrap();
+
+        let image = dng.get_preview_image(preview(7)).unwrap();
+
+        assert_eq!((image.width, image.height), (16, 8));
+        assert_eq!(&image.data[..3], &[156, 71, 100]);
+        assert_eq!(&image.data[image.data.len() - 3..], &[116, 31, 60]);
+        assert!(dng.get_preview_image(preview(34892)).is_none());
+        assert!(dng.get_preview_image(preview(5)).is_none());
+    }
+
+    #[test]
+    fn measured_black_level_through_table() {
+        // the masked top row is stored as 1, which the LinearizationTable maps to 10
+        let raw = gray_ifd(2, 2, vec![1, 1, 3, 3])
+            .tag(Tag::LinearizationTable_50712, Value::Short(vec![0, 10, 20, 30]))
+            .tag(Tag::ActiveArea_50829, Value::Short(vec![1, 0, 2, 2]))
+            .tag(Tag::MaskedAreas_50830, Value::Short(vec![0, 0, 1, 2]));
+        let dng = dng_with_raw(raw);
+
+        assert_eq!(dng.get_linearization_info_with(BlackLevelMode::Measured).black_level, vec![10.0]);
+        // without masked areas the tagged level stays
+        let dng = dng_with_raw(gray_ifd(2, 1, vec![7, 9]));
+        assert_eq!(dng.get_linearization_info_with(BlackLevelMode::Measured).black_level, vec![0.0]);
+    }
+
+    #[test]
+    fn linear_raw_planes() {
+        // 2x2 RGB plus an alpha sample, with a CFAPattern that has to be ignored and an OpcodeList3
+        // that halves the top row of the first plane
+        let samples = vec![
+            200, 100, 50, 255, 100, 200, 50, 255,
+            50, 100, 200, 255, 250, 250, 250, 255,
+        ];
+        let half_top_row = Opcode {
+            dng_version: [1, 3, 0, 0],
+            optional: false,
+            preview_skippable: false,
+            kind: OpcodeKind::ScalePerRow {
+                area: AreaSpec { top: 0, left: 0, bottom: 1, right: 2, plane: 0, planes: 1, row_pitch: 1, col_pitch: 1 },
+                scales: vec![0.5],
+            },
+        };
+        let raw = TestIfd::new()
+            .tag(Tag::ImageWidth_256, Value::Short(vec![2]))
+            .tag(Tag::ImageLength_257, Value::Short(vec![2]))
+            .tag(Tag::BitsPerSample_258, Value::Short(vec![8, 8, 8, 8]))
+            .tag(Tag::PhotometricInterpretation_262, Value::Short(vec![PHOTOMETRIC_LINEAR_RAW]))
+            .tag(Tag::SamplesPerPixel_277, Value::Short(vec![4]))
+            .tag(Tag::ExtraSamples_338, Value::Short(vec![2]))
+            .tag(Tag::CFARepeatPatternDim_33421, Value::Short(vec![2, 2]))
+            .tag(Tag::CFAPattern_33422, Value::Byte(vec![0, 1, 1, 2]))
+            .tag(Tag::OpcodeList_51022, Value::Undefined(encode_opcode_list(&[half_top_row])))
+            .strips(vec![samples]);
+        let identity = [(1, 1), (0, 1), (0, 1), (0, 1), (1, 1), (0, 1), (0, 1), (0, 1), (1, 1)];
+        let main = thumbnail_ifd().tag(Tag::ColorMatrix_50721, Value::SRational(identity.to_vec()));
+        let dng = DNG::from_encoded_vec(build_tiff(Endian::Little, main, vec![raw]));
+
+        assert!(dng.is_linear_raw() && dng.get_cfa_pattern().is_none());
+        assert_eq!(dng.color_planes(), vec![0, 1, 2]);
+        let camera = dng.get_camera_image(&RenderOptions::default()).unwrap();
+
+        // demosaicing would have mixed the neighbouring pixels
+        let expected = [[100, 100, 50], [50, 200, 50], [50, 100, 200], [250, 250, 250]];
+        let expected = expected.iter().flatten().map(|&v| v as f32 / 255.0).collect::<Vec<f32>>();
+        assert_eq!((camera.width, camera.height, camera.planes), (2, 2, 3));
+        assert!(camera.data.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-6));
+        assert!(dng.render(&RenderOptions::default()).is_ok());
+    }
+
+    // a 1x1 LinearRaw image whose camera RGB is what the camera sees of a gray card, `main` is IFD 0
+    fn linear_raw_pixel(main: TestIfd) -> DNG {
+        let raw = TestIfd::new()
+            .tag(Tag::ImageWidth_256, Value::Short(vec![1]))
+            .tag(Tag::ImageLength_257, Value::Short(vec![1]))
+            .tag(Tag::BitsPerSample_258, Value::Short(vec![8, 8, 8]))
+            .tag(Tag::PhotometricInterpretation_262, Value::Short(vec![PHOTOMETRIC_LINEAR_RAW]))
+            .tag(Tag::SamplesPerPixel_277, Value::Short(vec![3]))
+            .strips(vec![vec![200, 100, 50]]);
+        DNG::from_encoded_vec(build_tiff(Endian::Little, main, vec![raw]))
+    }
+
+    #[test]
+    fn custom_neutral() {
+        let identity = [(1, 1), (0, 1), (0, 1), (0, 1), (1, 1), (0, 1), (0, 1), (0, 1), (1, 1)];
+        let dng = linear_raw_pixel(thumbnail_ifd().tag(Tag::ColorMatrix_50721, Value::SRational(identity.to_vec())));
+        let is_gray = |options: &RenderOptions| {
+            let rgb = dng.render_linear(options).unwrap().data;
+            (rgb[0] - rgb[1]).abs() < 1e-4 && (rgb[1] - rgb[2]).abs() < 1e-4
+        };
+
+        // without an as shot white it's rendered for D50, which leaves it orange
+        assert!(!is_gray(&RenderOptions::default()));
+        assert!(is_gray(&RenderOptions { white_balance: WhiteBalance::Neutral(vec![1.0, 0.5, 0.25]), ..RenderOptions::default() }));
+    }
+
+    #[test]
+    fn render_errors() {
+        // a 4x4 RGGB CFA image, which has three color planes
+        let cfa = || gray_ifd(4, 4, vec![100; 16])
+            .tag(Tag::CFARepeatPatternDim_33421, Value::Short(vec![2, 2]))
+            .tag(Tag::CFAPattern_33422, Value::Byte(vec![0, 1, 1, 2]));
+        let options = RenderOptions::default();
+
+        assert_eq!(dng_with_raw(cfa()).render(&options).err(), Some(RenderError::NoColorMatrix));
+
+        // a ColorMatrix for a 4 color camera
+        let main = thumbnail_ifd().tag(Tag::ColorMatrix_50721, Value::SRational(vec![(1, 1); 12]));
+        let four_colors = DNG::from_encoded_vec(build_tiff(Endian::Little, main, vec![cfa()]));
+        assert_eq!(four_colors.render(&options).err(), Some(RenderError::PlaneMismatch { raw: 3, color_matrix: 4 }));
+    }
+
+    #[test]
+    fn thumbnail_formats() {
+        let rgb = dng_with_raw(gray_ifd(1, 1, vec![7]));
+        assert_eq!(rgb.get_thumbnail().map(|t| t.data), Some(vec![1, 2, 3]));
+        assert_eq!(rgb.get_thumbnail_planes(), Some(vec![vec![1], vec![2], vec![3]]));
+
+        // no NewSubFileType = 1 IFD
+        let without = DNG::from_encoded_vec(build_tiff(Endian::Little, gray_ifd(1, 1, vec![7]), Vec::new()));
+        assert!(without.get_thumbnail().is_none() && without.get_thumbnail_planes().is_none());
+
+        // 16 bit RGB
+        let deep = TestIfd::new()
+            .tag(Tag::NewSubFileType_254, Value::Long(vec![1]))
+            .tag(Tag::ImageWidth_256, Value::Short(vec![1]))
+            .tag(Tag::ImageLength_257, Value::Short(vec![1]))
+            .tag(Tag::BitsPerSample_258, Value::Short(vec![16, 16, 16]))
+            .tag(Tag::PhotometricInterpretation_262, Value::Short(vec![2]))
+            .tag(Tag::SamplesPerPixel_277, Value::Short(vec![3]))
+            .strips(vec![vec![0; 6]]);
+        let deep = DNG::from_encoded_vec(build_tiff(Endian::Little, deep, vec![gray_ifd(1, 1, vec![7])]));
+        assert!(deep.get_thumbnail().is_none());
+    }
 
     #[test]
     fn open_working() {
@@ -0,0 +1,219 @@
+use crate::{raster::Raster, sample_format::SampleFormat, tags::Tag, Endian, IFD};
+
+/// Linear reference values, 0.0 is black and 1.0 is the white level. Row major with the planes of
+/// each pixel interleaved.
+#[derive(Clone)]
+pub struct LinearImage {
+    pub width: usize,
+    pub height: usize,
+    pub planes: usize,
+    pub data: Vec<f32>,
+}
+
+impl LinearImage {
+    pub fn new(width: usize, height: usize, planes: usize) -> Self {
+        Self { width, height, planes, data: vec![0.0; width * height * planes] }
+    }
+
+    pub fn get(&self, row: usize, col: usize, plane: usize) -> f32 {
+        self.data[(row * self.width + col) * self.planes + plane]
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, plane: usize, value: f32) {
+        self.data[(row * self.width + col) * self.planes + plane] = value;
+    }
+
+    /// An image of just the given planes, in the given order.
+    pub fn select_planes(&self, planes: &[usize]) -> LinearImage {
+        let data = self.data.chunks(self.planes).flat_map(|pixel| planes.iter().map(|&p| pixel[p])).collect();
+        LinearImage { width: self.width, height: self.height, planes: planes.len(), data }
+    }
+}
+
+/// The tags that map stored raw values to linear reference values, see DNG spec 1.6 Chapter 5.
+#[derive(Clone)]
+pub struct LinearizationInfo {
+    pub table: Option<Vec<u16>>,
+    pub black_level_repeat_rows: usize,
+    pub black_level_repeat_cols: usize,
+    // indexed by [row][col][sample] of the repeat pattern
+    pub black_level: Vec<f64>,
+    // one per column / row of the active area
+    pub black_level_delta_h: Vec<f64>,
+    pub black_level_delta_v: Vec<f64>,
+    pub white_level: Vec<f64>,
+    // the black level pattern and deltas are relative to the top left of the active area
+    pub active_area_top: usize,
+    pub active_area_left: usize,
+}
+
+impl LinearizationInfo {
+    /// Levels without a LinearizationTable, deltas or repeat pattern, so one black level per sample.
+    pub fn new(black_level: Vec<f64>, white_level: Vec<f64>) -> Self {
+        Self {
+            table: None,
+            black_level_repeat_rows: 1,
+            black_level_repeat_cols: 1,
+            black_level,
+            black_level_delta_h: Vec::new(),
+            black_level_delta_v: Vec::new(),
+            white_level,
+            active_area_top: 0,
+            active_area_left: 0,
+        }
+    }
+
+    pub(crate) fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian, samples_per_pixel: usize, bits_per_sample: usize) -> Self {
+        let f64s = |tag: Tag| ifd.get_values(tag, buffer, endian).map(|v| v.to_vec().iter().map(|f| f.to_f64()).collect::<Vec<f64>>());
+
+        let (black_level_repeat_rows, black_level_repeat_cols) = match ifd.get_values(Tag::BlackLevelRepeatDim_50713, buffer, endian) {
+            Some(dim) => {
+                let dim = dim.to_vec();
+                (dim[0].to_usize(), dim[1].to_usize())
+            },
+            None => (1, 1),
+        };
+        let black_level = f64s(Tag::BlackLevel_50714)
+            .unwrap_or_else(|| vec![0.0; black_level_repeat_rows * black_level_repeat_cols * samples_per_pixel]);
+        assert_eq!(black_level.len(), black_level_repeat_rows * black_level_repeat_cols * samples_per_pixel, "BlackLevel doesn't match BlackLevelRepeatDim!");
+
+        // the white level defaults to the largest value the samples can hold
+        let white_level = match f64s(Tag::WhiteLevel_50717) {
+            Some(white) if white.len() == 1 => vec![white[0]; samples_per_pixel],
+            Some(white) => white,
+            None => vec![((1u64 << bits_per_sample) - 1) as f64; samples_per_pixel],
+        };
+
+        let (active_area_top, active_area_left) = match ifd.get_values(Tag::ActiveArea_50829, buffer, endian) {
+            Some(area) => {
+                let area = area.to_vec();
+                (area[0].to_usize(), area[1].to_usize())
+            },
+            None => (0, 0),
+        };
+
+        Self {
+            table: ifd.get_values(Tag::LinearizationTable_50712, buffer, endian).map(|t| t.to_vec().iter().map(|f| f.to_u16()).collect()),
+            black_level_repeat_rows,
+            black_level_repeat_cols,
+            black_level,
+            black_level_delta_h: f64s(Tag::BlackLevelDeltaH_50715).unwrap_or_default(),
+            black_level_delta_v: f64s(Tag::BlackLevelDeltaV_50716).unwrap_or_default(),
+            white_level,
+            active_area_top,
+            active_area_left,
+        }
+    }
+
+    // For samples that were scaled on the way into the Raster, see SampleFormat::raster_conversion
+    pub(crate) fn scale_levels(&mut self, scale: f64) {
+        for level in self.black_level.iter_mut()
+            .chain(self.black_level_delta_h.iter_mut())
+            .chain(self.black_level_delta_v.iter_mut())
+            .chain(self.white_level.iter_mut()) {
+            *level *= scale;
+        }
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        self.black_level.len() / (self.black_level_repeat_rows * self.black_level_repeat_cols)
+    }
+
+    /// The black level for a sample at a position in raw image coordinates.
+    pub fn black_level_at(&self, row: usize, col: usize, sample: usize) -> f64 {
+        let pattern = self.pattern_black_level(row, col, sample);
+        let delta_h = col.checked_sub(self.active_area_left).and_then(|c| self.black_level_delta_h.get(c)).unwrap_or(&0.0);
+        let delta_v = row.checked_sub(self.active_area_top).and_then(|r| self.black_level_delta_v.get(r)).unwrap_or(&0.0);
+        pattern + delta_h + delta_v
+    }
+
+    // The BlackLevel pattern's value for a position in raw image coordinates, without the deltas
+    pub(crate) fn pattern_black_level(&self, row: usize, col: usize, sample: usize) -> f64 {
+        let r = (row as isize - self.active_area_top as isize).rem_euclid(self.black_level_repeat_rows as isize) as usize;
+        let c = (col as isize - self.active_area_left as isize).rem_euclid(self.black_level_repeat_cols as isize) as usize;
+        self.black_level[(r * self.black_level_repeat_cols + c) * self.samples_per_pixel() + sample]
+    }
+
+    fn table_value(&self, stored: u16) -> u16 {
+        match &self.table {
+            Some(table) => table[(stored as usize).min(table.len() - 1)],
+            None => stored,
+        }
+    }
+
+    /// The raster with the LinearizationTable applied, which puts it in the units of the black and
+    /// white levels.
+    pub fn apply_table(&self, raster: &Raster) -> Raster {
+        Raster { data: raster.data.iter().map(|&s| self.table_value(s)).collect(), ..raster.clone() }
+    }
+
+    // The dng_sdk scales each plane by the range above its largest black level so that nothing
+    // that's below the white level ends up clipped
+    fn max_black_level(&self, sample: usize) -> f64 {
+        let spp = self.samples_per_pixel();
+        let pattern = self.black_level.iter().skip(sample).step_by(spp).cloned().fold(f64::MIN, f64::max);
+        let delta_h = self.black_level_delta_h.iter().cloned().fold(0.0, f64::max);
+        let delta_v = self.black_level_delta_v.iter().cloned().fold(0.0, f64::max);
+        pattern + delta_h + delta_v
+    }
+}
+
+/// Applies the linearization table, subtracts the black level and scales by the white level, clipping
+/// the results to [0, 1], see DNG spec 1.6 P87.
+pub fn linearize(raster: &Raster, info: &LinearizationInfo) -> LinearImage {
+    let stored = |i: usize| info.table_value(raster.data[i]) as f64;
+    linearize_samples(raster.width, raster.height, raster.samples_per_pixel, stored, info, 1.0)
+}
+
+/// Like linearize, for floating point samples (SampleFormat = 3) as they're stored. They aren't
+/// clipped at the white level, so HDR images keep their headroom above 1.0. The levels are in the
+/// units of the 16 bit Raster like they are for linearize, see SampleFormat::raster_conversion.
+pub fn linearize_float(raster: &Raster<f32>, info: &LinearizationInfo) -> LinearImage {
+    let (scale, _, _) = SampleFormat::Float.raster_conversion(raster.bits_per_sample);
+    let stored = |i: usize| raster.data[i] as f64 * scale;
+    linearize_samples(raster.width, raster.height, raster.samples_per_pixel, stored, info, f64::INFINITY)
+}
+
+fn linearize_samples(width: usize, height: usize, spp: usize, stored: impl Fn(usize) -> f64, info: &LinearizationInfo, max: f64) -> LinearImage {
+    let scales = (0..spp).map(|s| 1.0 / (info.white_level[s] - info.max_black_level(s))).collect::<Vec<f64>>();
+
+    let mut linear = LinearImage::new(width, height, spp);
+    for row in 0..height {
+        for col in 0..width {
+            for (sample, scale) in scales.iter().enumerate() {
+                let value = stored((row * width + col) * spp + sample);
+                let normalized = (value - info.black_level_at(row, col, sample)) * scale;
+                linear.set(row, col, sample, normalized.clamp(0.0, max) as f32);
+            }
+        }
+    }
+    linear
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn black_pattern_and_white_level() {
+        let raster = Raster { width: 2, height: 2, samples_per_pixel: 1, bits_per_sample: 16, data: vec![100, 200, 1100, 4000] };
+        let info = LinearizationInfo { black_level_repeat_cols: 2, ..LinearizationInfo::new(vec![100.0, 200.0], vec![1200.0]) };
+
+        let linear = linearize(&raster, &info);
+
+        assert_eq!(linear.data, vec![0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(info.black_level_at(1, 1, 0), 200.0);
+    }
+
+    #[test]
+    fn float_headroom() {
+        // a tagged WhiteLevel of 2.0, scaled into Raster units like DNG::get_linearization_info does
+        let mut info = LinearizationInfo::new(vec![0.0], vec![2.0]);
+        info.scale_levels(65535.0);
+        let raster = Raster { width: 4, height: 1, samples_per_pixel: 1, bits_per_sample: 32, data: vec![-0.5, 1.0, 2.0, 6.0] };
+
+        let linear = linearize_float(&raster, &info);
+
+        assert_eq!(linear.data, vec![0.0, 0.5, 1.0, 3.0]);
+    }
+}
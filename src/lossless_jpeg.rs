@@ -0,0 +1,532 @@
+// See ITU T.81 (JPEG), Annex H (lossless mode of operation)
+// DNG spec 1.6 Compression P20 uses this for compression = 7 raw data
+
+pub(crate) const SOI: u8 = 0xD8;
+pub(crate) const EOI: u8 = 0xD9;
+pub(crate) const SOF3: u8 = 0xC3;
+pub(crate) const DHT: u8 = 0xC4;
+pub(crate) const SOS: u8 = 0xDA;
+pub(crate) const DRI: u8 = 0xDD;
+const RST0: u8 = 0xD0;
+const RST7: u8 = 0xD7;
+
+pub(crate) struct LosslessJpeg {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) components: usize,
+    pub(crate) samples: Vec<u16>, // row major, components interleaved
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct HuffmanTable {
+    // See T.81 F.2.2.3, F.15 (max_code, val_ptr, min_code)
+    min_code: [i32; 17],
+    max_code: [i32; 18],
+    val_ptr: [usize; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    pub(crate) fn new(counts: &[u8; 16], values: Vec<u8>) -> Self {
+        let mut table = Self { values, ..Default::default() };
+        let mut code = 0i32;
+        let mut k = 0usize;
+        for length in 1..=16 {
+            let count = counts[length - 1] as usize;
+            table.val_ptr[length] = k;
+            table.min_code[length] = code;
+            code += count as i32;
+            k += count;
+            table.max_code[length] = if count > 0 { code - 1 } else { -1 };
+            code <<= 1;
+        }
+        table.max_code[17] = i32::MAX;
+        table
+    }
+}
+
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bits: u64,
+    bit_count: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8], position: usize) -> Self {
+        Self { data, position, bits: 0, bit_count: 0 }
+    }
+
+    fn fill(&mut self) {
+        while self.bit_count <= 56 {
+            let mut byte = 0u8;
+            if self.position < self.data.len() {
+                byte = self.data[self.position];
+                if byte == 0xFF {
+                    let next = *self.data.get(self.position + 1).unwrap_or(&0);
+                    if next == 0x00 {
+                        self.position += 2;
+                    } else {
+                        // a marker, feed zeros until it's explicitly consumed
+                        byte = 0;
+                    }
+                } else {
+                    self.position += 1;
+                }
+            }
+            self.bits |= (byte as u64) << (56 - self.bit_count);
+            self.bit_count += 8;
+        }
+    }
+
+    pub(crate) fn read_bits(&mut self, count: usize) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        if self.bit_count < count {
+            self.fill();
+        }
+        let value = (self.bits >> (64 - count)) as u32;
+        self.bits <<= count;
+        self.bit_count -= count;
+        value
+    }
+
+    pub(crate) fn decode(&mut self, table: &HuffmanTable) -> u8 {
+        let mut code = self.read_bits(1) as i32;
+        let mut length = 1;
+        while code > table.max_code[length] {
+            code = (code << 1) | self.read_bits(1) as i32;
+            length += 1;
+            if length > 16 {
+                panic!("Invalid Huffman code in the JPEG data!");
+            }
+        }
+        table.values[table.val_ptr[length] + (code - table.min_code[length]) as usize]
+    }
+
+    // See T.81 F.1.2.1, Table F.1
+    pub(crate) fn receive_extend(&mut self, ssss: u8) -> i32 {
+        match ssss {
+            0 => 0,
+            16 => 32768,
+            _ => {
+                let value = self.read_bits(ssss as usize) as i32;
+                if value < 1 << (ssss - 1) {
+                    value - (1 << ssss) + 1
+                } else {
+                    value
+                }
+            }
+        }
+    }
+
+    // Drops any buffered bits and skips the RSTn marker that has to follow them.
+    pub(crate) fn restart(&mut self) {
+        self.bits = 0;
+        self.bit_count = 0;
+        while self.position + 1 < self.data.len() {
+            if self.data[self.position] == 0xFF && (RST0..=RST7).contains(&self.data[self.position + 1]) {
+                self.position += 2;
+                return;
+            }
+            self.position += 1;
+        }
+    }
+}
+
+pub(crate) fn marker_length(data: &[u8], position: usize) -> usize {
+    ((data[position] as usize) << 8) | data[position + 1] as usize
+}
+
+pub(crate) fn decode(data: &[u8]) -> LosslessJpeg {
+    assert!(data.len() > 4 && data[0] == 0xFF && data[1] == SOI, "The data isn't a JPEG stream!");
+
+    let mut tables: [Option<HuffmanTable>; 4] = Default::default();
+    let mut restart_interval = 0usize;
+    let mut width = 0;
+    let mut height = 0;
+    let mut precision = 0;
+    let mut component_ids: Vec<u8> = Vec::new();
+    let mut position = 2;
+
+    loop {
+        while data[position] != 0xFF || data[position + 1] == 0xFF {
+            position += 1;
+        }
+        let marker = data[position + 1];
+        position += 2;
+        match marker {
+            SOF3 => {
+                precision = data[position + 2] as usize;
+                height = marker_length(data, position + 3);
+                width = marker_length(data, position + 5);
+                let count = data[position + 7] as usize;
+                component_ids = (0..count).map(|i| data[position + 8 + i * 3]).collect();
+                position += marker_length(data, position);
+            },
+            0xC0..=0xCF if marker != DHT && marker != 0xC8 && marker != 0xCC => {
+                panic!("Only lossless (SOF3) JPEG data is supported!")
+            },
+            DHT => {
+                let end = position + marker_length(data, position);
+                let mut p = position + 2;
+                while p < end {
+                    let id = (data[p] & 0x0F) as usize;
+                    let mut counts = [0u8; 16];
+                    counts.copy_from_slice(&data[p + 1..p + 17]);
+                    let total = counts.iter().map(|&c| c as usize).sum::<usize>();
+                    let values = data[p + 17..p + 17 + total].to_vec();
+                    tables[id] = Some(HuffmanTable::new(&counts, values));
+                    p += 17 + total;
+                }
+                position = end;
+            },
+            DRI => {
+                restart_interval = marker_length(data, position + 2);
+                position += marker_length(data, position);
+            },
+            SOS => {
+                let count = data[position + 2] as usize;
+                let mut scan_tables = Vec::with_capacity(count);
+                for i in 0..count {
+                    let table = (data[position + 4 + i * 2] >> 4) as usize;
+                    scan_tables.push(tables[table].clone().expect("The scan references a missing Huffman table!"));
+                }
+                let predictor = data[position + 3 + count * 2];
+                let point_transform = (data[position + 5 + count * 2] & 0x0F) as usize;
+                assert_eq!(count, component_ids.len(), "Only single scan lossless JPEG data is supported!");
+                position += marker_length(data, position);
+
+                let samples = decode_scan(
+                    &data[position..], &scan_tables, width, height, precision, predictor, point_transform, restart_interval
+                );
+                return LosslessJpeg { width, height, components: count, samples };
+            },
+            EOI => panic!("Reached the end of the JPEG data without finding a scan!"),
+            _ => {
+                position += marker_length(data, position);
+            }
+        }
+    }
+}
+
+// See T.81 H.1.2.1, Table H.1
+fn predict(predictor: u8, ra: i32, rb: i32, rc: i32) -> i32 {
+    match predictor {
+        1 => ra,
+        2 => rb,
+        3 => rc,
+        4 => ra + rb - rc,
+        5 => ra + ((rb - rc) >> 1),
+        6 => rb + ((ra - rc) >> 1),
+        7 => (ra + rb) >> 1,
+        _ => panic!("That isn't a valid lossless JPEG predictor!")
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn decode_scan(
+    data: &[u8],
+    tables: &[HuffmanTable],
+    width: usize,
+    height: usize,
+    precision: usize,
+    predictor: u8,
+    point_transform: usize,
+    restart_interval: usize,
+) -> Vec<u16> {
+    let components = tables.len();
+    let row_length = width * components;
+    let initial = 1i32 << (precision - point_transform - 1);
+    let mut samples = vec![0u16; row_length * height];
+    let mut reader = BitReader::new(data, 0);
+    let mut mcu_count = 0usize;
+    // See T.81 H.1.2.1, the image and every restart interval start out with the initial prediction
+    // and then predict from the left until the end of that line
+    let mut use_initial = true;
+    let mut first_line = true;
+
+    for row in 0..height {
+        for col in 0..width {
+            if restart_interval > 0 && mcu_count > 0 && mcu_count.is_multiple_of(restart_interval) {
+                reader.restart();
+                use_initial = true;
+                first_line = true;
+            }
+            for (c, table) in tables.iter().enumerate() {
+                let index = row * row_length + col * components + c;
+                let prediction = if use_initial {
+                    initial
+                } else if first_line {
+                    samples[index - components] as i32
+                } else if col == 0 {
+                    samples[index - row_length] as i32
+                } else {
+                    let ra = samples[index - components] as i32;
+                    let rb = samples[index - row_length] as i32;
+                    let rc = samples[index - row_length - components] as i32;
+                    predict(predictor, ra, rb, rc)
+                };
+                let ssss = reader.decode(table);
+                let diff = reader.receive_extend(ssss);
+                samples[index] = ((prediction + diff) & 0xFFFF) as u16;
+            }
+            use_initial = false;
+            mcu_count += 1;
+        }
+        first_line = false;
+    }
+
+    if point_transform > 0 {
+        for sample in samples.iter_mut() {
+            *sample <<= point_transform;
+        }
+    }
+    samples
+}
+
+fn category(diff: i32) -> u8 {
+    match diff {
+        0 => 0,
+        32768 => 16,
+        _ => (32 - diff.unsigned_abs().leading_zeros()) as u8,
+    }
+}
+
+// See T.81 K.2, code lengths limited to 16 bits with the all ones code reserved
+fn huffman_table(frequencies: &[usize; 17]) -> ([u8; 16], Vec<u8>) {
+    let mut freq = [0usize; 18];
+    freq[..17].copy_from_slice(frequencies);
+    freq[17] = 1;
+    let mut code_size = [0usize; 18];
+    let mut others = [-1i32; 18];
+
+    loop {
+        let mut v1: Option<usize> = None;
+        let mut v2: Option<usize> = None;
+        for v in 0..18 {
+            if freq[v] == 0 {
+                continue;
+            }
+            match v1 {
+                Some(u) if freq[v] > freq[u] => {},
+                _ => v1 = Some(v),
+            }
+        }
+        for v in 0..18 {
+            if freq[v] == 0 || Some(v) == v1 {
+                continue;
+            }
+            match v2 {
+                Some(u) if freq[v] > freq[u] => {},
+                _ => v2 = Some(v),
+            }
+        }
+        let (mut v1, mut v2) = match (v1, v2) {
+            (Some(v1), Some(v2)) => (v1, v2),
+            _ => break,
+        };
+        freq[v1] += freq[v2];
+        freq[v2] = 0;
+        code_size[v1] += 1;
+        while others[v1] >= 0 {
+            v1 = others[v1] as usize;
+            code_size[v1] += 1;
+        }
+        others[v1] = v2 as i32;
+        code_size[v2] += 1;
+        while others[v2] >= 0 {
+            v2 = others[v2] as usize;
+            code_size[v2] += 1;
+        }
+    }
+
+    let mut bits = [0usize; 33];
+    for &size in code_size.iter().filter(|&&s| s > 0) {
+        bits[size] += 1;
+    }
+    for i in (17..=32).rev() {
+        while bits[i] > 0 {
+            let mut j = i - 2;
+            while bits[j] == 0 {
+                j -= 1;
+            }
+            bits[i] -= 2;
+            bits[i - 1] += 1;
+            bits[j + 1] += 2;
+            bits[j] -= 1;
+        }
+    }
+    let mut i = 16;
+    while bits[i] == 0 {
+        i -= 1;
+    }
+    bits[i] -= 1;
+
+    let mut values = Vec::new();
+    for size in 1..=32 {
+        for (symbol, &s) in code_size.iter().enumerate().take(17) {
+            if s == size {
+                values.push(symbol as u8);
+            }
+        }
+    }
+    let mut counts = [0u8; 16];
+    for length in 1..=16 {
+        counts[length - 1] = bits[length] as u8;
+    }
+    (counts, values)
+}
+
+struct BitWriter {
+    data: Vec<u8>,
+    bits: u32,
+    bit_count: usize,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            self.bits = (self.bits << 1) | ((value >> i) & 1);
+            self.bit_count += 1;
+            if self.bit_count == 8 {
+                self.push_byte();
+            }
+        }
+    }
+
+    fn push_byte(&mut self) {
+        let byte = self.bits as u8;
+        self.data.push(byte);
+        if byte == 0xFF {
+            self.data.push(0x00);
+        }
+        self.bits = 0;
+        self.bit_count = 0;
+    }
+
+    fn flush(&mut self) {
+        while self.bit_count != 0 {
+            self.write_bits(1, 1);
+        }
+    }
+}
+
+fn push_marker(data: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    data.extend([0xFF, marker]);
+    data.extend(((payload.len() + 2) as u16).to_be_bytes());
+    data.extend(payload);
+}
+
+// Uses predictor 1 and a single Huffman table that's optimized for the data
+pub(crate) fn encode(samples: &[u16], width: usize, height: usize, components: usize, precision: usize) -> Vec<u8> {
+    assert_eq!(samples.len(), width * height * components, "The sample count doesn't match the dimensions!");
+    assert!((2..=16).contains(&precision), "Lossless JPEG precision has to be between 2 and 16 bits!");
+
+    let row_length = width * components;
+    let initial = 1i32 << (precision - 1);
+    let mut diffs = Vec::with_capacity(samples.len());
+    for row in 0..height {
+        for col in 0..width {
+            for c in 0..components {
+                let index = row * row_length + col * components + c;
+                let prediction = match (row, col) {
+                    (0, 0) => initial,
+                    (_, 0) => samples[index - row_length] as i32,
+                    _ => samples[index - components] as i32,
+                };
+                let mut diff = (samples[index] as i32 - prediction) & 0xFFFF;
+                if diff > 32768 {
+                    diff -= 65536;
+                }
+                diffs.push(diff);
+            }
+        }
+    }
+
+    let mut frequencies = [0usize; 17];
+    for &diff in &diffs {
+        frequencies[category(diff) as usize] += 1;
+    }
+    let (counts, values) = huffman_table(&frequencies);
+    let mut codes = [(0u32, 0usize); 17];
+    let mut code = 0u32;
+    let mut k = 0;
+    for (length, &count) in counts.iter().enumerate() {
+        for _ in 0..count {
+            codes[values[k] as usize] = (code, length + 1);
+            code += 1;
+            k += 1;
+        }
+        code <<= 1;
+    }
+
+    let mut data = vec![0xFF, SOI];
+    let mut frame = vec![precision as u8];
+    frame.extend((height as u16).to_be_bytes());
+    frame.extend((width as u16).to_be_bytes());
+    frame.push(components as u8);
+    for c in 0..components {
+        frame.extend([c as u8, 0x11, 0]);
+    }
+    push_marker(&mut data, SOF3, &frame);
+
+    let mut table = vec![0x00];
+    table.extend(counts);
+    table.extend(&values);
+    push_marker(&mut data, DHT, &table);
+
+    let mut scan = vec![components as u8];
+    for c in 0..components {
+        scan.extend([c as u8, 0x00]);
+    }
+    scan.extend([1, 0, 0]);
+    push_marker(&mut data, SOS, &scan);
+
+    let mut writer = BitWriter { data, bits: 0, bit_count: 0 };
+    for &diff in &diffs {
+        let ssss = category(diff);
+        let (code, length) = codes[ssss as usize];
+        writer.write_bits(code, length);
+        if ssss > 0 && ssss < 16 {
+            let extra = if diff < 0 { diff - 1 } else { diff };
+            writer.write_bits(extra as u32 & ((1 << ssss) - 1), ssss as usize);
+        }
+    }
+    writer.flush();
+
+    let mut data = writer.data;
+    data.extend([0xFF, EOI]);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let width = 7;
+        let height = 5;
+        let components = 2;
+        let samples = (0..width * height * components).map(|i| ((i * 7919) % 4096) as u16).collect::<Vec<u16>>();
+
+        let encoded = encode(&samples, width, height, components, 12);
+        let decoded = decode(&encoded);
+
+        assert_eq!(decoded.width, width);
+        assert_eq!(decoded.height, height);
+        assert_eq!(decoded.components, components);
+        assert_eq!(decoded.samples, samples);
+    }
+
+    #[test]
+    fn round_trip_full_range() {
+        let samples = vec![0u16, 65535, 0, 32768, 65535, 1, 12345, 54321, 0];
+
+        let decoded = decode(&encode(&samples, 3, 3, 1, 16));
+
+        assert_eq!(decoded.samples, samples);
+    }
+}
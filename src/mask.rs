@@ -0,0 +1,89 @@
+use image::Image;
+
+use crate::{geometry::Geometry, linearize::LinearImage, raster::Raster};
+
+/// A transparency mask (NewSubFileType = 4) as alpha in [0, 1], where 0 is fully transparent, lined up
+/// with the active area of the main image, see DNG spec 1.6 P19.
+pub fn mask_to_alpha(mask: &Raster, geometry: &Geometry) -> LinearImage {
+    let max = ((1u64 << mask.bits_per_sample) - 1) as f32;
+    let alpha = LinearImage {
+        width: mask.width,
+        height: mask.height,
+        planes: 1,
+        data: mask.data.iter().step_by(mask.samples_per_pixel).map(|&v| v as f32 / max).collect(),
+    };
+    let active = geometry.active_area;
+    if (alpha.width, alpha.height) == (geometry.width, geometry.height) {
+        alpha.crop(&active)
+    } else {
+        alpha.resize_nearest(active.width(), active.height())
+    }
+}
+
+impl LinearImage {
+    pub fn resize_nearest(&self, width: usize, height: usize) -> LinearImage {
+        let mut resized = LinearImage::new(width, height, self.planes);
+        for row in 0..height {
+            let r = (row * self.height / height).min(self.height - 1);
+            for col in 0..width {
+                let c = (col * self.width / width).min(self.width - 1);
+                for plane in 0..self.planes {
+                    resized.set(row, col, plane, self.get(r, c, plane));
+                }
+            }
+        }
+        resized
+    }
+}
+
+/// Alpha in [0, 1] as 8 bit samples, for exports.
+pub fn alpha_to_u8(alpha: &LinearImage) -> Vec<u8> {
+    alpha.data.iter().map(|&a| (a.clamp(0.0, 1.0) * 255.0).round() as u8).collect()
+}
+
+/// Alpha in [0, 1] as 16 bit samples, for exports that go with render_gray16.
+pub fn alpha_to_u16(alpha: &LinearImage) -> Vec<u16> {
+    alpha.data.iter().map(|&a| (a.clamp(0.0, 1.0) * 65535.0).round() as u16).collect()
+}
+
+/// Blends the transparent parts of an 8 bit RGB image into a solid matte color.
+pub fn apply_matte(image: &Image, alpha: &[u8], matte: [u8; 3]) -> Image {
+    let data = image.data.chunks(3).zip(alpha)
+        .flat_map(|(pixel, &a)| {
+            let a = a as u32;
+            [0, 1, 2].map(|i| ((pixel[i] as u32 * a + matte[i] as u32 * (255 - a) + 127) / 255) as u8)
+        })
+        .collect();
+    Image { data, width: image.width, height: image.height }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Rect;
+
+    #[test]
+    fn mask_lines_up_with_active_area() {
+        // an 8 bit mask over a 4x2 raw image with a 2x2 active area on the right
+        let mask = Raster { width: 4, height: 2, samples_per_pixel: 1, bits_per_sample: 8, data: vec![0, 0, 255, 0, 0, 0, 0, 255] };
+        let geometry = Geometry::new(4, 2, Rect { top: 0, left: 2, bottom: 2, right: 4 });
+
+        let alpha = mask_to_alpha(&mask, &geometry);
+
+        assert_eq!(alpha.data, vec![1.0, 0.0, 0.0, 1.0]);
+        let matted = apply_matte(&Image { data: vec![200; 12], width: 2, height: 2 }, &alpha_to_u8(&alpha), [0, 0, 0]);
+        assert_eq!(matted.data, vec![200, 200, 200, 0, 0, 0, 0, 0, 0, 200, 200, 200]);
+    }
+
+    #[test]
+    fn alpha_depths() {
+        // 32 bit masks don't overflow the maximum
+        let mask = Raster { width: 2, height: 1, samples_per_pixel: 1, bits_per_sample: 32, data: vec![0, 0] };
+        let geometry = Geometry::new(2, 1, Rect { top: 0, left: 0, bottom: 1, right: 2 });
+        assert_eq!(mask_to_alpha(&mask, &geometry).data, vec![0.0, 0.0]);
+
+        let alpha = LinearImage { width: 3, height: 1, planes: 1, data: vec![0.0, 0.5, 1.0] };
+        assert_eq!(alpha_to_u16(&alpha), vec![0, 32768, 65535]);
+        assert_eq!(alpha_to_u8(&alpha), vec![0, 128, 255]);
+    }
+}
@@ -0,0 +1,164 @@
+use std::ops::{Add, Mul};
+
+/// A small dense row major matrix, for the color math's 3xN, Nx3 and NxN matrices.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn new(rows: usize, cols: usize, data: Vec<f64>) -> Self {
+        assert_eq!(data.len(), rows * cols, "The matrix data doesn't match its size!");
+        Self { rows, cols, data }
+    }
+
+    pub fn identity(size: usize) -> Self {
+        Self::diagonal(&vec![1.0; size])
+    }
+
+    pub fn diagonal(values: &[f64]) -> Self {
+        let size = values.len();
+        let mut data = vec![0.0; size * size];
+        for (i, value) in values.iter().enumerate() {
+            data[i * size + i] = *value;
+        }
+        Self { rows: size, cols: size, data }
+    }
+
+    pub fn column(values: &[f64]) -> Self {
+        Self { rows: values.len(), cols: 1, data: values.to_vec() }
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.data[row * self.cols + col]
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut data = Vec::with_capacity(self.data.len());
+        for col in 0..self.cols {
+            for row in 0..self.rows {
+                data.push(self.get(row, col));
+            }
+        }
+        Self { rows: self.cols, cols: self.rows, data }
+    }
+
+    pub fn scale(&self, factor: f64) -> Self {
+        Self { data: self.data.iter().map(|v| v * factor).collect(), ..self.clone() }
+    }
+
+    pub fn max_entry(&self) -> f64 {
+        self.data.iter().cloned().fold(f64::MIN, f64::max)
+    }
+
+    pub fn apply(&self, vector: &[f64]) -> Vec<f64> {
+        assert_eq!(vector.len(), self.cols, "The vector doesn't match the matrix!");
+        (0..self.rows).map(|row| (0..self.cols).map(|col| self.get(row, col) * vector[col]).sum()).collect()
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, panics for singular matrices.
+    pub fn inverse(&self) -> Self {
+        assert_eq!(self.rows, self.cols, "Only square matrices can be inverted!");
+        let n = self.rows;
+        let mut a = self.data.clone();
+        let mut inverse = Self::identity(n).data;
+        for col in 0..n {
+            let pivot = (col..n).max_by(|&i, &j| a[i * n + col].abs().partial_cmp(&a[j * n + col].abs()).unwrap()).unwrap();
+            assert!(a[pivot * n + col].abs() > 1e-12, "The matrix can't be inverted!");
+            for k in 0..n {
+                a.swap(col * n + k, pivot * n + k);
+                inverse.swap(col * n + k, pivot * n + k);
+            }
+            let divisor = a[col * n + col];
+            for k in 0..n {
+                a[col * n + k] /= divisor;
+                inverse[col * n + k] /= divisor;
+            }
+            for row in 0..n {
+                if row == col {
+                    continue;
+                }
+                let factor = a[row * n + col];
+                for k in 0..n {
+                    a[row * n + k] -= factor * a[col * n + k];
+                    inverse[row * n + k] -= factor * inverse[col * n + k];
+                }
+            }
+        }
+        Self { rows: n, cols: n, data: inverse }
+    }
+
+    /// The inverse for square matrices, otherwise the inverse through the reduction matrix when there is
+    /// one or the pseudo inverse when there isn't, see DNG spec 1.6 P90.
+    pub fn inverse_with(&self, reduction: Option<&Matrix>) -> Self {
+        if self.rows == self.cols {
+            self.inverse()
+        } else if let Some(reduction) = reduction {
+            (reduction * self).inverse() * reduction.clone()
+        } else {
+            let transpose = self.transpose();
+            (&transpose * self).inverse() * transpose
+        }
+    }
+}
+
+impl Mul for &Matrix {
+    type Output = Matrix;
+
+    fn mul(self, other: &Matrix) -> Matrix {
+        assert_eq!(self.cols, other.rows, "The matrices can't be multiplied!");
+        let mut data = vec![0.0; self.rows * other.cols];
+        for row in 0..self.rows {
+            for col in 0..other.cols {
+                data[row * other.cols + col] = (0..self.cols).map(|k| self.get(row, k) * other.get(k, col)).sum();
+            }
+        }
+        Matrix { rows: self.rows, cols: other.cols, data }
+    }
+}
+
+impl Mul for Matrix {
+    type Output = Matrix;
+
+    fn mul(self, other: Matrix) -> Matrix {
+        &self * &other
+    }
+}
+
+impl Add for &Matrix {
+    type Output = Matrix;
+
+    fn add(self, other: &Matrix) -> Matrix {
+        assert!(self.rows == other.rows && self.cols == other.cols, "The matrices can't be added!");
+        Matrix { rows: self.rows, cols: self.cols, data: self.data.iter().zip(&other.data).map(|(a, b)| a + b).collect() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverse() {
+        let m = Matrix::new(3, 3, vec![2.0, 0.0, 1.0, 1.0, 3.0, 0.0, 0.0, 1.0, 4.0]);
+
+        let product = &m * &m.inverse();
+
+        for (a, b) in product.data.iter().zip(Matrix::identity(3).data.iter()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn pseudo_inverse() {
+        let m = Matrix::new(4, 3, vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0]);
+
+        let product = &m.inverse_with(None) * &m;
+
+        for (a, b) in product.data.iter().zip(Matrix::identity(3).data.iter()) {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
+}
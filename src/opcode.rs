@@ -0,0 +1,645 @@
+use std::fmt;
+
+use crate::{area::{self, GainMap}, bad_pixels, geometry::Rect, get_value, lens, linearize::LinearImage, raster::Raster, tags::Tag, Endian, IFD};
+
+/// The part of the image an opcode works on, see DNG spec 1.6 MapTable P103. Only every row_pitch
+/// row and col_pitch column, starting at top / left, is processed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AreaSpec {
+    pub top: u32,
+    pub left: u32,
+    pub bottom: u32,
+    pub right: u32,
+    pub plane: u32,
+    pub planes: u32,
+    pub row_pitch: u32,
+    pub col_pitch: u32,
+}
+
+/// The coefficients for one plane of WarpRectilinear, radial kr0 - kr3 and tangential kt0 - kt1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RectilinearCoefficients {
+    pub radial: [f64; 4],
+    pub tangential: [f64; 2],
+}
+
+/// The coefficients for one plane of WarpRectilinear2, which allows a higher order radial polynomial
+/// that's only valid between two radii.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rectilinear2Coefficients {
+    pub radial: [f64; 15],
+    pub tangential: [f64; 2],
+    pub min_valid_radius: f64,
+    pub max_valid_radius: f64,
+}
+
+/// The operation and parameters of an opcode, see DNG spec 1.6 Chapter 7.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpcodeKind {
+    // the center is normalized, (0.5, 0.5) is the center of the image
+    WarpRectilinear { planes: Vec<RectilinearCoefficients>, center: (f64, f64) },
+    WarpFisheye { planes: Vec<[f64; 4]>, center: (f64, f64) },
+    FixVignetteRadial { k: [f64; 5], center: (f64, f64) },
+    FixBadPixelsConstant { constant: u32, bayer_phase: u32 },
+    // points are (row, column), rectangles are (top, left, bottom, right)
+    FixBadPixelsList { bayer_phase: u32, points: Vec<(u32, u32)>, rects: Vec<(u32, u32, u32, u32)> },
+    TrimBounds { top: u32, left: u32, bottom: u32, right: u32 },
+    MapTable { area: AreaSpec, table: Vec<u16> },
+    MapPolynomial { area: AreaSpec, coefficients: Vec<f64> },
+    GainMap { area: AreaSpec, map: GainMap },
+    DeltaPerRow { area: AreaSpec, deltas: Vec<f32> },
+    DeltaPerColumn { area: AreaSpec, deltas: Vec<f32> },
+    ScalePerRow { area: AreaSpec, scales: Vec<f32> },
+    ScalePerColumn { area: AreaSpec, scales: Vec<f32> },
+    WarpRectilinear2 { planes: Vec<Rectilinear2Coefficients>, center: (f64, f64), reciprocal_radial: bool },
+    // opcodes this crate doesn't know, with their parameters untouched
+    Unknown { id: u32, parameters: Vec<u8> },
+}
+
+impl OpcodeKind {
+    pub fn id(&self) -> u32 {
+        use OpcodeKind::*;
+        match self {
+            WarpRectilinear { .. } => 1,
+            WarpFisheye { .. } => 2,
+            FixVignetteRadial { .. } => 3,
+            FixBadPixelsConstant { .. } => 4,
+            FixBadPixelsList { .. } => 5,
+            TrimBounds { .. } => 6,
+            MapTable { .. } => 7,
+            MapPolynomial { .. } => 8,
+            GainMap { .. } => 9,
+            DeltaPerRow { .. } => 10,
+            DeltaPerColumn { .. } => 11,
+            ScalePerRow { .. } => 12,
+            ScalePerColumn { .. } => 13,
+            WarpRectilinear2 { .. } => 14,
+            Unknown { id, .. } => *id,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Opcode {
+    // the earliest DNG version that knows about the opcode
+    pub dng_version: [u8; 4],
+    // readers that don't know an optional opcode can skip it, otherwise they have to give up
+    pub optional: bool,
+    // the opcode can be skipped when rendering previews
+    pub preview_skippable: bool,
+    pub kind: OpcodeKind,
+}
+
+/// Which of the three opcode lists, they're applied at different points of the pipeline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpcodeListStage {
+    // on the raw image as stored
+    Raw,
+    // after linearization
+    Linear,
+    // after demosaicing
+    Demosaiced,
+}
+
+impl OpcodeListStage {
+    fn tag(&self) -> Tag {
+        match self {
+            OpcodeListStage::Raw => Tag::OpcodeList_51008,
+            OpcodeListStage::Linear => Tag::OpcodeList_51009,
+            OpcodeListStage::Demosaiced => Tag::OpcodeList_51022,
+        }
+    }
+}
+
+// Opcode lists are always big endian, whatever the file's byte order is. Every read is None when
+// it would go past the end of the buffer.
+struct Reader<'a> {
+    buffer: &'a Vec<u8>,
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn remaining(&self) -> usize {
+        self.buffer.len() - self.offset
+    }
+
+    // the offset of the next `size` bytes
+    fn take(&mut self, size: usize) -> Option<usize> {
+        if self.remaining() < size {
+            return None;
+        }
+        self.offset += size;
+        Some(self.offset - size)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let offset = self.take(4)?;
+        Some(get_value::long(self.buffer, offset, &Endian::Big))
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let offset = self.take(2)?;
+        Some(get_value::short(self.buffer, offset, &Endian::Big))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        let offset = self.take(4)?;
+        Some(get_value::float(self.buffer, offset, &Endian::Big))
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        let offset = self.take(8)?;
+        Some(get_value::double(self.buffer, offset, &Endian::Big))
+    }
+
+    fn f64s<const N: usize>(&mut self) -> Option<[f64; N]> {
+        let mut values = [0.0; N];
+        for v in &mut values {
+            *v = self.f64()?;
+        }
+        Some(values)
+    }
+
+    // a count of items that are `size` bytes each, which all have to fit in what's left
+    fn count(&mut self, size: usize) -> Option<usize> {
+        let count = self.u32()? as usize;
+        fits(count, size, self.remaining())
+    }
+
+    fn area(&mut self) -> Option<AreaSpec> {
+        Some(AreaSpec {
+            top: self.u32()?,
+            left: self.u32()?,
+            bottom: self.u32()?,
+            right: self.u32()?,
+            plane: self.u32()?,
+            planes: self.u32()?,
+            row_pitch: self.u32()?,
+            col_pitch: self.u32()?,
+        })
+    }
+
+    fn center(&mut self) -> Option<(f64, f64)> {
+        Some((self.f64()?, self.f64()?))
+    }
+}
+
+fn fits(count: usize, size: usize, remaining: usize) -> Option<usize> {
+    (count.checked_mul(size)? <= remaining).then_some(count)
+}
+
+// None when the parameters are too short for what they say they hold
+fn parse_kind(id: u32, parameters: &Vec<u8>) -> Option<OpcodeKind> {
+    use OpcodeKind::*;
+    let mut r = Reader { buffer: parameters, offset: 0 };
+    Some(match id {
+        1 => {
+            let n = r.count(6 * 8)?;
+            let planes = (0..n).map(|_| Some(RectilinearCoefficients { radial: r.f64s()?, tangential: r.f64s()? })).collect::<Option<_>>()?;
+            WarpRectilinear { planes, center: r.center()? }
+        },
+        2 => {
+            let n = r.count(4 * 8)?;
+            let planes = (0..n).map(|_| r.f64s()).collect::<Option<_>>()?;
+            WarpFisheye { planes, center: r.center()? }
+        },
+        3 => FixVignetteRadial { k: r.f64s()?, center: r.center()? },
+        4 => FixBadPixelsConstant { constant: r.u32()?, bayer_phase: r.u32()? },
+        5 => {
+            let bayer_phase = r.u32()?;
+            let point_count = r.u32()? as usize;
+            let rect_count = r.u32()? as usize;
+            fits(point_count.checked_mul(2)?.checked_add(rect_count.checked_mul(4)?)?, 4, r.remaining())?;
+            let points = (0..point_count).map(|_| Some((r.u32()?, r.u32()?))).collect::<Option<_>>()?;
+            let rects = (0..rect_count).map(|_| Some((r.u32()?, r.u32()?, r.u32()?, r.u32()?))).collect::<Option<_>>()?;
+            FixBadPixelsList { bayer_phase, points, rects }
+        },
+        6 => TrimBounds { top: r.u32()?, left: r.u32()?, bottom: r.u32()?, right: r.u32()? },
+        7 => {
+            let area = r.area()?;
+            let size = r.count(2)?;
+            MapTable { area, table: (0..size).map(|_| r.u16()).collect::<Option<_>>()? }
+        },
+        8 => {
+            let area = r.area()?;
+            let degree = r.u32()? as usize;
+            let count = fits(degree.checked_add(1)?, 8, r.remaining())?;
+            MapPolynomial { area, coefficients: (0..count).map(|_| r.f64()).collect::<Option<_>>()? }
+        },
+        9 => {
+            let area = r.area()?;
+            let points_v = r.u32()? as usize;
+            let points_h = r.u32()? as usize;
+            let spacing_v = r.f64()?;
+            let spacing_h = r.f64()?;
+            let origin_v = r.f64()?;
+            let origin_h = r.f64()?;
+            let planes = r.u32()? as usize;
+            let count = fits(points_v.checked_mul(points_h)?.checked_mul(planes)?, 4, r.remaining())?;
+            let gains = (0..count).map(|_| r.f32()).collect::<Option<_>>()?;
+            let map = area::GainMap { points_v, points_h, spacing_v, spacing_h, origin_v, origin_h, planes, gains };
+            GainMap { area, map }
+        },
+        10..=13 => {
+            let area = r.area()?;
+            let count = r.count(4)?;
+            let values = (0..count).map(|_| r.f32()).collect::<Option<_>>()?;
+            match id {
+                10 => DeltaPerRow { area, deltas: values },
+                11 => DeltaPerColumn { area, deltas: values },
+                12 => ScalePerRow { area, scales: values },
+                _ => ScalePerColumn { area, scales: values },
+            }
+        },
+        14 => {
+            let n = r.count(19 * 8)?;
+            let planes = (0..n)
+                .map(|_| Some(Rectilinear2Coefficients {
+                    radial: r.f64s()?,
+                    tangential: r.f64s()?,
+                    min_valid_radius: r.f64()?,
+                    max_valid_radius: r.f64()?,
+                }))
+                .collect::<Option<_>>()?;
+            let center = r.center()?;
+            WarpRectilinear2 { planes, center, reciprocal_radial: r.u32()? != 0 }
+        },
+        _ => Unknown { id, parameters: parameters.clone() },
+    })
+}
+
+/// Parses an OpcodeList1/2/3 payload, see DNG spec 1.6 Chapter 7 P99.
+pub fn parse_opcode_list(bytes: &Vec<u8>) -> Result<Vec<Opcode>, OpcodeError> {
+    let mut r = Reader { buffer: bytes, offset: 0 };
+    // every opcode has at least a 16 byte header
+    let count = r.count(16).ok_or(OpcodeError::Truncated)?;
+    let mut opcodes = Vec::with_capacity(count);
+    for _ in 0..count {
+        let header = (r.u32(), r.u32(), r.u32(), r.u32());
+        let (Some(id), Some(dng_version), Some(flags), Some(size)) = header else {
+            return Err(OpcodeError::Truncated);
+        };
+        let offset = r.take(size as usize).ok_or(OpcodeError::Truncated)?;
+        let parameters = bytes[offset..offset + size as usize].to_vec();
+        opcodes.push(Opcode {
+            dng_version: dng_version.to_be_bytes(),
+            optional: flags & 1 != 0,
+            preview_skippable: flags & 2 != 0,
+            kind: parse_kind(id, &parameters).ok_or(OpcodeError::Malformed(id))?,
+        });
+    }
+    Ok(opcodes)
+}
+
+// The inverse of Reader
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u32(&mut self, v: u32) {
+        self.bytes.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.bytes.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.bytes.extend_from_slice(&v.to_be_bytes());
+    }
+
+    fn f64s(&mut self, values: &[f64]) {
+        for v in values {
+            self.bytes.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    fn area(&mut self, area: &AreaSpec) {
+        for v in [area.top, area.left, area.bottom, area.right, area.plane, area.planes, area.row_pitch, area.col_pitch] {
+            self.u32(v);
+        }
+    }
+}
+
+fn encode_kind(kind: &OpcodeKind) -> Vec<u8> {
+    use OpcodeKind::*;
+    let mut w = Writer::default();
+    match kind {
+        WarpRectilinear { planes, center } => {
+            w.u32(planes.len() as u32);
+            for plane in planes {
+                w.f64s(&plane.radial);
+                w.f64s(&plane.tangential);
+            }
+            w.f64s(&[center.0, center.1]);
+        },
+        WarpFisheye { planes, center } => {
+            w.u32(planes.len() as u32);
+            for plane in planes {
+                w.f64s(plane);
+            }
+            w.f64s(&[center.0, center.1]);
+        },
+        FixVignetteRadial { k, center } => {
+            w.f64s(k);
+            w.f64s(&[center.0, center.1]);
+        },
+        FixBadPixelsConstant { constant, bayer_phase } => {
+            w.u32(*constant);
+            w.u32(*bayer_phase);
+        },
+        FixBadPixelsList { bayer_phase, points, rects } => {
+            w.u32(*bayer_phase);
+            w.u32(points.len() as u32);
+            w.u32(rects.len() as u32);
+            for &(row, col) in points {
+                w.u32(row);
+                w.u32(col);
+            }
+            for &(top, left, bottom, right) in rects {
+                [top, left, bottom, right].into_iter().for_each(|v| w.u32(v));
+            }
+        },
+        TrimBounds { top, left, bottom, right } => [*top, *left, *bottom, *right].into_iter().for_each(|v| w.u32(v)),
+        MapTable { area, table } => {
+            w.area(area);
+            w.u32(table.len() as u32);
+            table.iter().for_each(|&v| w.u16(v));
+        },
+        MapPolynomial { area, coefficients } => {
+            // DNG spec 1.6 P105, the degree and then degree + 1 coefficients
+            assert!(!coefficients.is_empty(), "A MapPolynomial needs at least one coefficient!");
+            w.area(area);
+            w.u32(coefficients.len() as u32 - 1);
+            w.f64s(coefficients);
+        },
+        GainMap { area, map } => {
+            w.area(area);
+            w.u32(map.points_v as u32);
+            w.u32(map.points_h as u32);
+            w.f64s(&[map.spacing_v, map.spacing_h, map.origin_v, map.origin_h]);
+            w.u32(map.planes as u32);
+            map.gains.iter().for_each(|&g| w.f32(g));
+        },
+        DeltaPerRow { area, deltas: values } | DeltaPerColumn { area, deltas: values }
+        | ScalePerRow { area, scales: values } | ScalePerColumn { area, scales: values } => {
+            w.area(area);
+            w.u32(values.len() as u32);
+            values.iter().for_each(|&v| w.f32(v));
+        },
+        WarpRectilinear2 { planes, center, reciprocal_radial } => {
+            w.u32(planes.len() as u32);
+            for plane in planes {
+                w.f64s(&plane.radial);
+                w.f64s(&plane.tangential);
+                w.f64s(&[plane.min_valid_radius, plane.max_valid_radius]);
+            }
+            w.f64s(&[center.0, center.1]);
+            w.u32(*reciprocal_radial as u32);
+        },
+        Unknown { parameters, .. } => w.bytes.extend_from_slice(parameters),
+    }
+    w.bytes
+}
+
+/// The inverse of parse_opcode_list, for writing an OpcodeList1/2/3 tag.
+pub fn encode_opcode_list(opcodes: &[Opcode]) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.u32(opcodes.len() as u32);
+    for opcode in opcodes {
+        let parameters = encode_kind(&opcode.kind);
+        w.u32(opcode.kind.id());
+        w.bytes.extend_from_slice(&opcode.dng_version);
+        w.u32(opcode.optional as u32 | (opcode.preview_skippable as u32) << 1);
+        w.u32(parameters.len() as u32);
+        w.bytes.extend_from_slice(&parameters);
+    }
+    w.bytes
+}
+
+pub(crate) fn read_opcode_list(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian, stage: OpcodeListStage) -> Result<Vec<Opcode>, OpcodeError> {
+    match ifd.get_values(stage.tag(), buffer, endian) {
+        Some(list) => parse_opcode_list(&list.to_vec().iter().map(|f| f.to_u8()).collect()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Why a list of opcodes couldn't be read or applied.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpcodeError {
+    // an opcode that isn't optional and that this crate can't run, by ID
+    Unsupported(u32),
+    // the list ends in the middle of an opcode
+    Truncated,
+    // an opcode's parameters are too short for what they say they hold, by ID
+    Malformed(u32),
+}
+
+impl fmt::Display for OpcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpcodeError::Unsupported(id) => write!(f, "Opcode {} isn't supported and isn't optional", id),
+            OpcodeError::Truncated => write!(f, "The opcode list ends in the middle of an opcode"),
+            OpcodeError::Malformed(id) => write!(f, "The parameters of opcode {} are too short", id),
+        }
+    }
+}
+
+impl std::error::Error for OpcodeError {}
+
+/// Runs the opcodes in order. Opcodes this crate can't run are skipped when they're optional,
+/// otherwise the image can't be processed correctly so it's an error.
+///
+/// TrimBounds crops the image. Its bounds are in the coordinates of the image the list started with
+/// and the opcodes after it see the trimmed image, see trim_origin.
+pub fn apply_opcodes(image: &LinearImage, opcodes: &[Opcode]) -> Result<LinearImage, OpcodeError> {
+    use OpcodeKind::*;
+    let mut image = image.clone();
+    let mut origin = (0, 0);
+    for opcode in opcodes {
+        image = match &opcode.kind {
+            WarpRectilinear { planes, center } => lens::warp_rectilinear(&image, planes, *center),
+            WarpRectilinear2 { planes, center, reciprocal_radial } => lens::warp_rectilinear2(&image, planes, *center, *reciprocal_radial),
+            WarpFisheye { planes, center } => lens::warp_fisheye(&image, planes, *center),
+            FixVignetteRadial { k, center } => lens::fix_vignette_radial(&image, k, *center),
+            FixBadPixelsConstant { constant, bayer_phase } => bad_pixels::fix_bad_pixels_constant(&image, *constant, *bayer_phase),
+            FixBadPixelsList { bayer_phase, points, rects } => bad_pixels::fix_bad_pixels_list(&image, *bayer_phase, points, rects),
+            TrimBounds { top, left, bottom, right } => {
+                let bounds = Rect { top: *top as usize, left: *left as usize, bottom: *bottom as usize, right: *right as usize };
+                let rect = bounds.relative_to(origin, image.width, image.height);
+                origin = (origin.0 + rect.top, origin.1 + rect.left);
+                image.crop(&rect)
+            },
+            MapTable { area, table } => area::map_table(&image, area, table),
+            MapPolynomial { area, coefficients } => area::map_polynomial(&image, area, coefficients),
+            GainMap { area, map } => map.apply(&image, area),
+            DeltaPerRow { area, deltas } => area::delta_per_row(&image, area, deltas),
+            DeltaPerColumn { area, deltas } => area::delta_per_column(&image, area, deltas),
+            ScalePerRow { area, scales } => area::scale_per_row(&image, area, scales),
+            ScalePerColumn { area, scales } => area::scale_per_column(&image, area, scales),
+            _ if opcode.optional => continue,
+            kind => return Err(OpcodeError::Unsupported(kind.id())),
+        };
+    }
+    Ok(image)
+}
+
+/// Where the top left of the image that apply_opcodes returns is in the image it was given, which
+/// only TrimBounds changes.
+pub fn trim_origin(opcodes: &[Opcode]) -> (usize, usize) {
+    opcodes.iter().fold((0, 0), |origin, opcode| match opcode.kind {
+        OpcodeKind::TrimBounds { top, left, .. } => (origin.0.max(top as usize), origin.1.max(left as usize)),
+        _ => origin,
+    })
+}
+
+/// OpcodeList1 works on the stored values, which the opcodes see as fractions of 65535.
+pub fn apply_opcodes_to_raster(raster: &Raster, opcodes: &[Opcode]) -> Result<Raster, OpcodeError> {
+    if opcodes.is_empty() {
+        return Ok(raster.clone());
+    }
+    let image = LinearImage {
+        width: raster.width,
+        height: raster.height,
+        planes: raster.samples_per_pixel,
+        data: raster.data.iter().map(|&v| v as f32 / 65535.0).collect(),
+    };
+    let image = apply_opcodes(&image, opcodes)?;
+    Ok(Raster {
+        width: image.width,
+        height: image.height,
+        samples_per_pixel: image.planes,
+        bits_per_sample: raster.bits_per_sample,
+        data: image.data.iter().map(|&v| (v * 65535.0).round().clamp(0.0, 65535.0) as u16).collect(),
+    })
+}
+
+/// OpcodeList1 on floating point samples, which the opcodes see as they're stored (a white level
+/// of 1.0 is 65535 in a Raster, so that's the same as apply_opcodes_to_raster without the clipping).
+pub fn apply_opcodes_to_float_raster(raster: &Raster<f32>, opcodes: &[Opcode]) -> Result<Raster<f32>, OpcodeError> {
+    if opcodes.is_empty() {
+        return Ok(raster.clone());
+    }
+    let image = LinearImage { width: raster.width, height: raster.height, planes: raster.samples_per_pixel, data: raster.data.clone() };
+    let image = apply_opcodes(&image, opcodes)?;
+    Ok(Raster {
+        width: image.width,
+        height: image.height,
+        samples_per_pixel: image.planes,
+        bits_per_sample: raster.bits_per_sample,
+        data: image.data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_list() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&2u32.to_be_bytes());
+        // TrimBounds, optional
+        bytes.extend_from_slice(&6u32.to_be_bytes());
+        bytes.extend_from_slice(&[1, 3, 0, 0]);
+        bytes.extend_from_slice(&1u32.to_be_bytes());
+        bytes.extend_from_slice(&16u32.to_be_bytes());
+        for v in [1u32, 2, 30, 40] {
+            bytes.extend_from_slice(&v.to_be_bytes());
+        }
+        // something from the future
+        bytes.extend_from_slice(&99u32.to_be_bytes());
+        bytes.extend_from_slice(&[1, 7, 0, 0]);
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes.extend_from_slice(&3u32.to_be_bytes());
+        bytes.extend_from_slice(&[7, 8, 9]);
+
+        let opcodes = parse_opcode_list(&bytes).unwrap();
+
+        assert_eq!(opcodes.len(), 2);
+        assert!(opcodes[0].optional);
+        assert_eq!(opcodes[0].kind, OpcodeKind::TrimBounds { top: 1, left: 2, bottom: 30, right: 40 });
+        assert!(!opcodes[1].optional);
+        assert_eq!(opcodes[1].kind, OpcodeKind::Unknown { id: 99, parameters: vec![7, 8, 9] });
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let opcodes = vec![
+            bad_pixels::bad_pixels_opcode(vec![(3, 4), (10, 2)], 1),
+            Opcode {
+                dng_version: [1, 3, 0, 0],
+                optional: true,
+                preview_skippable: false,
+                kind: OpcodeKind::FixVignetteRadial { k: [0.1, 0.2, 0.0, 0.0, 0.0], center: (0.5, 0.5) },
+            },
+        ];
+
+        assert_eq!(parse_opcode_list(&encode_opcode_list(&opcodes)).unwrap(), opcodes);
+    }
+
+    #[test]
+    fn truncated_list() {
+        let opcodes = vec![mandatory(OpcodeKind::TrimBounds { top: 1, left: 2, bottom: 30, right: 40 })];
+        let bytes = encode_opcode_list(&opcodes);
+
+        // cut in the parameters, in the header and a count that's more than the list holds
+        assert_eq!(parse_opcode_list(&bytes[..bytes.len() - 1].to_vec()), Err(OpcodeError::Truncated));
+        assert_eq!(parse_opcode_list(&bytes[..10].to_vec()), Err(OpcodeError::Truncated));
+        let mut more = bytes.clone();
+        more[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(parse_opcode_list(&more), Err(OpcodeError::Truncated));
+        assert_eq!(parse_opcode_list(&vec![0, 0]), Err(OpcodeError::Truncated));
+    }
+
+    #[test]
+    fn malformed_parameters() {
+        // a MapTable that says it has 1000 entries but only has 2
+        let area = AreaSpec { top: 0, left: 0, bottom: 1, right: 1, plane: 0, planes: 1, row_pitch: 1, col_pitch: 1 };
+        let mut parameters = encode_kind(&OpcodeKind::MapTable { area, table: vec![1, 2] });
+        parameters[32..36].copy_from_slice(&1000u32.to_be_bytes());
+        let table = mandatory(OpcodeKind::Unknown { id: 7, parameters });
+        assert_eq!(parse_opcode_list(&encode_opcode_list(&[table])), Err(OpcodeError::Malformed(7)));
+
+        // a GainMap whose point counts multiply past the end of the parameters
+        let mut gain_map = vec![0; 32];
+        gain_map.extend(u32::MAX.to_be_bytes());
+        gain_map.extend(u32::MAX.to_be_bytes());
+        gain_map.extend([0; 32]);
+        gain_map.extend(2u32.to_be_bytes());
+        let gain_map = mandatory(OpcodeKind::Unknown { id: 9, parameters: gain_map });
+        assert_eq!(parse_opcode_list(&encode_opcode_list(&[gain_map])), Err(OpcodeError::Malformed(9)));
+    }
+
+    fn mandatory(kind: OpcodeKind) -> Opcode {
+        Opcode { dng_version: [1, 3, 0, 0], optional: false, preview_skippable: false, kind }
+    }
+
+    #[test]
+    fn trim_bounds() {
+        let image = LinearImage { width: 4, height: 3, planes: 1, data: (0..12).map(|v| v as f32).collect() };
+        // the second TrimBounds is in the same coordinates as the first and goes past the image
+        let opcodes = vec![
+            mandatory(OpcodeKind::TrimBounds { top: 1, left: 1, bottom: 3, right: 4 }),
+            mandatory(OpcodeKind::TrimBounds { top: 1, left: 2, bottom: 5, right: 5 }),
+        ];
+
+        let trimmed = apply_opcodes(&image, &opcodes).unwrap();
+
+        assert_eq!((trimmed.width, trimmed.height), (2, 2));
+        assert_eq!(trimmed.data, vec![6.0, 7.0, 10.0, 11.0]);
+        assert_eq!(trim_origin(&opcodes), (1, 2));
+    }
+
+    #[test]
+    fn unsupported_mandatory_opcode() {
+        let image = LinearImage::new(2, 2, 1);
+        let unknown = OpcodeKind::Unknown { id: 99, parameters: Vec::new() };
+
+        assert_eq!(apply_opcodes(&image, &[mandatory(unknown.clone())]).err(), Some(OpcodeError::Unsupported(99)));
+        let optional = Opcode { optional: true, ..mandatory(unknown) };
+        assert_eq!(apply_opcodes(&image, &[optional]).unwrap().data, image.data);
+    }
+}
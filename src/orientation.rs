@@ -0,0 +1,142 @@
+use image::Image;
+
+use crate::{geometry::Rect, linearize::LinearImage};
+
+/// TIFF/EXIF Orientation, how the stored image has to be flipped and rotated to be the right way up.
+/// The rotations are clockwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Orientation {
+    Normal = 1,
+    MirrorHorizontal = 2,
+    Rotate180 = 3,
+    MirrorVertical = 4,
+    // mirrored horizontally, then rotated 270
+    Transpose = 5,
+    Rotate90 = 6,
+    // mirrored horizontally, then rotated 90
+    Transverse = 7,
+    Rotate270 = 8,
+}
+
+impl Orientation {
+    // TIFF6 Orientation P36, unknown values are treated as normal like most readers do
+    pub fn from_value(value: u16) -> Self {
+        use Orientation::*;
+        match value {
+            2 => MirrorHorizontal,
+            3 => Rotate180,
+            4 => MirrorVertical,
+            5 => Transpose,
+            6 => Rotate90,
+            7 => Transverse,
+            8 => Rotate270,
+            _ => Normal,
+        }
+    }
+
+    pub fn value(&self) -> u16 {
+        *self as u16
+    }
+
+    /// Whether the displayed image is the stored one's height wide and width tall.
+    pub fn swaps_dimensions(&self) -> bool {
+        self.value() >= 5
+    }
+
+    pub fn display_size(&self, width: usize, height: usize) -> (usize, usize) {
+        if self.swaps_dimensions() { (height, width) } else { (width, height) }
+    }
+
+    /// Where a stored pixel ends up once displayed, as (row, column).
+    pub fn map_point(&self, row: usize, col: usize, width: usize, height: usize) -> (usize, usize) {
+        use Orientation::*;
+        let (w, h) = (width - 1, height - 1);
+        match self {
+            Normal => (row, col),
+            MirrorHorizontal => (row, w - col),
+            Rotate180 => (h - row, w - col),
+            MirrorVertical => (h - row, col),
+            Transpose => (col, row),
+            Rotate90 => (col, h - row),
+            Transverse => (w - col, h - row),
+            Rotate270 => (w - col, row),
+        }
+    }
+
+    /// Where a rectangle of the stored image ends up once displayed, e.g. for crops.
+    pub fn map_rect(&self, rect: &Rect, width: usize, height: usize) -> Rect {
+        use Orientation::*;
+        // on the pixel edges rather than the pixel centers, so bottom and right stay exclusive
+        let map = |y: usize, x: usize| match self {
+            Normal => (y, x),
+            MirrorHorizontal => (y, width - x),
+            Rotate180 => (height - y, width - x),
+            MirrorVertical => (height - y, x),
+            Transpose => (x, y),
+            Rotate90 => (x, height - y),
+            Transverse => (width - x, height - y),
+            Rotate270 => (width - x, y),
+        };
+        let (y0, x0) = map(rect.top, rect.left);
+        let (y1, x1) = map(rect.bottom, rect.right);
+        Rect { top: y0.min(y1), left: x0.min(x1), bottom: y0.max(y1), right: x0.max(x1) }
+    }
+
+    // Reorders interleaved pixels of `samples` each from stored order into display order
+    fn orient_pixels<T: Copy + Default>(&self, data: &[T], width: usize, height: usize, samples: usize) -> Vec<T> {
+        let (display_width, _) = self.display_size(width, height);
+        let mut oriented = vec![T::default(); data.len()];
+        for row in 0..height {
+            for col in 0..width {
+                let (r, c) = self.map_point(row, col, width, height);
+                let from = (row * width + col) * samples;
+                let to = (r * display_width + c) * samples;
+                oriented[to..to + samples].copy_from_slice(&data[from..from + samples]);
+            }
+        }
+        oriented
+    }
+
+    pub fn orient_image(&self, image: &LinearImage) -> LinearImage {
+        let (width, height) = self.display_size(image.width, image.height);
+        LinearImage { width, height, planes: image.planes, data: self.orient_pixels(&image.data, image.width, image.height, image.planes) }
+    }
+
+    /// For 8 bit RGB images like thumbnails and renders.
+    pub fn orient_rgb(&self, image: &Image) -> Image {
+        let (width, height) = self.display_size(image.width as usize, image.height as usize);
+        Image {
+            data: self.orient_pixels(&image.data, image.width as usize, image.height as usize, 3),
+            width: width as u32,
+            height: height as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_clockwise() {
+        // 2 wide, 1 tall
+        let image = LinearImage { width: 2, height: 1, planes: 1, data: vec![1.0, 2.0] };
+
+        let rotated = Orientation::Rotate90.orient_image(&image);
+
+        assert_eq!((rotated.width, rotated.height), (1, 2));
+        assert_eq!(rotated.data, vec![1.0, 2.0]);
+        assert_eq!(Orientation::Rotate270.orient_image(&image).data, vec![2.0, 1.0]);
+    }
+
+    #[test]
+    fn rects_follow_pixels() {
+        // 4 wide, 3 tall, a 1x1 rect at row 0, column 1
+        let rect = Rect { top: 0, left: 1, bottom: 1, right: 2 };
+        for value in 1..=8 {
+            let orientation = Orientation::from_value(value);
+            let (row, col) = orientation.map_point(0, 1, 4, 3);
+            assert_eq!(orientation.map_rect(&rect, 4, 3), Rect { top: row, left: col, bottom: row + 1, right: col + 1 });
+        }
+    }
+}
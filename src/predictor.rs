@@ -0,0 +1,110 @@
+// TIFF6.0 Predictor P64, Adobe Photoshop TIFF Technical Note 3 (floating point predictor) and DNG
+// spec 1.6 Predictor P20, which adds versions that difference against the pixel 2 or 4 columns back
+
+use crate::{codec::ChunkInfo, Endian};
+
+/// Undoes the Predictor a chunk was stored with, on the bytes the codec decoded it to.
+pub(crate) fn undo_predictor(predictor: u16, data: &mut [u8], chunk: &ChunkInfo) {
+    match predictor {
+        1 => {},
+        2 => undo_horizontal_difference(data, chunk, 1),
+        34892 => undo_horizontal_difference(data, chunk, 2),
+        34893 => undo_horizontal_difference(data, chunk, 4),
+        3 | 34894 => undo_floating_point(data, chunk, 1),
+        34895 => undo_floating_point(data, chunk, 2),
+        34896 => undo_floating_point(data, chunk, 4),
+        _ => panic!("Predictor {} isn't supported!", predictor),
+    }
+}
+
+// Every sample after the first `factor` pixels of a row is stored as the difference to the same
+// sample `factor` pixels to the left, wrapping around
+fn undo_horizontal_difference(data: &mut [u8], chunk: &ChunkInfo, factor: usize) {
+    let bytes = chunk.bits_per_sample / 8;
+    assert!(matches!(chunk.bits_per_sample, 8 | 16 | 32), "Horizontal differencing of {} bit samples isn't supported!", chunk.bits_per_sample);
+    let row_samples = chunk.width * chunk.samples_per_pixel;
+    let stride = chunk.samples_per_pixel * factor;
+    let read = |b: &[u8]| match chunk.endian {
+        Endian::Big => b.iter().fold(0u32, |value, &byte| (value << 8) | byte as u32),
+        Endian::Little => b.iter().rev().fold(0u32, |value, &byte| (value << 8) | byte as u32),
+    };
+    for row in data.chunks_mut(row_samples * bytes).take(chunk.length) {
+        for i in stride..row_samples.min(row.len() / bytes) {
+            let sum = read(&row[(i - stride) * bytes..(i - stride + 1) * bytes]).wrapping_add(read(&row[i * bytes..(i + 1) * bytes]));
+            let sample = &mut row[i * bytes..(i + 1) * bytes];
+            for (b, byte) in sample.iter_mut().enumerate() {
+                let shift = match chunk.endian {
+                    Endian::Big => (bytes - 1 - b) * 8,
+                    Endian::Little => b * 8,
+                };
+                *byte = (sum >> shift) as u8;
+            }
+        }
+    }
+}
+
+// Each row's bytes are differenced `factor` pixels apart after being split into byte planes, the most
+// significant byte of every sample first. The samples come back in the file's byte order.
+fn undo_floating_point(data: &mut [u8], chunk: &ChunkInfo, factor: usize) {
+    let bytes = chunk.bits_per_sample / 8;
+    let row_samples = chunk.width * chunk.samples_per_pixel;
+    let row_bytes = row_samples * bytes;
+    let stride = chunk.samples_per_pixel * factor;
+    for row in data.chunks_mut(row_bytes).take(chunk.length) {
+        if row.len() < row_bytes {
+            break;
+        }
+        for i in stride..row_bytes {
+            row[i] = row[i].wrapping_add(row[i - stride]);
+        }
+        let planes = row.to_vec();
+        for sample in 0..row_samples {
+            for plane in 0..bytes {
+                let b = match chunk.endian {
+                    Endian::Big => plane,
+                    Endian::Little => bytes - 1 - plane,
+                };
+                row[sample * bytes + b] = planes[plane * row_samples + sample];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn horizontal_difference() {
+        // two 16 bit RGB pixels, the second pixel's samples differ from the first's by 1, -1 and 256
+        let chunk = ChunkInfo { width: 2, length: 1, samples_per_pixel: 3, bits_per_sample: 16, endian: Endian::Little };
+        let mut data = [0x00, 0x10, 0x00, 0x20, 0xFF, 0xFF, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x01];
+
+        undo_predictor(2, &mut data, &chunk);
+
+        assert_eq!(data, [0x00, 0x10, 0x00, 0x20, 0xFF, 0xFF, 0x01, 0x10, 0xFF, 0x1F, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn floating_point_x2() {
+        // 1.0, 2.0, 1.5 and 2.5 as half floats (0x3C00, 0x4000, 0x3E00, 0x4100) in byte planes, then
+        // differenced 2 samples apart
+        let planes: [u8; 8] = [0x3C, 0x40, 0x3E, 0x41, 0x00, 0x00, 0x00, 0x00];
+        let mut data = planes;
+        for i in (2..8).rev() {
+            data[i] = data[i].wrapping_sub(planes[i - 2]);
+        }
+        for endian in [Endian::Big, Endian::Little] {
+            let chunk = ChunkInfo { width: 4, length: 1, samples_per_pixel: 1, bits_per_sample: 16, endian: endian.clone() };
+            let mut undone = data;
+
+            undo_predictor(34895, &mut undone, &chunk);
+
+            let expected = [0x3C00u16, 0x4000, 0x3E00, 0x4100].iter().flat_map(|&s| match endian {
+                Endian::Big => s.to_be_bytes(),
+                Endian::Little => s.to_le_bytes(),
+            }).collect::<Vec<u8>>();
+            assert_eq!(undone.to_vec(), expected);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{tags::Tag, Endian, IFD};
+
+/// DNG spec 1.6 PreviewColorSpace P67.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PreviewColorSpace {
+    Unknown,
+    GrayGamma22,
+    Srgb,
+    AdobeRgb,
+    ProPhotoRgb,
+}
+
+impl PreviewColorSpace {
+    fn from_value(value: u32) -> Self {
+        use PreviewColorSpace::*;
+        match value {
+            1 => GrayGamma22,
+            2 => Srgb,
+            3 => AdobeRgb,
+            4 => ProPhotoRgb,
+            _ => Unknown,
+        }
+    }
+}
+
+/// A reduced resolution (NewSubFileType = 1) image and what it says about itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preview {
+    // the IFD's offset, which identifies the preview
+    pub offset: usize,
+    pub width: usize,
+    pub height: usize,
+    pub compression: u16,
+    pub photometric_interpretation: u16,
+    pub samples_per_pixel: usize,
+    pub bits_per_sample: usize,
+    pub color_space: PreviewColorSpace,
+    // provenance, which DNG spec 1.6 P66 only requires in IFD 0 for the main preview
+    pub application_name: Option<String>,
+    pub application_version: Option<String>,
+    pub settings_name: Option<String>,
+    pub date_time: Option<String>,
+}
+
+// ASCII and UTF-8 tags, without the terminating NUL
+pub(crate) fn read_string(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian, tag: Tag) -> Option<String> {
+    let bytes = ifd.get_values(tag, buffer, endian)?.to_vec().iter().map(|f| f.to_u8()).collect::<Vec<u8>>();
+    Some(String::from_utf8_lossy(&bytes).trim_end_matches('\0').to_string())
+}
+
+impl Preview {
+    pub(crate) fn read(buffer: &Vec<u8>, offset: usize, ifd: &IFD, endian: &Endian) -> Self {
+        let usize_or = |tag: Tag, default: usize| ifd.get_values(tag, buffer, endian).map(|v| v.to_vec()[0].to_usize()).unwrap_or(default);
+        let string = |tag: Tag| read_string(buffer, ifd, endian, tag);
+        Self {
+            offset,
+            width: usize_or(Tag::ImageWidth_256, 0),
+            height: usize_or(Tag::ImageLength_257, 0),
+            compression: usize_or(Tag::Compression_259, 1) as u16,
+            photometric_interpretation: usize_or(Tag::PhotometricInterpretation_262, 2) as u16,
+            samples_per_pixel: usize_or(Tag::SamplesPerPixel_277, 1),
+            bits_per_sample: usize_or(Tag::BitsPerSample_258, 1),
+            color_space: PreviewColorSpace::from_value(usize_or(Tag::PreviewColorSpace_50970, 0) as u32),
+            application_name: string(Tag::PreviewApplicationName_50966),
+            application_version: string(Tag::PreviewApplicationVersion_50967),
+            settings_name: string(Tag::PreviewSettingsName_50968),
+            date_time: string(Tag::PreviewDateTime_50971),
+        }
+    }
+}
+
+/// The smallest preview that's at least min_width wide without being wider than max_width.
+pub fn best_preview(previews: &[Preview], min_width: usize, max_width: usize) -> Option<&Preview> {
+    previews.iter()
+        .filter(|p| p.width >= min_width && p.width <= max_width)
+        .min_by_key(|p| p.width * p.height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preview(width: usize, height: usize) -> Preview {
+        Preview {
+            offset: width,
+            width,
+            height,
+            compression: 7,
+            photometric_interpretation: 6,
+            samples_per_pixel: 3,
+            bits_per_sample: 8,
+            color_space: PreviewColorSpace::Srgb,
+            application_name: None,
+            application_version: None,
+            settings_name: None,
+            date_time: None,
+        }
+    }
+
+    #[test]
+    fn smallest_adequate_preview() {
+        let previews = vec![preview(256, 171), preview(1024, 683), preview(4000, 2667)];
+
+        assert_eq!(best_preview(&previews, 800, 5000).unwrap().width, 1024);
+        assert_eq!(best_preview(&previews, 100, 300).unwrap().width, 256);
+        assert!(best_preview(&previews, 5000, 8000).is_none());
+    }
+}
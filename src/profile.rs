@@ -0,0 +1,395 @@
+use crate::{color::ColorSpace, linearize::LinearImage, tags::Tag, Endian, IFD};
+
+/// A table of hue shifts (in degrees), saturation scales and value scales indexed by hue, saturation
+/// and value, see DNG spec 1.6 ProfileHueSatMapDims P71. A single value division makes it 2.5D.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HueSatMap {
+    pub hue_divisions: usize,
+    pub sat_divisions: usize,
+    pub val_divisions: usize,
+    // ordered by value, then hue, then saturation
+    pub data: Vec<[f32; 3]>,
+    // the value axis is indexed with sRGB encoded values instead of linear ones
+    pub srgb_encoded: bool,
+}
+
+impl HueSatMap {
+    fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian, dims: Tag, data: Tag, encoding: Tag) -> Option<Self> {
+        let dims = ifd.get_values(dims, buffer, endian)?.to_vec().iter().map(|f| f.to_usize()).collect::<Vec<usize>>();
+        let values = ifd.get_values(data, buffer, endian)?.to_vec().iter().map(|f| f.to_f64() as f32).collect::<Vec<f32>>();
+        let (hue_divisions, sat_divisions, val_divisions) = (dims[0], dims[1], dims[2].max(1));
+        assert_eq!(values.len(), hue_divisions * sat_divisions * val_divisions * 3, "The hue/sat map doesn't match its dimensions!");
+        let srgb_encoded = match ifd.get_values(encoding, buffer, endian) {
+            Some(encoding) => encoding.to_value().to_u32() == 1,
+            None => false,
+        };
+        Some(Self {
+            hue_divisions,
+            sat_divisions,
+            val_divisions,
+            data: values.chunks(3).map(|c| [c[0], c[1], c[2]]).collect(),
+            srgb_encoded,
+        })
+    }
+
+    /// A weighted mix of two maps with the same dimensions, for dual illuminant profiles.
+    pub fn interpolate(&self, other: &HueSatMap, weight: f64) -> HueSatMap {
+        assert!(
+            self.hue_divisions == other.hue_divisions && self.sat_divisions == other.sat_divisions && self.val_divisions == other.val_divisions,
+            "The hue/sat maps have different dimensions!"
+        );
+        let w = weight as f32;
+        let data = self.data.iter().zip(&other.data)
+            .map(|(a, b)| [a[0] * w + b[0] * (1.0 - w), a[1] * w + b[1] * (1.0 - w), a[2] * w + b[2] * (1.0 - w)])
+            .collect();
+        HueSatMap { data, ..self.clone() }
+    }
+
+    fn entry(&self, val: usize, hue: usize, sat: usize) -> [f32; 3] {
+        self.data[(val * self.hue_divisions + hue) * self.sat_divisions + sat]
+    }
+
+    // bilinear in hue and saturation, hue wraps around
+    fn lookup_2d(&self, val: usize, h: f32, s: f32) -> [f32; 3] {
+        let h_scaled = if self.hue_divisions < 2 { 0.0 } else { h * self.hue_divisions as f32 / 6.0 };
+        let s_scaled = s * (self.sat_divisions - 1) as f32;
+        let mut h0 = h_scaled as usize;
+        let s0 = (s_scaled as usize).min(self.sat_divisions.saturating_sub(2));
+        let mut h1 = h0 + 1;
+        if h0 >= self.hue_divisions - 1 {
+            h0 = self.hue_divisions - 1;
+            h1 = 0;
+        }
+        let s1 = (s0 + 1).min(self.sat_divisions - 1);
+        let hf = h_scaled - h0 as f32;
+        let sf = s_scaled - s0 as f32;
+        let mut result = [0.0; 3];
+        for (i, r) in result.iter_mut().enumerate() {
+            let low = self.entry(val, h0, s0)[i] * (1.0 - hf) + self.entry(val, h1, s0)[i] * hf;
+            let high = self.entry(val, h0, s1)[i] * (1.0 - hf) + self.entry(val, h1, s1)[i] * hf;
+            *r = low * (1.0 - sf) + high * sf;
+        }
+        result
+    }
+
+    /// Applies the map to linear ProPhoto RGB.
+    pub fn apply(&self, image: &LinearImage) -> LinearImage {
+        assert_eq!(image.planes, 3, "Hue/sat maps only work on RGB images!");
+        let mut mapped = image.clone();
+        for pixel in mapped.data.chunks_mut(3) {
+            let (h, s, v) = rgb_to_hsv(pixel[0], pixel[1], pixel[2]);
+            let v_encoded = if self.srgb_encoded { ColorSpace::Srgb.encode(v) } else { v };
+
+            let [hue_shift, sat_scale, val_scale] = if self.val_divisions < 2 {
+                self.lookup_2d(0, h, s)
+            } else {
+                let v_scaled = v_encoded.clamp(0.0, 1.0) * (self.val_divisions - 1) as f32;
+                let v0 = (v_scaled as usize).min(self.val_divisions - 2);
+                let vf = v_scaled - v0 as f32;
+                let low = self.lookup_2d(v0, h, s);
+                let high = self.lookup_2d(v0 + 1, h, s);
+                [0, 1, 2].map(|i| low[i] * (1.0 - vf) + high[i] * vf)
+            };
+
+            let h = h + hue_shift * 6.0 / 360.0;
+            let s = (s * sat_scale).min(1.0);
+            let v_encoded = (v_encoded * val_scale).clamp(0.0, 1.0);
+            let v = if self.srgb_encoded { srgb_decode(v_encoded) } else { v_encoded };
+            let (r, g, b) = hsv_to_rgb(h, s, v);
+            pixel.copy_from_slice(&[r, g, b]);
+        }
+        mapped
+    }
+}
+
+/// ProfileToneCurve, points in [0, 1] joined with a natural cubic spline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToneCurve {
+    pub points: Vec<(f64, f64)>,
+}
+
+impl ToneCurve {
+    pub fn new(points: Vec<(f64, f64)>) -> Self {
+        assert!(points.len() >= 2, "A tone curve needs at least two points!");
+        Self { points }
+    }
+
+    // the spline's second derivatives at each point
+    fn second_derivatives(&self) -> Vec<f64> {
+        let n = self.points.len();
+        let mut m = vec![0.0; n];
+        let mut c = vec![0.0; n];
+        let mut d = vec![0.0; n];
+        // Thomas algorithm for the tridiagonal system with natural end conditions
+        for i in 1..n - 1 {
+            let (x0, y0) = self.points[i - 1];
+            let (x1, y1) = self.points[i];
+            let (x2, y2) = self.points[i + 1];
+            let a = (x1 - x0) / 6.0;
+            let b = (x2 - x0) / 3.0;
+            let cc = (x2 - x1) / 6.0;
+            let r = (y2 - y1) / (x2 - x1) - (y1 - y0) / (x1 - x0);
+            let denominator = b - a * c[i - 1];
+            c[i] = cc / denominator;
+            d[i] = (r - a * d[i - 1]) / denominator;
+        }
+        for i in (1..n - 1).rev() {
+            m[i] = d[i] - c[i] * m[i + 1];
+        }
+        m
+    }
+
+    pub fn evaluate(&self, x: f64) -> f64 {
+        self.evaluate_with(x, &self.second_derivatives())
+    }
+
+    fn evaluate_with(&self, x: f64, m: &[f64]) -> f64 {
+        let n = self.points.len();
+        if x <= self.points[0].0 {
+            return self.points[0].1;
+        }
+        if x >= self.points[n - 1].0 {
+            return self.points[n - 1].1;
+        }
+        let i = self.points.partition_point(|p| p.0 <= x).max(1) - 1;
+        let (x0, y0) = self.points[i];
+        let (x1, y1) = self.points[i + 1];
+        let h = x1 - x0;
+        let a = (x1 - x) / h;
+        let b = (x - x0) / h;
+        a * y0 + b * y1 + ((a * a * a - a) * m[i] + (b * b * b - b) * m[i + 1]) * h * h / 6.0
+    }
+
+    /// Applies the curve to RGB while keeping the hue, by mapping the largest and smallest channels
+    /// through the curve and interpolating the middle one, like Adobe's RGB tone operator.
+    pub fn apply(&self, image: &LinearImage) -> LinearImage {
+        const TABLE_SIZE: usize = 4096;
+        let m = self.second_derivatives();
+        let table = (0..=TABLE_SIZE).map(|i| self.evaluate_with(i as f64 / TABLE_SIZE as f64, &m) as f32).collect::<Vec<f32>>();
+        let curve = |v: f32| {
+            let x = v.clamp(0.0, 1.0) * TABLE_SIZE as f32;
+            let i = (x as usize).min(TABLE_SIZE - 1);
+            let f = x - i as f32;
+            table[i] * (1.0 - f) + table[i + 1] * f
+        };
+
+        let mut toned = image.clone();
+        for pixel in toned.data.chunks_mut(image.planes) {
+            if pixel.len() != 3 {
+                pixel.iter_mut().for_each(|v| *v = curve(*v));
+                continue;
+            }
+            let mut order = [0, 1, 2];
+            order.sort_by(|&a, &b| pixel[b].partial_cmp(&pixel[a]).unwrap());
+            let [large, middle, small] = order.map(|i| pixel[i].clamp(0.0, 1.0));
+            let new_large = curve(large);
+            let new_small = curve(small);
+            let new_middle = if large > small { new_small + (new_large - new_small) * (middle - small) / (large - small) } else { new_small };
+            pixel[order[0]] = new_large;
+            pixel[order[1]] = new_middle;
+            pixel[order[2]] = new_small;
+        }
+        toned
+    }
+}
+
+/// The rendering tables of the camera profile in IFD 0.
+#[derive(Clone, Debug, Default)]
+pub struct CameraProfile {
+    pub hue_sat_map: [Option<HueSatMap>; 2],
+    pub look_table: Option<HueSatMap>,
+    pub tone_curve: Option<ToneCurve>,
+}
+
+impl CameraProfile {
+    pub(crate) fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian) -> Self {
+        let hue_sat_map = |data: Tag| HueSatMap::read(buffer, ifd, endian, Tag::ProfileHueSatMapDims_50937, data, Tag::ProfileHueSatMapEncoding_51107);
+        let tone_curve = ifd.get_values(Tag::ProfileToneCurve_50940, buffer, endian).map(|v| {
+            let values = v.to_vec().iter().map(|f| f.to_f64()).collect::<Vec<f64>>();
+            ToneCurve::new(values.chunks(2).map(|p| (p[0], p[1])).collect())
+        });
+        Self {
+            hue_sat_map: [hue_sat_map(Tag::ProfileHueSatMapData_50938), hue_sat_map(Tag::ProfileHueSatMapData_50939)],
+            look_table: HueSatMap::read(buffer, ifd, endian, Tag::ProfileLookTableDims_50981, Tag::ProfileLookTableData_50982, Tag::ProfileLookTableEncoding_51108),
+            tone_curve,
+        }
+    }
+
+    /// The hue/sat map for a white, `weight` is ColorCalibration::first_calibration_weight.
+    pub fn hue_sat_map_for(&self, weight: f64) -> Option<HueSatMap> {
+        match &self.hue_sat_map {
+            [Some(m1), Some(m2)] => Some(m1.interpolate(m2, weight)),
+            [Some(m), None] | [None, Some(m)] => Some(m.clone()),
+            [None, None] => None,
+        }
+    }
+}
+
+// h is in [0, 6), see DNG spec 1.6 P95
+fn rgb_to_hsv(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let v = r.max(g).max(b);
+    let gap = v - r.min(g).min(b);
+    if gap <= 0.0 {
+        return (0.0, 0.0, v);
+    }
+    let h = if r == v {
+        let h = (g - b) / gap;
+        if h < 0.0 { h + 6.0 } else { h }
+    } else if g == v {
+        2.0 + (b - r) / gap
+    } else {
+        4.0 + (r - g) / gap
+    };
+    (h, gap / v, v)
+}
+
+fn hsv_to_rgb(h: f32, s: f32, v: f32) -> (f32, f32, f32) {
+    if s <= 0.0 {
+        return (v, v, v);
+    }
+    let h = h.rem_euclid(6.0);
+    let i = (h as usize).min(5);
+    let f = h - i as f32;
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - s * f);
+    let t = v * (1.0 - s * (1.0 - f));
+    match i {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    }
+}
+
+fn srgb_decode(v: f32) -> f32 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::{temperature_to_xy, ColorCalibration},
+        get_value,
+        matrix::Matrix,
+        test_utils::{build_tiff, TestIfd, Value},
+    };
+
+    // 1 hue x 2 saturations x 2 values, which leaves dark colors alone and halves bright ones
+    fn darkening_map(srgb_encoded: bool) -> HueSatMap {
+        let data = vec![[0.0, 1.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.5], [0.0, 1.0, 0.5]];
+        HueSatMap { hue_divisions: 1, sat_divisions: 2, val_divisions: 2, data, srgb_encoded }
+    }
+
+    fn gray(v: f32) -> LinearImage {
+        LinearImage { width: 1, height: 1, planes: 3, data: vec![v; 3] }
+    }
+
+    fn read_profile(ifd: TestIfd) -> CameraProfile {
+        let buffer = build_tiff(Endian::Little, ifd.strips(vec![Vec::new()]), Vec::new());
+        let ifd = IFD::parse_ifd(&buffer, get_value::long(&buffer, 4, &Endian::Little) as usize, &Endian::Little);
+        CameraProfile::read(&buffer, &ifd, &Endian::Little)
+    }
+
+    #[test]
+    fn value_divisions() {
+        let map = darkening_map(false);
+
+        // halfway between the value divisions the value scale is halfway between 1.0 and 0.5
+        assert!((map.apply(&gray(0.5)).data[0] - 0.375).abs() < 1e-6);
+        assert!((map.apply(&gray(1.0)).data[0] - 0.5).abs() < 1e-6);
+        assert!((map.apply(&gray(0.0)).data[0]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn srgb_encoded_tables() {
+        let data = || Value::Float(darkening_map(false).data.iter().flatten().copied().collect());
+        let profile = read_profile(TestIfd::new()
+            .tag(Tag::ProfileHueSatMapDims_50937, Value::Long(vec![1, 2, 2]))
+            .tag(Tag::ProfileHueSatMapData_50938, data())
+            .tag(Tag::ProfileLookTableDims_50981, Value::Long(vec![1, 2, 2]))
+            .tag(Tag::ProfileLookTableData_50982, data())
+            .tag(Tag::ProfileHueSatMapEncoding_51107, Value::Long(vec![1]))
+            .tag(Tag::ProfileLookTableEncoding_51108, Value::Long(vec![1])));
+
+        let look_table = profile.look_table.clone().unwrap();
+        assert_eq!(look_table, darkening_map(true));
+        assert_eq!(profile.hue_sat_map_for(1.0), Some(darkening_map(true)));
+
+        // 0.214 linear is about 0.5 sRGB encoded, so it's indexed halfway up the value axis and the
+        // scale applies to the encoded value
+        let v = srgb_decode(0.5);
+        let expected = srgb_decode(0.5 * 0.75);
+        assert!((look_table.apply(&gray(v)).data[1] - expected).abs() < 1e-4);
+        assert!((darkening_map(false).apply(&gray(v)).data[1] - expected).abs() > 1e-2);
+    }
+
+    #[test]
+    fn dual_illuminant_maps() {
+        let identity = Matrix::new(3, 3, vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        let calibration = ColorCalibration {
+            planes: 3,
+            // standard light A (2850K) and D65 (6500K)
+            calibration_illuminant: [17, 21],
+            color_matrix: [Some(identity.clone()), Some(identity)],
+            camera_calibration: [None, None],
+            forward_matrix: [None, None],
+            reduction_matrix: [None, None],
+            analog_balance: vec![1.0; 3],
+        };
+        // the first map halves every value, the second leaves them
+        let halve = HueSatMap { val_divisions: 1, data: vec![[0.0, 1.0, 0.5]; 2], ..darkening_map(false) };
+        let keep = HueSatMap { val_divisions: 1, data: vec![[0.0, 1.0, 1.0]; 2], ..darkening_map(false) };
+        let profile = CameraProfile { hue_sat_map: [Some(halve), Some(keep)], ..CameraProfile::default() };
+
+        // 4000K is weighted by inverse temperature between the two illuminants
+        let weight = calibration.first_calibration_weight(temperature_to_xy(4000.0, 0.0));
+        let expected_weight = (1.0 / 4000.0 - 1.0 / 6500.0) / (1.0 / 2850.0 - 1.0 / 6500.0);
+        assert!((weight - expected_weight).abs() < 1e-3, "{}", weight);
+
+        let map = profile.hue_sat_map_for(weight).unwrap();
+        let scale = 0.5 * weight + (1.0 - weight);
+        assert!((map.apply(&gray(0.8)).data[2] as f64 - 0.8 * scale).abs() < 1e-4);
+        assert!((profile.hue_sat_map_for(1.0).unwrap().apply(&gray(0.8)).data[2] - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn hue_shift() {
+        // 2.5D, 6 hues x 2 saturations, rotates every hue by 120 degrees
+        let map = HueSatMap { hue_divisions: 6, sat_divisions: 2, val_divisions: 1, data: vec![[120.0, 1.0, 1.0]; 12], srgb_encoded: false };
+        let image = LinearImage { width: 1, height: 1, planes: 3, data: vec![0.5, 0.0, 0.0] };
+
+        let mapped = map.apply(&image);
+
+        assert!(mapped.data[0].abs() < 1e-6 && (mapped.data[1] - 0.5).abs() < 1e-6 && mapped.data[2].abs() < 1e-6);
+    }
+
+    #[test]
+    fn tone_curve_through_points() {
+        let curve = ToneCurve::new(vec![(0.0, 0.0), (0.25, 0.15), (0.5, 0.5), (1.0, 1.0)]);
+
+        assert!((curve.evaluate(0.25) - 0.15).abs() < 1e-9);
+        assert!(curve.evaluate(0.1) < 0.1);
+        assert_eq!(curve.evaluate(1.5), 1.0);
+    }
+
+    #[test]
+    fn tone_curve_keeps_hue() {
+        let curve = ToneCurve::new(vec![(0.0, 0.0), (0.25, 0.4), (0.75, 0.9), (1.0, 1.0)]);
+        let image = LinearImage { width: 2, height: 1, planes: 3, data: vec![0.6, 0.2, 0.35, 0.5, 0.5, 0.5] };
+
+        let toned = curve.apply(&image);
+
+        // the largest and smallest channels go through the curve, the middle keeps the hue
+        let pixel = &toned.data[..3];
+        assert!((pixel[0] as f64 - curve.evaluate(0.6)).abs() < 1e-4);
+        assert!((pixel[1] as f64 - curve.evaluate(0.2)).abs() < 1e-4);
+        let (hue, _, _) = rgb_to_hsv(0.6, 0.2, 0.35);
+        let (toned_hue, _, _) = rgb_to_hsv(pixel[0], pixel[1], pixel[2]);
+        assert!((hue - toned_hue).abs() < 1e-4);
+        // grays stay gray
+        assert!(toned.data[3..].iter().all(|&v| (v as f64 - curve.evaluate(0.5)).abs() < 1e-4));
+    }
+}
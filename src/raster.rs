@@ -0,0 +1,375 @@
+use crate::{codec::{self, ChunkInfo, CodecRegistry}, predictor, sample_format::{self, PixelBuffer, SampleFormat}, tags::Tag, Endian, IFD};
+
+/// Decoded samples of an IFD's image, row major with the samples of each pixel interleaved
+/// (PlanarConfiguration = 1) regardless of how they were stored. Samples are 16 bit unless they're
+/// read as a PixelBuffer.
+#[derive(Clone)]
+pub struct Raster<T = u16> {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub bits_per_sample: usize,
+    pub data: Vec<T>,
+}
+
+impl Raster {
+    pub fn get(&self, row: usize, col: usize, sample: usize) -> u16 {
+        self.data[(row * self.width + col) * self.samples_per_pixel + sample]
+    }
+
+    /// Splits the interleaved samples into one buffer per sample plane (PlanarConfiguration = 2).
+    pub fn planes(&self) -> Vec<Vec<u16>> {
+        let mut planes = vec![Vec::with_capacity(self.width * self.height); self.samples_per_pixel];
+        for pixel in self.data.chunks(self.samples_per_pixel) {
+            for (plane, &sample) in planes.iter_mut().zip(pixel) {
+                plane.push(sample);
+            }
+        }
+        planes
+    }
+
+    pub fn from_planes(width: usize, height: usize, bits_per_sample: usize, planes: &[Vec<u16>]) -> Self {
+        let samples_per_pixel = planes.len();
+        let mut data = Vec::with_capacity(width * height * samples_per_pixel);
+        for i in 0..width * height {
+            for plane in planes {
+                data.push(plane[i]);
+            }
+        }
+        Self { width, height, samples_per_pixel, bits_per_sample, data }
+    }
+}
+
+// Strips are treated as tiles that are as wide as the image, see TIFF6.0 P67
+struct ChunkLayout {
+    chunk_width: usize,
+    chunk_length: usize,
+    chunks_across: usize,
+    chunks_down: usize,
+    tiled: bool,
+}
+
+impl ChunkLayout {
+    fn new(ifd: &IFD, buffer: &Vec<u8>, endian: &Endian, width: usize, height: usize) -> Self {
+        match ifd.get_values(Tag::TileWidth_322, buffer, endian) {
+            Some(tile_width) => {
+                let chunk_width = tile_width.to_value().to_usize();
+                let chunk_length = ifd.get_values(Tag::TileLength_323, buffer, endian).expect("A tiled image needs a TileLength!").to_value().to_usize();
+                Self {
+                    chunk_width,
+                    chunk_length,
+                    chunks_across: width.div_ceil(chunk_width),
+                    chunks_down: height.div_ceil(chunk_length),
+                    tiled: true,
+                }
+            },
+            None => {
+                let rows_per_strip = match ifd.get_values(Tag::RowsPerStrip_278, buffer, endian) {
+                    Some(rows) => rows.to_value().to_usize().min(height),
+                    None => height,
+                };
+                Self {
+                    chunk_width: width,
+                    chunk_length: rows_per_strip,
+                    chunks_across: 1,
+                    chunks_down: height.div_ceil(rows_per_strip),
+                    tiled: false,
+                }
+            }
+        }
+    }
+
+    fn chunks_per_plane(&self) -> usize {
+        self.chunks_across * self.chunks_down
+    }
+}
+
+// Every sample format and size as 16 bit samples, see PixelBuffer::into_raster
+pub(crate) fn read_raster(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian, codecs: &CodecRegistry) -> Raster {
+    read_pixel_buffer(buffer, ifd, endian, codecs).into_raster()
+}
+
+fn read_bits_per_sample(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian) -> usize {
+    match ifd.get_values(Tag::BitsPerSample_258, buffer, endian) {
+        Some(bits) => bits.to_vec()[0].to_usize(),
+        None => 1,
+    }
+}
+
+// The stored bytes of the first strip or tile, e.g. to see what kind of JPEG data it holds
+pub(crate) fn first_chunk<'a>(buffer: &'a Vec<u8>, ifd: &IFD, endian: &Endian) -> Option<&'a [u8]> {
+    let (offsets_tag, byte_counts_tag) = match ifd.get_values(Tag::TileOffsets_324, buffer, endian) {
+        Some(_) => (Tag::TileOffsets_324, Tag::TileByteCounts_325),
+        None => (Tag::StripOffsets_273, Tag::StripByteCounts_279),
+    };
+    let offset = ifd.get_values(offsets_tag, buffer, endian)?.to_vec()[0].to_usize();
+    let byte_count = ifd.get_values(byte_counts_tag, buffer, endian)?.to_vec()[0].to_usize();
+    buffer.get(offset..offset + byte_count)
+}
+
+pub(crate) fn read_pixel_buffer(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian, codecs: &CodecRegistry) -> PixelBuffer {
+    let wide = |data: &[u8], chunk: &ChunkInfo| sample_format::unpack_wide_samples(data, chunk);
+    match (SampleFormat::read(buffer, ifd, endian), read_bits_per_sample(buffer, ifd, endian)) {
+        (SampleFormat::Float, 16) => PixelBuffer::F16(read_samples(buffer, ifd, endian, codecs, codec::unpack_samples)),
+        (SampleFormat::Float, 24) => PixelBuffer::F32(read_samples(buffer, ifd, endian, codecs, |data, chunk| {
+            wide(data, chunk).iter().map(|&s| sample_format::fp24_to_f32(s)).collect()
+        })),
+        (SampleFormat::Float, 32) => PixelBuffer::F32(read_samples(buffer, ifd, endian, codecs, |data, chunk| {
+            wide(data, chunk).iter().map(|&s| f32::from_bits(s)).collect()
+        })),
+        (SampleFormat::Float, bits) => panic!("{} bit floats aren't supported!", bits),
+        (SampleFormat::Signed, bits) => PixelBuffer::I32(read_samples(buffer, ifd, endian, codecs, |data, chunk| {
+            let unpacked = if bits > 16 { wide(data, chunk) } else { codec::unpack_samples(data, chunk).iter().map(|&s| s as u32).collect() };
+            // moves the sign bit to the top so the shift back sign extends
+            unpacked.iter().map(|&s| ((s << (32 - bits)) as i32) >> (32 - bits)).collect()
+        })),
+        (SampleFormat::Unsigned, 8) => PixelBuffer::U8(read_samples(buffer, ifd, endian, codecs, |data, chunk| {
+            data[..chunk.width * chunk.samples_per_pixel * chunk.length].to_vec()
+        })),
+        (SampleFormat::Unsigned, bits) if bits <= 16 => PixelBuffer::U16(read_samples(buffer, ifd, endian, codecs, codec::unpack_samples)),
+        (SampleFormat::Unsigned, _) => PixelBuffer::U32(read_samples(buffer, ifd, endian, codecs, wide)),
+    }
+}
+
+// Puts the decoded strips or tiles together, `unpack` turns a chunk's uncompressed bytes into samples
+fn read_samples<T: Copy + Default>(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian, codecs: &CodecRegistry, unpack: impl Fn(&[u8], &ChunkInfo) -> Vec<T>) -> Raster<T> {
+    let width = ifd.get_values(Tag::ImageWidth_256, buffer, endian).expect("The IFD has no ImageWidth!").to_value().to_usize();
+    let height = ifd.get_values(Tag::ImageLength_257, buffer, endian).expect("The IFD has no ImageLength!").to_value().to_usize();
+    let samples_per_pixel = match ifd.get_values(Tag::SamplesPerPixel_277, buffer, endian) {
+        Some(spp) => spp.to_value().to_usize(),
+        None => 1,
+    };
+    let bits_per_sample = read_bits_per_sample(buffer, ifd, endian);
+    let compression = match ifd.get_values(Tag::Compression_259, buffer, endian) {
+        Some(compression) => compression.to_value().to_u16(),
+        None => 1,
+    };
+    let predictor = match ifd.get_values(Tag::Predictor_317, buffer, endian) {
+        Some(predictor) => predictor.to_value().to_u16(),
+        None => 1,
+    };
+    let planar = match ifd.get_values(Tag::PlanarConfiguration_284, buffer, endian) {
+        Some(planar) => planar.to_value().to_u16() == 2,
+        None => false,
+    };
+
+    let layout = ChunkLayout::new(ifd, buffer, endian, width, height);
+    let (offsets_tag, byte_counts_tag) = if layout.tiled {
+        (Tag::TileOffsets_324, Tag::TileByteCounts_325)
+    } else {
+        (Tag::StripOffsets_273, Tag::StripByteCounts_279)
+    };
+    let offsets = ifd.get_values(offsets_tag, buffer, endian).expect("The IFD has no image data offsets!").to_vec().iter().map(|f| f.to_usize()).collect::<Vec<usize>>();
+    let byte_counts = ifd.get_values(byte_counts_tag, buffer, endian).expect("The IFD has no image data byte counts!").to_vec().iter().map(|f| f.to_usize()).collect::<Vec<usize>>();
+
+    // TIFF6.0 P38, with separate planes each plane has its own full set of strips or tiles
+    let (chunk_samples_per_pixel, planes) = if planar { (1, samples_per_pixel) } else { (samples_per_pixel, 1) };
+    assert_eq!(offsets.len(), layout.chunks_per_plane() * planes, "The number of strips or tiles doesn't match the image size!");
+
+    // DNG spec 1.6 SubTileBlockSize P50, the pixels in each tile can be grouped in blocks
+    let sub_tile_block = ifd.get_values(Tag::SubTileBlockSize_50974, buffer, endian)
+        .map(|size| size.to_vec().iter().map(|f| f.to_usize()).collect::<Vec<usize>>())
+        .filter(|size| size[0] * size[1] > 1);
+
+    let mut data = vec![T::default(); width * height * samples_per_pixel];
+    for (i, (&offset, &byte_count)) in offsets.iter().zip(byte_counts.iter()).enumerate() {
+        let plane = i / layout.chunks_per_plane();
+        let index = i % layout.chunks_per_plane();
+        let x0 = (index % layout.chunks_across) * layout.chunk_width;
+        let y0 = (index / layout.chunks_across) * layout.chunk_length;
+        // the last strip only holds the rows that are left, tiles are always padded to full size
+        let chunk_length = if layout.tiled { layout.chunk_length } else { layout.chunk_length.min(height - y0) };
+
+        let chunk = ChunkInfo {
+            width: layout.chunk_width,
+            length: chunk_length,
+            samples_per_pixel: chunk_samples_per_pixel,
+            bits_per_sample,
+            endian: endian.clone(),
+        };
+        let mut decoded = match codecs.decode(compression, &buffer[offset..offset + byte_count], &chunk) {
+            Ok(decoded) => decoded,
+            Err(error) => panic!("{}!", error),
+        };
+        predictor::undo_predictor(predictor, &mut decoded, &chunk);
+        let mut samples = unpack(&decoded, &chunk);
+        if let Some(block) = &sub_tile_block {
+            samples = deblock(&samples, &chunk, block[0], block[1]);
+        }
+
+        for y in 0..chunk_length.min(height - y0) {
+            for x in 0..layout.chunk_width.min(width - x0) {
+                for s in 0..chunk_samples_per_pixel {
+                    let source = (y * layout.chunk_width + x) * chunk_samples_per_pixel + s;
+                    let destination = ((y0 + y) * width + x0 + x) * samples_per_pixel + plane + s;
+                    data[destination] = samples[source];
+                }
+            }
+        }
+    }
+
+    // DNG spec 1.6 RowInterleaveFactor P51 and DNG spec 1.7 ColumnInterleaveFactor, these apply to
+    // the image as a whole once it's been put together from its strips or tiles
+    let row_factor = match ifd.get_values(Tag::RowInterleaveFactor_50975, buffer, endian) {
+        Some(factor) => factor.to_value().to_usize(),
+        None => 1,
+    };
+    let column_factor = match ifd.get_values(Tag::ColumnInterleaveFactor_52547, buffer, endian) {
+        Some(factor) => factor.to_value().to_usize(),
+        None => 1,
+    };
+    if row_factor > 1 || column_factor > 1 {
+        data = deinterleave(&data, width, height, samples_per_pixel, row_factor, column_factor);
+    }
+
+    Raster { width, height, samples_per_pixel, bits_per_sample, data }
+}
+
+// Puts a chunk that's stored as row scanned blocks of block_rows x block_columns pixels back into
+// simple row scan order
+fn deblock<T: Copy + Default>(samples: &[T], chunk: &ChunkInfo, block_rows: usize, block_columns: usize) -> Vec<T> {
+    assert!(chunk.width.is_multiple_of(block_columns) && chunk.length.is_multiple_of(block_rows), "The tile size has to be a multiple of the SubTileBlockSize!");
+    let spp = chunk.samples_per_pixel;
+    let blocks_across = chunk.width / block_columns;
+    let mut deblocked = vec![T::default(); samples.len()];
+    let mut source = 0;
+    for block in 0..blocks_across * (chunk.length / block_rows) {
+        let y0 = (block / blocks_across) * block_rows;
+        let x0 = (block % blocks_across) * block_columns;
+        for y in y0..y0 + block_rows {
+            let destination = (y * chunk.width + x0) * spp;
+            deblocked[destination..destination + block_columns * spp].copy_from_slice(&samples[source..source + block_columns * spp]);
+            source += block_columns * spp;
+        }
+    }
+    deblocked
+}
+
+// Maps a stored row (or column) back to where it belongs when the image is stored as `factor`
+// interleaved fields, e.g. rows 0, 2, 4, ... followed by rows 1, 3, 5, ... for a factor of 2
+fn interleaved_position(stored: usize, size: usize, factor: usize) -> usize {
+    let mut first_of_field = 0;
+    for field in 0..factor {
+        let field_size = (size + factor - 1 - field) / factor;
+        if stored < first_of_field + field_size {
+            return field + (stored - first_of_field) * factor;
+        }
+        first_of_field += field_size;
+    }
+    panic!("The stored position is outside of the image!")
+}
+
+fn deinterleave<T: Copy + Default>(data: &[T], width: usize, height: usize, spp: usize, row_factor: usize, column_factor: usize) -> Vec<T> {
+    let columns = (0..width).map(|x| interleaved_position(x, width, column_factor)).collect::<Vec<usize>>();
+    let mut deinterleaved = vec![T::default(); data.len()];
+    for stored_row in 0..height {
+        let row = interleaved_position(stored_row, height, row_factor);
+        for (stored_column, &column) in columns.iter().enumerate() {
+            let source = (stored_row * width + stored_column) * spp;
+            let destination = (row * width + column) * spp;
+            deinterleaved[destination..destination + spp].copy_from_slice(&data[source..source + spp]);
+        }
+    }
+    deinterleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_value, test_utils::{build_tiff, TestIfd, Value}};
+
+    // A 3x3 image with 2 samples per pixel, sample 0 is 10 * row + column and sample 1 is 100 more
+    fn planar_ifd() -> TestIfd {
+        TestIfd::new()
+            .tag(Tag::ImageWidth_256, Value::Short(vec![3]))
+            .tag(Tag::ImageLength_257, Value::Short(vec![3]))
+            .tag(Tag::BitsPerSample_258, Value::Short(vec![8, 8]))
+            .tag(Tag::SamplesPerPixel_277, Value::Short(vec![2]))
+            .tag(Tag::PlanarConfiguration_284, Value::Short(vec![2]))
+    }
+
+    fn planar_expected() -> Vec<u16> {
+        (0..9).flat_map(|i| [(i / 3 * 10 + i % 3) as u16, (100 + i / 3 * 10 + i % 3) as u16]).collect()
+    }
+
+    fn read(buffer: &Vec<u8>, endian: Endian) -> Raster {
+        let offset = get_value::long(buffer, 4, &endian) as usize;
+        read_raster(buffer, &IFD::parse_ifd(buffer, offset, &endian), &endian, &CodecRegistry::new())
+    }
+
+    #[test]
+    fn planar_strips() {
+        // two strips per plane, all of plane 0's strips come first
+        let ifd = planar_ifd()
+            .tag(Tag::RowsPerStrip_278, Value::Short(vec![2]))
+            .strips(vec![vec![0, 1, 2, 10, 11, 12], vec![20, 21, 22], vec![100, 101, 102, 110, 111, 112], vec![120, 121, 122]]);
+        let buffer = build_tiff(Endian::Little, ifd, Vec::new());
+
+        let raster = read(&buffer, Endian::Little);
+
+        assert_eq!((raster.width, raster.height, raster.samples_per_pixel), (3, 3, 2));
+        assert_eq!(raster.data, planar_expected());
+    }
+
+    #[test]
+    fn planar_tiles() {
+        // 2x2 tiles, padded at the right and bottom edges
+        let plane = |base: u8| vec![
+            vec![base, base + 1, base + 10, base + 11],
+            vec![base + 2, 0, base + 12, 0],
+            vec![base + 20, base + 21, 0, 0],
+            vec![base + 22, 0, 0, 0],
+        ];
+        let ifd = planar_ifd()
+            .tag(Tag::TileWidth_322, Value::Short(vec![2]))
+            .tag(Tag::TileLength_323, Value::Short(vec![2]))
+            .tiles([plane(0), plane(100)].concat());
+        let buffer = build_tiff(Endian::Big, ifd, Vec::new());
+
+        assert_eq!(read(&buffer, Endian::Big).data, planar_expected());
+    }
+
+    #[test]
+    fn deflate_float_tile() {
+        // a 3x2 image in one 4x2 tile of 32 bit floats with the floating point predictor, compressed
+        // with Python's zlib. 4.5 and 2.0 are over the default white level of 1.0.
+        let tile = vec![
+            0x78, 0xDA, 0xB3, 0x63, 0x64, 0x3C, 0xD0, 0xC0, 0x20, 0x50, 0xC0, 0x00, 0x05,
+            0xFB, 0x1B, 0x80, 0xFC, 0x06, 0x06, 0x38, 0x00, 0x00, 0x61, 0x2B, 0x05, 0x01,
+        ];
+        let ifd = TestIfd::new()
+            .tag(Tag::ImageWidth_256, Value::Short(vec![3]))
+            .tag(Tag::ImageLength_257, Value::Short(vec![2]))
+            .tag(Tag::BitsPerSample_258, Value::Short(vec![32]))
+            .tag(Tag::Compression_259, Value::Short(vec![8]))
+            .tag(Tag::Predictor_317, Value::Short(vec![34894]))
+            .tag(Tag::TileWidth_322, Value::Short(vec![4]))
+            .tag(Tag::TileLength_323, Value::Short(vec![2]))
+            .tag(Tag::SampleFormat_339, Value::Short(vec![3]))
+            .tiles(vec![tile]);
+        let buffer = build_tiff(Endian::Big, ifd, Vec::new());
+        let ifd = IFD::parse_ifd(&buffer, get_value::long(&buffer, 4, &Endian::Big) as usize, &Endian::Big);
+
+        let pixels = read_pixel_buffer(&buffer, &ifd, &Endian::Big, &CodecRegistry::new());
+
+        assert!(matches!(pixels, PixelBuffer::F32(_)));
+        assert_eq!(pixels.to_f32(), vec![0.25, 1.0, 4.5, -1.0, 0.5, 2.0]);
+    }
+
+    #[test]
+    fn interleaved_rows() {
+        // 5 rows in 2 fields are stored as 0, 2, 4, 1, 3
+        let positions = (0..5).map(|r| interleaved_position(r, 5, 2)).collect::<Vec<usize>>();
+
+        assert_eq!(positions, vec![0, 2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn sub_tile_blocks() {
+        let chunk = ChunkInfo { width: 4, length: 2, samples_per_pixel: 1, bits_per_sample: 16, endian: Endian::Little };
+        // two 2x2 blocks, each stored in row scan order
+        let samples = vec![0, 1, 4, 5, 2, 3, 6, 7];
+
+        assert_eq!(deblock(&samples, &chunk, 2, 2), vec![0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+}
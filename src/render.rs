@@ -0,0 +1,127 @@
+use std::fmt;
+
+use image::Image;
+
+use crate::{
+    black_level::BlackLevelMode,
+    color::ColorSpace,
+    demosaic::DemosaicAlgorithm,
+    linearize::LinearImage,
+    matrix::Matrix,
+    opcode::OpcodeError,
+    raster::Raster,
+    white_balance::WhiteBalance,
+};
+
+/// How DNG::render turns the raw image into an RGB image.
+#[derive(Clone, Debug)]
+pub struct RenderOptions {
+    pub black_level: BlackLevelMode,
+    // None picks the best algorithm for the CFA pattern
+    pub demosaic: Option<DemosaicAlgorithm>,
+    pub white_balance: WhiteBalance,
+    pub color_space: ColorSpace,
+    // in EV, on top of BaselineExposure and BaselineExposureOffset
+    pub exposure: f64,
+    // the HueSatMap, LookTable and ProfileToneCurve
+    pub use_profile: bool,
+    // rotates and flips the image per Orientation, otherwise it's the way the raw image is stored
+    pub display_oriented: bool,
+    // what shows through the transparent parts of DNGs with a transparency mask, None ignores the mask
+    pub matte: Option<[u8; 3]>,
+}
+
+/// Why DNG::render couldn't render the raw image.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderError {
+    Opcode(OpcodeError),
+    // a color raw image needs ColorMatrix1 to get from camera space to XYZ
+    NoColorMatrix,
+    // the raw image's color planes and the ColorMatrix's columns
+    PlaneMismatch { raw: usize, color_matrix: usize },
+}
+
+impl From<OpcodeError> for RenderError {
+    fn from(error: OpcodeError) -> Self {
+        RenderError::Opcode(error)
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::Opcode(error) => write!(f, "{}", error),
+            RenderError::NoColorMatrix => write!(f, "The DNG has no ColorMatrix1"),
+            RenderError::PlaneMismatch { raw, color_matrix } => write!(f, "The raw image has {} planes but the ColorMatrix has {}", raw, color_matrix),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            black_level: BlackLevelMode::Tagged,
+            demosaic: None,
+            white_balance: WhiteBalance::AsShot,
+            color_space: ColorSpace::Srgb,
+            exposure: 0.0,
+            use_profile: true,
+            display_oriented: true,
+            matte: None,
+        }
+    }
+}
+
+pub(crate) fn apply_exposure(image: &LinearImage, ev: f64) -> LinearImage {
+    let gain = 2f64.powf(ev) as f32;
+    LinearImage { data: image.data.iter().map(|v| v * gain).collect(), ..image.clone() }
+}
+
+/// Encodes linear RGB with the space's transfer function into 8 bit samples. Gray ends up in all
+/// three channels.
+pub(crate) fn encode(image: &LinearImage, space: ColorSpace) -> Image {
+    let encode = |v: f32| (space.encode(v) * 255.0).round() as u8;
+    let data = match image.planes {
+        1 => image.data.iter().flat_map(|&v| [encode(v); 3]).collect(),
+        _ => image.data.iter().map(|&v| encode(v)).collect(),
+    };
+    Image { data, width: image.width as u32, height: image.height as u32 }
+}
+
+/// The luminance of linear RGB in one of the output spaces, gray is already luminance.
+pub(crate) fn luminance(image: &LinearImage, space: ColorSpace) -> LinearImage {
+    if image.planes == 1 {
+        return image.clone();
+    }
+    let rgb_to_xyz = space.rgb_to_xyz();
+    image.transform(&Matrix::new(1, 3, (0..3).map(|col| rgb_to_xyz.get(1, col)).collect()))
+}
+
+/// Encodes linear gray with the space's transfer function into 16 bit samples.
+pub(crate) fn encode_gray16(image: &LinearImage, space: ColorSpace) -> Raster {
+    Raster {
+        width: image.width,
+        height: image.height,
+        samples_per_pixel: 1,
+        bits_per_sample: 16,
+        data: luminance(image, space).data.iter().map(|&v| (space.encode(v) * 65535.0).round() as u16).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gray_output() {
+        let gray = LinearImage { width: 2, height: 1, planes: 1, data: vec![0.0, 1.0] };
+
+        assert_eq!(encode(&gray, ColorSpace::Srgb).data, vec![0, 0, 0, 255, 255, 255]);
+        assert_eq!(encode_gray16(&gray, ColorSpace::Srgb).data, vec![0, 65535]);
+        // white RGB is white gray
+        let white = LinearImage { width: 1, height: 1, planes: 3, data: vec![1.0; 3] };
+        assert!((luminance(&white, ColorSpace::Srgb).data[0] - 1.0).abs() < 1e-4);
+    }
+}
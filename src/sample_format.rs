@@ -0,0 +1,189 @@
+use crate::{codec::ChunkInfo, raster::Raster, tags::Tag, Endian, IFD};
+
+/// TIFF6.0 SampleFormat P80, how the bits of each sample are interpreted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    Unsigned,
+    Signed,
+    // IEEE floats, plus the 24 bit floats of DNG spec 1.6 P20
+    Float,
+}
+
+impl SampleFormat {
+    // undefined (4) is read as unsigned like the default
+    pub(crate) fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian) -> Self {
+        match ifd.get_values(Tag::SampleFormat_339, buffer, endian).map(|v| v.to_vec()[0].to_u16()) {
+            Some(2) => SampleFormat::Signed,
+            Some(3) => SampleFormat::Float,
+            _ => SampleFormat::Unsigned,
+        }
+    }
+
+    // How samples of this format and size become the 16 bit samples of a Raster: what they're scaled
+    // by, the Raster's bits per sample, and how many bits the default white level has. Signed samples
+    // lose their sign bit. Floats default to being white at 1.0, which maps to 65535, and a tagged
+    // WhiteLevel is scaled the same way.
+    pub(crate) fn raster_conversion(&self, bits_per_sample: usize) -> (f64, usize, usize) {
+        match self {
+            SampleFormat::Unsigned => (0.5f64.powi(bits_per_sample.saturating_sub(16) as i32), bits_per_sample.min(16), bits_per_sample),
+            SampleFormat::Signed => {
+                let positive = bits_per_sample - 1;
+                (0.5f64.powi(positive.saturating_sub(16) as i32), positive.min(16), positive)
+            },
+            SampleFormat::Float => (65535.0, 16, 1),
+        }
+    }
+}
+
+/// Samples in the type they're stored as.
+#[derive(Clone)]
+pub enum PixelBuffer {
+    U8(Raster<u8>),
+    // unsigned samples of up to 16 bits, e.g. 12 or 14 bit raw data
+    U16(Raster<u16>),
+    // unsigned 24 and 32 bit samples
+    U32(Raster<u32>),
+    // signed samples of any size, sign extended
+    I32(Raster<i32>),
+    // half floats as their bits, see f16_to_f32
+    F16(Raster<u16>),
+    // 24 and 32 bit floats
+    F32(Raster<f32>),
+}
+
+impl PixelBuffer {
+    pub fn width(&self) -> usize {
+        self.layout().0
+    }
+
+    pub fn height(&self) -> usize {
+        self.layout().1
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.layout().2
+    }
+
+    pub fn bits_per_sample(&self) -> usize {
+        self.layout().3
+    }
+
+    // width, height, samples per pixel and bits per sample
+    fn layout(&self) -> (usize, usize, usize, usize) {
+        use PixelBuffer::*;
+        match self {
+            U8(r) => (r.width, r.height, r.samples_per_pixel, r.bits_per_sample),
+            U16(r) | F16(r) => (r.width, r.height, r.samples_per_pixel, r.bits_per_sample),
+            U32(r) => (r.width, r.height, r.samples_per_pixel, r.bits_per_sample),
+            I32(r) => (r.width, r.height, r.samples_per_pixel, r.bits_per_sample),
+            F32(r) => (r.width, r.height, r.samples_per_pixel, r.bits_per_sample),
+        }
+    }
+
+    pub fn sample_format(&self) -> SampleFormat {
+        use PixelBuffer::*;
+        match self {
+            U8(_) | U16(_) | U32(_) => SampleFormat::Unsigned,
+            I32(_) => SampleFormat::Signed,
+            F16(_) | F32(_) => SampleFormat::Float,
+        }
+    }
+
+    /// The stored values as floats, without any scaling.
+    pub fn to_f32(&self) -> Vec<f32> {
+        use PixelBuffer::*;
+        match self {
+            U8(r) => r.data.iter().map(|&s| s as f32).collect(),
+            U16(r) => r.data.iter().map(|&s| s as f32).collect(),
+            U32(r) => r.data.iter().map(|&s| s as f32).collect(),
+            I32(r) => r.data.iter().map(|&s| s as f32).collect(),
+            F16(r) => r.data.iter().map(|&s| f16_to_f32(s)).collect(),
+            F32(r) => r.data.clone(),
+        }
+    }
+
+    /// The stored values as floats in a Raster, see to_f32.
+    pub fn to_f32_raster(&self) -> Raster<f32> {
+        let (width, height, samples_per_pixel, bits_per_sample) = self.layout();
+        Raster { width, height, samples_per_pixel, bits_per_sample, data: self.to_f32() }
+    }
+
+    /// The 16 bit samples the rest of the crate works with, see SampleFormat::raster_conversion.
+    /// Floats over 1.0 are clipped at 65535, so HDR data loses its headroom. linearize_float works
+    /// on the floats themselves and keeps it.
+    pub fn into_raster(self) -> Raster {
+        let (width, height, samples_per_pixel, stored_bits) = self.layout();
+        let (scale, bits_per_sample, _) = self.sample_format().raster_conversion(stored_bits);
+        let data = match self {
+            PixelBuffer::U8(r) => r.data.iter().map(|&s| s as u16).collect(),
+            PixelBuffer::U16(r) => r.data,
+            // negative samples end up as 0 and anything over the top as 65535
+            buffer => buffer.to_f32().iter().map(|&s| (s as f64 * scale).round() as u16).collect(),
+        };
+        Raster { width, height, samples_per_pixel, bits_per_sample, data }
+    }
+}
+
+// Floats with fewer bits than an f32, with IEEE style bias, subnormals, infinities and NaNs
+fn small_float_to_f32(value: u32, exponent_bits: u32, mantissa_bits: u32) -> f32 {
+    let sign = if (value >> (exponent_bits + mantissa_bits)) & 1 == 1 { -1.0 } else { 1.0 };
+    let max_exponent = (1 << exponent_bits) - 1;
+    let bias = (max_exponent >> 1) as i32;
+    let exponent = (value >> mantissa_bits) & max_exponent;
+    let mantissa = value & ((1 << mantissa_bits) - 1);
+    let fraction = mantissa as f32 / (1u32 << mantissa_bits) as f32;
+    sign * match exponent {
+        0 => fraction * 2f32.powi(1 - bias),
+        e if e == max_exponent => if mantissa == 0 { f32::INFINITY } else { f32::NAN },
+        e => (1.0 + fraction) * 2f32.powi(e as i32 - bias),
+    }
+}
+
+pub fn f16_to_f32(bits: u16) -> f32 {
+    small_float_to_f32(bits as u32, 5, 10)
+}
+
+// DNG spec 1.6 P20, a sign bit, 7 exponent bits with a bias of 63 and 16 mantissa bits
+pub fn fp24_to_f32(bits: u32) -> f32 {
+    small_float_to_f32(bits, 7, 16)
+}
+
+// 24 and 32 bit samples are whole bytes in the file's byte order, smaller ones are handled by
+// codec::unpack_samples
+pub(crate) fn unpack_wide_samples(data: &[u8], chunk: &ChunkInfo) -> Vec<u32> {
+    let count = chunk.width * chunk.samples_per_pixel * chunk.length;
+    let bytes = chunk.bits_per_sample / 8;
+    assert!(chunk.bits_per_sample == 24 || chunk.bits_per_sample == 32, "{} bit samples aren't supported!", chunk.bits_per_sample);
+    data[..count * bytes].chunks(bytes)
+        .map(|b| match chunk.endian {
+            Endian::Big => b.iter().fold(0, |value, &byte| (value << 8) | byte as u32),
+            Endian::Little => b.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u32),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_floats() {
+        assert_eq!(f16_to_f32(0x3C00), 1.0);
+        assert_eq!(f16_to_f32(0xC000), -2.0);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7C00), f32::INFINITY);
+        assert_eq!(fp24_to_f32(0x3F0000), 1.0);
+        assert_eq!(fp24_to_f32(0x3E8000), 0.75);
+    }
+
+    #[test]
+    fn wide_samples_to_raster() {
+        let chunk = ChunkInfo { width: 2, length: 1, samples_per_pixel: 1, bits_per_sample: 24, endian: Endian::Big };
+        let samples = unpack_wide_samples(&[0xFF, 0xFF, 0xFF, 0x00, 0x01, 0x00], &chunk);
+
+        assert_eq!(samples, vec![0xFFFFFF, 0x100]);
+        let buffer = PixelBuffer::U32(Raster { width: 2, height: 1, samples_per_pixel: 1, bits_per_sample: 24, data: samples });
+        let raster = buffer.into_raster();
+        assert_eq!((raster.bits_per_sample, raster.data), (16, vec![65535, 1]));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{geometry::{Geometry, Rect}, linearize::LinearImage, preview::read_string, raster::Raster, tags::Tag, Endian, IFD};
+
+/// DNG spec 1.6 NewSubFileType P18, a semantic mask is a mask (bit 2) with bit 16 set.
+pub(crate) const SEMANTIC_MASK_TYPE: u32 = 0x10004;
+
+/// A semantic mask like Sky, Skin, Subject or Hair, as alpha in [0, 1] where 1 is fully in the mask.
+#[derive(Clone)]
+pub struct SemanticMask {
+    pub name: String,
+    // tells apart several masks with the same name, e.g. one per person
+    pub instance_id: Option<String>,
+    // the part of the main image's default crop the mask covers, None when it covers all of it
+    pub sub_area: Option<Rect>,
+    pub alpha: LinearImage,
+}
+
+impl SemanticMask {
+    pub(crate) fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian, raster: &Raster) -> Self {
+        let max = ((1u64 << raster.bits_per_sample) - 1) as f32;
+        let sub_area = ifd.get_values(Tag::MaskSubArea_52536, buffer, endian).map(|v| {
+            let v = v.to_vec().iter().map(|f| f.to_usize()).collect::<Vec<usize>>();
+            Rect { top: v[0], left: v[1], bottom: v[2], right: v[3] }
+        });
+        Self {
+            name: read_string(buffer, ifd, endian, Tag::SemanticName_52526).unwrap_or_default(),
+            instance_id: read_string(buffer, ifd, endian, Tag::SemanticInstanceID_52528),
+            sub_area,
+            alpha: LinearImage {
+                width: raster.width,
+                height: raster.height,
+                planes: 1,
+                data: raster.data.iter().step_by(raster.samples_per_pixel).map(|&v| v as f32 / max).collect(),
+            },
+        }
+    }
+
+    /// Where the mask goes in the main image's default crop, which is also where it goes in a render
+    /// that isn't display oriented.
+    pub fn placement(&self, geometry: &Geometry) -> Rect {
+        let crop = geometry.default_crop();
+        self.sub_area.unwrap_or(Rect { top: 0, left: 0, bottom: crop.height(), right: crop.width() })
+    }
+
+    /// The mask scaled into its placement in an image the size of the default crop, 0 outside of it,
+    /// so it lines up pixel for pixel with a render.
+    pub fn align(&self, geometry: &Geometry) -> LinearImage {
+        let crop = geometry.default_crop();
+        let placement = self.placement(geometry);
+        let scaled = self.alpha.resize_nearest(placement.width(), placement.height());
+        let mut aligned = LinearImage::new(crop.width(), crop.height(), 1);
+        for row in placement.top..placement.bottom.min(crop.height()) {
+            for col in placement.left..placement.right.min(crop.width()) {
+                aligned.set(row, col, 0, scaled.get(row - placement.top, col - placement.left, 0));
+            }
+        }
+        aligned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_area_placement() {
+        let geometry = Geometry::new(4, 4, Rect { top: 0, left: 0, bottom: 4, right: 4 });
+        // a 1x1 mask stretched over the 2x2 bottom right corner
+        let mask = SemanticMask {
+            name: "Sky".to_string(),
+            instance_id: None,
+            sub_area: Some(Rect { top: 2, left: 2, bottom: 4, right: 4 }),
+            alpha: LinearImage { width: 1, height: 1, planes: 1, data: vec![1.0] },
+        };
+
+        let aligned = mask.align(&geometry);
+
+        assert_eq!(aligned.data, vec![
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 1.0,
+            0.0, 0.0, 1.0, 1.0,
+        ]);
+        assert_eq!(SemanticMask { sub_area: None, ..mask }.placement(&geometry), Rect { top: 0, left: 0, bottom: 4, right: 4 });
+    }
+}
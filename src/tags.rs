@@ -346,4 +346,14 @@ pub(crate) enum Tag {
     NewRawImageDigest_51111 = 51111,  //  0xC7A7  This tag is a modified MD5 digest of the raw image data. It has been updated from the algorithm used to compute the RawImageDigest tag be more multi-processor friendly, and to support lossy compression algorithms. The details of the algorithm used to compute this tag are documented in the Adobe DNG SDK source code.	DNG spec (1.4, 2012), p. 76	 
     RawToPreviewGain_51112 = 51112,  //  0xC7A8  The gain (what number the sample values are multiplied by) between the main raw IFD and the preview IFD containing this tag.	DNG spec (1.4, 2012), p. 76	 
     DefaultUserCrop_51125 = 51125,  //  0xC7B5  Specifies a default user crop rectangle in relative coordinates. The values must satisfy: 0.0 <= top < bottom <= 1.0; 0.0 <= left < right <= 1.0. The default values of (top = 0, left = 0, bottom = 1, right = 1) correspond exactly to the default crop rectangle (as specified by the DefaultCropOrigin and DefaultCropSize tags).	DNG spec (1.4, 2012), p. 70	 
-}
\ No newline at end of file
+    DepthFormat_51177 = 51177,  //  0xC7E9  Specifies the encoding of any depth data in the file. Can be unknown (0), linear (1) or inverse (2). Used in IFD 0 of DNG files.	DNG spec (1.5, 2019)	 
+    DepthNear_51178 = 51178,  //  0xC7EA  Specifies distance from the camera represented by the zero value in the depth map. 0/0 means unknown. Used in IFD 0 of DNG files.	DNG spec (1.5, 2019)	 
+    DepthFar_51179 = 51179,  //  0xC7EB  Specifies distance from the camera represented by the maximum value in the depth map. 0/0 means unknown. 1/0 can be used to specify infinity. Used in IFD 0 of DNG files.	DNG spec (1.5, 2019)	 
+    DepthUnits_51180 = 51180,  //  0xC7EC  Specifies the measurement units for the DepthNear and DepthFar tags. Can be unknown (0) or meters (1). Used in IFD 0 of DNG files.	DNG spec (1.5, 2019)	 
+    DepthMeasureType_51181 = 51181,  //  0xC7ED  Specifies the measurement geometry for the depth map. Can be unknown (0), optical axis (1) or optical ray (2). Used in IFD 0 of DNG files.	DNG spec (1.5, 2019)	 
+    EnhanceParams_51182 = 51182,  //  0xC7EE  The parameters used to make an enhanced raw image from the original one. Used in enhanced raw image IFDs of DNG files.	DNG spec (1.6, 2020)	 
+    SemanticName_52526 = 52526,  //  0xCD2E  A string that identifies the semantic mask, e.g. Sky, Skin, Subject or Hair. Used in semantic mask IFDs of DNG files.	DNG spec (1.6, 2020)	 
+    SemanticInstanceID_52528 = 52528,  //  0xCD30  A string that identifies a specific instance in a semantic mask, e.g. a person in a Subject mask. Used in semantic mask IFDs of DNG files.	DNG spec (1.6, 2020)	 
+    MaskSubArea_52536 = 52536,  //  0xCD38  The top, left, bottom and right of the area of the main image that the mask covers, when it doesn't cover all of it. Used in mask IFDs of DNG files.	DNG spec (1.6, 2020)	 
+    ColumnInterleaveFactor_52547 = 52547,  //  0xCD43  Specifies that columns of the image are stored in interleaved order. The value of the tag specifies the number of interleaved fields. Used in Raw IFD of DNG files.	DNG spec (1.7, 2023), p. 97	 
+}
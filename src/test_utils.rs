@@ -0,0 +1,215 @@
+// Builds small TIFF/DNG files in memory, so tests can exercise the IFD parsing and everything that
+// reads from it without sample files
+
+use crate::{tags::Tag, Endian};
+
+pub(crate) enum Value {
+    Byte(Vec<u8>),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<(u32, u32)>),
+    Undefined(Vec<u8>),
+    SRational(Vec<(i32, i32)>),
+    Float(Vec<f32>),
+}
+
+impl Value {
+    // TIFF6.0 P15/16, the type and count of the entry and its values in the file's byte order
+    fn encode(&self, endian: &Endian) -> (u16, usize, Vec<u8>) {
+        match self {
+            Value::Byte(v) => (1, v.len(), v.clone()),
+            Value::Short(v) => (3, v.len(), v.iter().flat_map(|&s| u16_bytes(s, endian)).collect()),
+            Value::Long(v) => (4, v.len(), v.iter().flat_map(|&l| u32_bytes(l, endian)).collect()),
+            Value::Rational(v) => (5, v.len(), v.iter().flat_map(|&(n, d)| [u32_bytes(n, endian), u32_bytes(d, endian)].concat()).collect()),
+            Value::Undefined(v) => (7, v.len(), v.clone()),
+            Value::SRational(v) => (10, v.len(), v.iter().flat_map(|&(n, d)| [u32_bytes(n as u32, endian), u32_bytes(d as u32, endian)].concat()).collect()),
+            Value::Float(v) => (11, v.len(), v.iter().flat_map(|&f| u32_bytes(f.to_bits(), endian)).collect()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct TestIfd {
+    entries: Vec<(u16, Value)>,
+    // the offsets and byte counts tags, and the data of each strip or tile
+    chunks: Option<(Tag, Tag, Vec<Vec<u8>>)>,
+}
+
+impl TestIfd {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn tag(mut self, tag: Tag, value: Value) -> Self {
+        self.entries.push((tag as u16, value));
+        self
+    }
+
+    pub(crate) fn strips(mut self, strips: Vec<Vec<u8>>) -> Self {
+        self.chunks = Some((Tag::StripOffsets_273, Tag::StripByteCounts_279, strips));
+        self
+    }
+
+    pub(crate) fn tiles(mut self, tiles: Vec<Vec<u8>>) -> Self {
+        self.chunks = Some((Tag::TileOffsets_324, Tag::TileByteCounts_325, tiles));
+        self
+    }
+}
+
+fn u32_bytes(value: u32, endian: &Endian) -> [u8; 4] {
+    match endian {
+        Endian::Big => value.to_be_bytes(),
+        Endian::Little => value.to_le_bytes(),
+    }
+}
+
+fn u16_bytes(value: u16, endian: &Endian) -> [u8; 2] {
+    match endian {
+        Endian::Big => value.to_be_bytes(),
+        Endian::Little => value.to_le_bytes(),
+    }
+}
+
+// Writes an IFD's image data and out of line values at the end of the buffer, then the IFD itself
+fn write_ifd(buffer: &mut Vec<u8>, ifd: TestIfd, sub_ifds: &[u32], endian: &Endian) -> u32 {
+    let mut entries = ifd.entries;
+    if let Some((offsets_tag, byte_counts_tag, chunks)) = ifd.chunks {
+        let mut offsets = Vec::new();
+        for chunk in &chunks {
+            offsets.push(buffer.len() as u32);
+            buffer.extend(chunk);
+        }
+        entries.push((offsets_tag as u16, Value::Long(offsets)));
+        entries.push((byte_counts_tag as u16, Value::Long(chunks.iter().map(|c| c.len() as u32).collect())));
+    }
+    if !sub_ifds.is_empty() {
+        entries.push((Tag::SubIFDs_330 as u16, Value::Long(sub_ifds.to_vec())));
+    }
+    entries.sort_by_key(|(tag, _)| *tag);
+
+    let mut directory = u16_bytes(entries.len() as u16, endian).to_vec();
+    for (tag, value) in &entries {
+        let (data_type, count, mut bytes) = value.encode(endian);
+        directory.extend(u16_bytes(*tag, endian));
+        directory.extend(u16_bytes(data_type, endian));
+        directory.extend(u32_bytes(count as u32, endian));
+        if bytes.len() <= 4 {
+            bytes.resize(4, 0);
+            directory.extend(bytes);
+        } else {
+            if buffer.len() % 2 == 1 {
+                buffer.push(0);
+            }
+            directory.extend(u32_bytes(buffer.len() as u32, endian));
+            buffer.extend(bytes);
+        }
+    }
+    directory.extend([0; 4]);
+
+    if buffer.len() % 2 == 1 {
+        buffer.push(0);
+    }
+    let offset = buffer.len() as u32;
+    buffer.extend(directory);
+    offset
+}
+
+/// A TIFF file with `main` as IFD 0 and `sub_ifds` as its SubIFDs.
+pub(crate) fn build_tiff(endian: Endian, main: TestIfd, sub_ifds: Vec<TestIfd>) -> Vec<u8> {
+    let mut buffer = match endian {
+        Endian::Big => vec![b'M', b'M', 0, 42, 0, 0, 0, 0],
+        Endian::Little => vec![b'I', b'I', 42, 0, 0, 0, 0, 0],
+    };
+    let sub_ifd_offsets = sub_ifds.into_iter().map(|ifd| write_ifd(&mut buffer, ifd, &[], &endian)).collect::<Vec<u32>>();
+    let main_offset = write_ifd(&mut buffer, main, &sub_ifd_offsets, &endian);
+    buffer[4..8].copy_from_slice(&u32_bytes(main_offset, &endian));
+    buffer
+}
+
+// T.81 F.1.2.1, the category of a DC difference and its extra bits
+fn dc_bits(diff: i32) -> (u32, u32) {
+    let category = 32 - diff.unsigned_abs().leading_zeros();
+    let extra = if diff < 0 { diff - 1 } else { diff } as u32 & ((1 << category) - 1);
+    (category, extra)
+}
+
+/// A baseline JPEG where every 8x8 block is flat, from the block values in scan order (MCU by MCU,
+/// then component by component). The DC table gives category n the 4 bit code n and the AC table
+/// only has end of block, so there's no need for a real encoder.
+pub(crate) fn dc_only_jpeg(width: usize, height: usize, sampling: &[(usize, usize)], blocks: &[u8], restart_interval: usize) -> Vec<u8> {
+    let mut data = vec![0xFF, 0xD8];
+    let mut segment = |marker: u8, payload: Vec<u8>| {
+        data.extend([0xFF, marker]);
+        data.extend(((payload.len() + 2) as u16).to_be_bytes());
+        data.extend(payload);
+    };
+    segment(0xDB, [vec![0], vec![1; 64]].concat());
+    let mut frame = vec![8];
+    frame.extend((height as u16).to_be_bytes());
+    frame.extend((width as u16).to_be_bytes());
+    frame.push(sampling.len() as u8);
+    for (i, (h, v)) in sampling.iter().enumerate() {
+        frame.extend([i as u8 + 1, ((h << 4) | v) as u8, 0]);
+    }
+    segment(0xC0, frame);
+    let mut dc_counts = vec![0x00, 0, 0, 0, 12];
+    dc_counts.extend([0; 12]);
+    dc_counts.extend(0..12);
+    segment(0xC4, dc_counts);
+    let mut ac_counts = vec![0x10, 1];
+    ac_counts.extend([0; 15]);
+    ac_counts.push(0);
+    segment(0xC4, ac_counts);
+    if restart_interval > 0 {
+        segment(0xDD, (restart_interval as u16).to_be_bytes().to_vec());
+    }
+    let mut scan = vec![sampling.len() as u8];
+    for i in 0..sampling.len() {
+        scan.extend([i as u8 + 1, 0x00]);
+    }
+    scan.extend([0, 63, 0]);
+    segment(0xDA, scan);
+
+    let blocks_per_mcu = if sampling.len() == 1 { 1 } else { sampling.iter().map(|(h, v)| h * v).sum() };
+    let mut bits = Vec::new();
+    let mut entropy = Vec::new();
+    let mut predictions = vec![0i32; sampling.len()];
+    let flush = |bits: &mut Vec<bool>, entropy: &mut Vec<u8>| {
+        // pad with ones and stuff a zero after every 0xFF, T.81 F.1.2.3 and B.1.1.5
+        while !bits.len().is_multiple_of(8) {
+            bits.push(true);
+        }
+        for byte in bits.chunks(8) {
+            let byte = byte.iter().fold(0u8, |b, &bit| (b << 1) | bit as u8);
+            entropy.push(byte);
+            if byte == 0xFF {
+                entropy.push(0);
+            }
+        }
+        bits.clear();
+    };
+    for (mcu, values) in blocks.chunks(blocks_per_mcu).enumerate() {
+        if restart_interval > 0 && mcu > 0 && mcu.is_multiple_of(restart_interval) {
+            flush(&mut bits, &mut entropy);
+            entropy.extend([0xFF, 0xD0 + ((mcu / restart_interval - 1) % 8) as u8]);
+            predictions.iter_mut().for_each(|p| *p = 0);
+        }
+        let mut values = values.iter();
+        for (c, (h, v)) in sampling.iter().enumerate() {
+            let count = if sampling.len() == 1 { 1 } else { h * v };
+            for _ in 0..count {
+                // a flat block of value x has a DC coefficient of 8 * (x - 128)
+                let dc = 8 * (*values.next().unwrap() as i32 - 128);
+                let (category, extra) = dc_bits(dc - predictions[c]);
+                predictions[c] = dc;
+                bits.extend((0..4).rev().map(|i| (category >> i) & 1 == 1));
+                bits.extend((0..category).rev().map(|i| (extra >> i) & 1 == 1));
+                bits.push(false);
+            }
+        }
+    }
+    flush(&mut bits, &mut entropy);
+    data.extend(entropy);
+    data.extend([0xFF, 0xD9]);
+    data
+}
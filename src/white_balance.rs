@@ -0,0 +1,133 @@
+use crate::{
+    color::{temperature_to_xy, xy_to_temperature, ColorCalibration, ColorSpec, D50},
+    tags::Tag,
+    Endian, IFD,
+};
+
+/// The white balance picked at capture time, DNG files should have one or the other.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AsShotWhite {
+    // AsShotNeutral, a neutral color in camera space
+    Neutral(Vec<f64>),
+    // AsShotWhiteXY
+    Xy((f64, f64)),
+}
+
+impl AsShotWhite {
+    pub(crate) fn read(buffer: &Vec<u8>, ifd: &IFD, endian: &Endian) -> Option<Self> {
+        let f64s = |tag: Tag| ifd.get_values(tag, buffer, endian).map(|v| v.to_vec().iter().map(|f| f.to_f64()).collect::<Vec<f64>>());
+
+        if let Some(neutral) = f64s(Tag::AsShotNeutral_50728) {
+            Some(AsShotWhite::Neutral(neutral))
+        } else {
+            f64s(Tag::AsShotWhiteXY_50729).map(|xy| AsShotWhite::Xy((xy[0], xy[1])))
+        }
+    }
+}
+
+/// Which white to render with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WhiteBalance {
+    AsShot,
+    // correlated color temperature in kelvin and tint, like the sliders of a raw converter
+    Temperature { temperature: f64, tint: f64 },
+    // a camera neutral, e.g. picked from a gray card in the linear camera image
+    Neutral(Vec<f64>),
+    Xy((f64, f64)),
+}
+
+impl ColorCalibration {
+    pub fn neutral_to_temperature(&self, neutral: &[f64]) -> (f64, f64) {
+        xy_to_temperature(self.neutral_to_xy(neutral))
+    }
+
+    pub fn temperature_to_neutral(&self, temperature: f64, tint: f64) -> Vec<f64> {
+        self.xy_to_neutral(temperature_to_xy(temperature, tint))
+    }
+
+    /// The white chromaticity of a white balance. Files without an as shot white balance fall back
+    /// to D50, which leaves the camera to XYZ transform without any adaptation.
+    pub fn white_balance_xy(&self, white_balance: &WhiteBalance, as_shot: Option<&AsShotWhite>) -> (f64, f64) {
+        match white_balance {
+            WhiteBalance::AsShot => match as_shot {
+                Some(AsShotWhite::Neutral(neutral)) => self.neutral_to_xy(neutral),
+                Some(AsShotWhite::Xy(xy)) => *xy,
+                None => D50,
+            },
+            WhiteBalance::Temperature { temperature, tint } => temperature_to_xy(*temperature, *tint),
+            WhiteBalance::Neutral(neutral) => self.neutral_to_xy(neutral),
+            WhiteBalance::Xy(xy) => *xy,
+        }
+    }
+}
+
+impl ColorSpec {
+    /// The per plane multipliers that make the white neutral in camera space. The plane with the
+    /// smallest white value gets 1.0 and the others less, so nothing that wasn't clipped ends up clipped.
+    pub fn white_balance_gains(&self) -> Vec<f64> {
+        let min = self.camera_white.iter().cloned().fold(f64::MAX, f64::min);
+        self.camera_white.iter().map(|w| min / w).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::Matrix;
+
+    // with identity color matrices the camera neutral is the XYZ of the white
+    fn identity_calibration() -> ColorCalibration {
+        let identity = Matrix::new(3, 3, vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        ColorCalibration {
+            planes: 3,
+            calibration_illuminant: [17, 21],
+            color_matrix: [Some(identity.clone()), Some(identity)],
+            camera_calibration: [None, None],
+            forward_matrix: [None, None],
+            reduction_matrix: [None, None],
+            analog_balance: vec![1.0; 3],
+        }
+    }
+
+    #[test]
+    fn illuminant_a_temperature() {
+        // CIE illuminant A is a 2856K black body, so it's on the Planckian locus
+        let (temperature, tint) = identity_calibration().neutral_to_temperature(&[1.0985, 1.0, 0.35585]);
+
+        assert!((temperature - 2856.0).abs() < 10.0, "{}", temperature);
+        assert!(tint.abs() < 1.0, "{}", tint);
+    }
+
+    #[test]
+    fn gains() {
+        let spec = ColorSpec {
+            white: D50,
+            camera_white: vec![0.5, 1.0, 0.8],
+            camera_to_xyz: Matrix::new(3, 3, vec![0.0; 9]),
+        };
+
+        assert_eq!(spec.white_balance_gains(), vec![1.0, 0.5, 0.625]);
+    }
+
+    #[test]
+    fn neutral_temperature_round_trip() {
+        let calibration = ColorCalibration {
+            planes: 3,
+            calibration_illuminant: [17, 21],
+            color_matrix: [
+                Some(Matrix::new(3, 3, vec![1.2, -0.3, -0.1, -0.4, 1.3, 0.1, -0.1, 0.3, 0.6])),
+                Some(Matrix::new(3, 3, vec![1.0, -0.3, -0.1, -0.5, 1.4, 0.1, -0.1, 0.2, 0.8])),
+            ],
+            camera_calibration: [None, None],
+            forward_matrix: [None, None],
+            reduction_matrix: [None, None],
+            analog_balance: vec![1.0; 3],
+        };
+
+        let neutral = calibration.temperature_to_neutral(4000.0, 10.0);
+        let (temperature, tint) = calibration.neutral_to_temperature(&neutral);
+
+        assert!((temperature - 4000.0).abs() < 1.0);
+        assert!((tint - 10.0).abs() < 0.1);
+    }
+}